@@ -0,0 +1,100 @@
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use rebl::tree::mutable_tree::{MutableTree, TreeIndex};
+
+const TIPS: [usize; 3] = [1_000, 10_000, 100_000];
+
+fn make_tips(tree: &mut MutableTree, n: usize) -> Vec<TreeIndex> {
+    (0..n)
+        .map(|i| {
+            let tip = tree.make_external_node(&format!("t{}", i), None).unwrap();
+            tree.set_length(tip, 1.0);
+            tip
+        })
+        .collect()
+}
+
+/// ((((t0,t1),t2),t3),...)
+fn ladder(n: usize) -> MutableTree {
+    let mut tree = MutableTree::new();
+    let tips = make_tips(&mut tree, n);
+    let mut node = tips[0];
+    for tip in tips.into_iter().skip(1) {
+        node = tree.make_internal_node(vec![node, tip]);
+        tree.set_length(node, 1.0);
+    }
+    tree.set_root(Some(node));
+    tree
+}
+
+/// Tips joined in pairs, then the pairs in pairs and so on up to the root
+fn balanced(n: usize) -> MutableTree {
+    let mut tree = MutableTree::new();
+    let mut level = make_tips(&mut tree, n);
+    while level.len() > 1 {
+        level = level
+            .chunks(2)
+            .map(|pair| {
+                if pair.len() == 1 {
+                    pair[0]
+                } else {
+                    let node = tree.make_internal_node(pair.to_vec());
+                    tree.set_length(node, 1.0);
+                    node
+                }
+            })
+            .collect();
+    }
+    tree.set_root(Some(level[0]));
+    tree
+}
+
+fn count_tips(tree: &MutableTree) -> usize {
+    (0..tree.arena_len())
+        .filter(|node| tree.is_external(*node) && !tree.is_internal(*node))
+        .count()
+}
+
+fn visit_children(tree: &MutableTree) -> usize {
+    let mut visited = 0;
+    for node in 0..tree.arena_len() {
+        for i in 0..tree.get_num_children(node) {
+            visited += tree.get_child(node, i).unwrap();
+        }
+    }
+    visited
+}
+
+fn node_kinds(c: &mut Criterion) {
+    let mut group = c.benchmark_group("is_external");
+    group.sample_size(10);
+    for n in TIPS {
+        let tree = ladder(n);
+        group.bench_with_input(BenchmarkId::new("ladder", n), &tree, |b, tree| {
+            b.iter(|| count_tips(black_box(tree)))
+        });
+        let tree = balanced(n);
+        group.bench_with_input(BenchmarkId::new("balanced", n), &tree, |b, tree| {
+            b.iter(|| count_tips(black_box(tree)))
+        });
+    }
+    group.finish();
+}
+
+fn child_access(c: &mut Criterion) {
+    let mut group = c.benchmark_group("get_child");
+    group.sample_size(10);
+    for n in TIPS {
+        let tree = ladder(n);
+        group.bench_with_input(BenchmarkId::new("ladder", n), &tree, |b, tree| {
+            b.iter(|| visit_children(black_box(tree)))
+        });
+        let tree = balanced(n);
+        group.bench_with_input(BenchmarkId::new("balanced", n), &tree, |b, tree| {
+            b.iter(|| visit_children(black_box(tree)))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, node_kinds, child_access);
+criterion_main!(benches);
@@ -0,0 +1,117 @@
+use std::collections::HashMap;
+
+/// Sequences keyed by taxon, kept in the order they were added.
+///
+/// Sequences are not required to be the same length. [is_aligned](Alignment::is_aligned)
+/// checks that they are.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Alignment {
+    taxa: Vec<String>,
+    sequences: Vec<String>,
+    index: HashMap<String, usize>,
+}
+
+impl Alignment {
+    pub fn new() -> Self {
+        Alignment::default()
+    }
+
+    /// Add a sequence. Returns false and leaves the alignment unchanged if the taxon is
+    /// already present.
+    pub fn add(&mut self, taxon: String, sequence: String) -> bool {
+        if self.index.contains_key(&taxon) {
+            return false;
+        }
+        self.index.insert(taxon.clone(), self.taxa.len());
+        self.taxa.push(taxon);
+        self.sequences.push(sequence);
+        true
+    }
+
+    /// Append to the sequence of a taxon, adding it if needed. Used for interleaved input.
+    pub fn extend(&mut self, taxon: &str, sequence: &str) {
+        match self.index.get(taxon) {
+            Some(i) => self.sequences[*i].push_str(sequence),
+            None => {
+                self.add(taxon.to_string(), sequence.to_string());
+            }
+        }
+    }
+
+    pub fn get(&self, taxon: &str) -> Option<&str> {
+        self.index.get(taxon).map(|i| self.sequences[*i].as_str())
+    }
+
+    pub fn contains(&self, taxon: &str) -> bool {
+        self.index.contains_key(taxon)
+    }
+
+    pub fn taxa(&self) -> &[String] {
+        &self.taxa
+    }
+
+    /// Taxa and sequences in the order they were added
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.taxa
+            .iter()
+            .zip(self.sequences.iter())
+            .map(|(t, s)| (t.as_str(), s.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.taxa.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.taxa.is_empty()
+    }
+
+    /// The length of the sequences if they are all the same
+    pub fn width(&self) -> Option<usize> {
+        let first = self.sequences.first()?.len();
+        if self.sequences.iter().all(|s| s.len() == first) {
+            Some(first)
+        } else {
+            None
+        }
+    }
+
+    pub fn is_aligned(&self) -> bool {
+        self.is_empty() || self.width().is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::parser::newick_importer::NewickImporter;
+    use std::io::BufReader;
+
+    #[test]
+    fn add_and_extend() {
+        let mut alignment = Alignment::new();
+        assert!(alignment.add("A".to_string(), "AC".to_string()));
+        assert!(!alignment.add("A".to_string(), "GT".to_string()));
+        alignment.extend("B", "AC");
+        alignment.extend("A", "GT");
+        assert_eq!(Some("ACGT"), alignment.get("A"));
+        assert_eq!(vec!["A", "B"], alignment.taxa());
+        assert!(!alignment.is_aligned());
+        alignment.extend("B", "--");
+        assert_eq!(Some(4), alignment.width());
+    }
+
+    #[test]
+    fn attach_to_tree() {
+        let mut tree = NewickImporter::read_tree(BufReader::new("((A,B),C);".as_bytes())).unwrap();
+        let mut alignment = Alignment::new();
+        alignment.add("A".to_string(), "ACGT".to_string());
+        alignment.add("C".to_string(), "AGGT".to_string());
+        alignment.add("D".to_string(), "TGGT".to_string());
+        assert_eq!(vec!["D".to_string()], tree.attach_sequences(&alignment));
+        let a = tree.get_taxon_node("A").unwrap();
+        let b = tree.get_taxon_node("B").unwrap();
+        assert_eq!(Some("ACGT"), tree.get_sequence(a));
+        assert_eq!(None, tree.get_sequence(b));
+    }
+}
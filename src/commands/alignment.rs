@@ -0,0 +1,144 @@
+use rebl::alignment::Alignment;
+use rebl::io::compression;
+use rebl::io::parser::alignment_importer::read_alignment;
+use rebl::io::parser::tree_importer::TreeImporter;
+use rebl::io::writer::fasta_writer::write_fasta;
+use rebl::tree::mutable_tree::MutableTree;
+use std::collections::HashSet;
+use std::error::Error;
+use std::io::Write;
+use std::path;
+
+/// Write the sequences of the first tree's tips in the order they appear in the tree.
+/// Taxa found in only the tree or only the alignment are logged as warnings. If
+/// `pruned_trees` is given every tree is pruned to the taxa in the alignment and written
+/// there. Trees that share fewer than two taxa with the alignment are skipped with a
+/// warning.
+pub fn run<R: std::io::Read, T: TreeImporter<R>>(
+    mut trees: T,
+    handle: &mut dyn Write,
+    alignment: path::PathBuf,
+    pruned_trees: Option<path::PathBuf>,
+) -> Result<(), Box<dyn Error>> {
+    let alignment = read_alignment(compression::open_reader(&alignment)?)?;
+    if !trees.has_tree() {
+        return Err("no tree to match the alignment to".into());
+    }
+    let mut tree = trees.read_next_tree()?;
+    let (matched, missing) = match_tips(&tree, &alignment);
+    report(&tree, &alignment, &missing);
+    write_fasta(
+        handle,
+        matched
+            .iter()
+            .map(|taxon| (*taxon, alignment.get(taxon).unwrap())),
+    )?;
+
+    match pruned_trees {
+        Some(path) => {
+            let mut out = compression::create_writer(&path)?;
+            let taxa: HashSet<String> = alignment.taxa().iter().cloned().collect();
+            loop {
+                let shared = tree
+                    .external_nodes
+                    .iter()
+                    .filter(|tip| {
+                        tree.get_taxon(**tip)
+                            .is_some_and(|taxon| alignment.contains(taxon))
+                    })
+                    .count();
+                if shared == tree.external_nodes.len() {
+                    writeln!(out, "{}", tree)?;
+                } else if shared < 2 {
+                    warn!(
+                        "skipping tree {} as it shares {} taxa with the alignment",
+                        tree.get_id().unwrap_or("without an id"),
+                        shared
+                    );
+                } else {
+                    writeln!(out, "{}", MutableTree::from_tree(&mut tree, &taxa))?;
+                }
+                if !trees.has_tree() {
+                    break;
+                }
+                tree = trees.read_next_tree()?;
+            }
+            out.finish()?;
+        }
+        None => {
+            if trees.has_tree() {
+                warn!("only the first tree is used to order the alignment");
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The tips with a sequence in tree order, and the taxa of tips without one.
+fn match_tips<'a>(tree: &'a MutableTree, alignment: &Alignment) -> (Vec<&'a str>, Vec<&'a str>) {
+    tree.preorder_iter()
+        .filter(|node| tree.is_external(*node))
+        .filter_map(|node| tree.get_taxon(node))
+        .partition(|taxon| alignment.contains(taxon))
+}
+
+fn report(tree: &MutableTree, alignment: &Alignment, missing: &[&str]) {
+    if !missing.is_empty() {
+        warn!(
+            "{} taxa in the tree are not in the alignment: {}",
+            missing.len(),
+            missing.join(", ")
+        );
+    }
+    let extra = alignment
+        .taxa()
+        .iter()
+        .filter(|taxon| tree.get_taxon_node(taxon).is_none())
+        .map(|taxon| taxon.as_str())
+        .collect::<Vec<&str>>();
+    if !extra.is_empty() {
+        warn!(
+            "{} taxa in the alignment are not in the tree: {}",
+            extra.len(),
+            extra.join(", ")
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rebl::io::parser::alignment_importer::read_fasta;
+    use rebl::io::parser::newick_importer::NewickImporter;
+    use std::io::BufReader;
+
+    #[test]
+    fn tree_order() {
+        let tree =
+            NewickImporter::read_tree(BufReader::new("((C:1,A:1):1,(D:1,B:1):1);".as_bytes()))
+                .unwrap();
+        let alignment = read_fasta(">A\nAAAA\n>B\nCCCC\n>C\nGGGG\n>E\nTTTT\n".as_bytes()).unwrap();
+        let (matched, missing) = match_tips(&tree, &alignment);
+        assert_eq!(vec!["C", "A", "B"], matched);
+        assert_eq!(vec!["D"], missing);
+    }
+
+    #[test]
+    fn skip_trees_without_overlap() {
+        let dir = std::env::temp_dir();
+        let alignment = dir.join(format!("{}_overlap.fasta", std::process::id()));
+        let pruned = dir.join(format!("{}_overlap.nwk", std::process::id()));
+        std::fs::write(&alignment, ">A\nAAAA\n>B\nCCCC\n>C\nGGGG\n").unwrap();
+        let trees = NewickImporter::from_reader(BufReader::new(
+            "((A:1,B:1):1,C:2);((A:1,D:1):1,E:2);((D:1,E:1):1,F:2);((A:1,D:1):1,(B:1,E:1):1);"
+                .as_bytes(),
+        ));
+        let mut out = vec![];
+        run(trees, &mut out, alignment.clone(), Some(pruned.clone())).unwrap();
+        let written = std::fs::read_to_string(&pruned).unwrap();
+        std::fs::remove_file(&alignment).unwrap();
+        std::fs::remove_file(&pruned).unwrap();
+        assert_eq!(">A\nAAAA\n>B\nCCCC\n>C\nGGGG\n", String::from_utf8(out).unwrap());
+        assert_eq!("((A:1,B:1):1,C:2);\n(A:2,B:2);\n", written);
+    }
+}
@@ -1,7 +1,6 @@
 use super::command_io;
 
 use rebl::tree::mutable_tree::MutableTree;
-use rebl::tree::AnnotationValue;
 use std::collections::HashMap;
 use std::error::Error;
 use std::io::Write;
@@ -14,11 +13,9 @@ use std::fs::File;
 
 pub fn run<R: std::io::Read, T: TreeImporter<R>>(
     mut trees: T,
+    handle: &mut dyn Write,
     traits: path::PathBuf,
 ) -> Result<(), Box<dyn Error>> {
-    let stdout = std::io::stdout(); // get the global stdout entity
-    let mut handle = stdout.lock(); // acquire a lock on it
-
     while trees.has_tree() {
         let mut tree = trees.read_next_tree()?;
         //TODO avoid parsing at each loop
@@ -43,15 +40,13 @@ pub fn annotate_nodes(
         trace!("{:?}", result);
         let record: Record = result?;
         if let Some(taxon) = record.get(&*taxon_key).unwrap() {
-            if let Some(node_ref) = tree.get_label_node(&taxon) {
+            if let Some(node_ref) = tree.get_label_node(taxon) {
                 for (key, value) in record {
                     if key != taxon_key {
                         if let Some(annotation_value) = value {
-                            tree.annotate_node(
-                                node_ref,
-                                key,
-                                AnnotationParser::parse_annotation_value(&*annotation_value)?,
-                            );
+                            let annotation_value =
+                                AnnotationParser::parse_annotation_value(&key, &annotation_value)?;
+                            tree.annotate_node(node_ref, key, annotation_value);
                         }
                     }
                 }
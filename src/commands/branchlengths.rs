@@ -1,10 +1,8 @@
-use crate::commands::command_io;
+use crate::commands::parallel;
 use rebl::io::parser::tree_importer::TreeImporter;
 use rebl::tree::mutable_tree::MutableTree;
-use rebl::tree::mutable_tree::PreOrderIterator;
 use rebl::tree::AnnotationValue;
 use regex::Regex;
-use std::collections::HashMap;
 use std::error::Error;
 use std::fs::File;
 use std::io::Write;
@@ -93,13 +91,11 @@ pub enum TreeTimeSubCommands {
 
 pub fn run<R: std::io::Read, T: TreeImporter<R>>(
     mut trees: T,
+    handle: &mut dyn Write,
     cmd: SubCommands,
+    threads: usize,
 ) -> Result<(), Box<dyn Error>> {
-    let stdout = std::io::stdout(); // get the global stdout entity
-    let mut handle = stdout.lock(); // acquire a lock on it
-
-    while trees.has_tree() {
-        let mut tree = trees.read_next_tree()?;
+    parallel::for_each_tree(&mut trees, handle, threads, |_, mut tree, out| {
         match cmd {
             SubCommands::Scale { scalar } => {
                 scale(&mut tree, scalar);
@@ -117,20 +113,20 @@ pub fn run<R: std::io::Read, T: TreeImporter<R>>(
             SubCommands::Poisson { rate} =>poisson(&mut tree, rate),
             SubCommands::FromAnnotation{ref name, default} =>from_annotation(&mut tree, name, default)
         }
-        writeln!(handle, "{}", tree)?;
-    }
-    Ok(())
+        writeln!(out, "{}", tree)?;
+        Ok(())
+    })
 }
 //functions so we can test them
 fn scale(tree: &mut MutableTree, scalar: f64) {
-    for i in 0..tree.get_node_count() {
+    for i in 0..tree.arena_len() {
         if let Some(l) = tree.get_length(i) {
             tree.set_length(i, l * scalar);
         }
     }
 }
 fn poisson(tree: & mut MutableTree, rate:f64){
-    for i in 0..tree.get_node_count() {
+    for i in 0..tree.arena_len() {
         if let Some(l) = tree.get_length(i) {
             let r = rate*l;
 
@@ -143,7 +139,7 @@ fn poisson(tree: & mut MutableTree, rate:f64){
 }
 
 fn min_length(tree: &mut MutableTree, min_length: f64) {
-    for i in 0..tree.get_node_count() {
+    for i in 0..tree.arena_len() {
         if let Some(l) = tree.get_length(i) {
             if l < min_length {
                 tree.set_length(i, min_length);
@@ -152,7 +148,7 @@ fn min_length(tree: &mut MutableTree, min_length: f64) {
     }
 }
 fn round(tree: &mut MutableTree) {
-    for i in 0..tree.get_node_count() {
+    for i in 0..tree.arena_len() {
         if let Some(l) = tree.get_length(i) {
             tree.set_length(i, l.round());
         }
@@ -166,9 +162,6 @@ fn from_file(tree: &mut MutableTree, lengths: &Path) {
         .has_headers(false)
         .from_reader(file);
 
-    // We nest this call in its own scope because of lifetimes.
-    type Record = HashMap<String, f64>;
-
     for result in rdr.records() {
         let record = result.unwrap();
         let node = tree.get_label_node(&record[0]);
@@ -190,7 +183,7 @@ fn tree_time(tree: &mut MutableTree, cmd: &TreeTimeSubCommands) {
         TreeTimeSubCommands::Transversions => Regex::new(r"[AG]\d+[CT]|[CT]\d+[AG]").unwrap(),
     };
 
-    for i in 0..tree.get_node_count() {
+    for i in 0..tree.arena_len() {
         if i != tree.get_root().unwrap() {
             if let Some(mutations) = tree.get_annotation(i, "mutations") {
                 if let AnnotationValue::Discrete(mut_string) = mutations {
@@ -222,7 +215,7 @@ fn tree_time(tree: &mut MutableTree, cmd: &TreeTimeSubCommands) {
 fn from_annotation(tree: &mut MutableTree, name:&str, default: f64){
     if let Some(annotation) = tree.get_annotation_type(name){
         match annotation{
-            AnnotationValue::Continuous(_)=>{
+            AnnotationValue::Continuous(_) | AnnotationValue::Integer(_)=>{
 
             }
             _=>{
@@ -233,9 +226,9 @@ fn from_annotation(tree: &mut MutableTree, name:&str, default: f64){
         panic!("Tree does not contain annotation {}" ,name)
     }
     
-    for node in PreOrderIterator::new(None,tree){
-        if let Some(AnnotationValue::Continuous(new_length)) = tree.get_annotation(node,name){
-            tree.set_length(node,*new_length);
+    for node in tree.preorder_iter().collect::<Vec<_>>(){
+        if let Some(new_length) = tree.get_annotation(node,name).and_then(AnnotationValue::as_f64){
+            tree.set_length(node,new_length);
         }else{
             tree.set_length(node,default);
         }
@@ -256,7 +249,7 @@ mod tests {
         let mut tree =
             NewickImporter::read_tree(BufReader::new(s.as_bytes())).expect("error in parsing");
         scale(&mut tree, 10.0);
-        for i in 0..tree.get_node_count() {
+        for i in 0..tree.arena_len() {
             if i != tree.get_root().unwrap() {
                 assert_eq!(Some(1.0), tree.get_length(i));
             }
@@ -269,7 +262,7 @@ mod tests {
         let mut tree =
             NewickImporter::read_tree(BufReader::new(s.as_bytes())).expect("error in parsing");
         min_length(&mut tree, 10.0);
-        for i in 0..tree.get_node_count() {
+        for i in 0..tree.arena_len() {
             if i != tree.get_root().unwrap() {
                 assert_eq!(Some(10.0), tree.get_length(i));
             }
@@ -281,7 +274,7 @@ mod tests {
         let mut tree =
             NewickImporter::read_tree(BufReader::new(s.as_bytes())).expect("error in parsing");
         round(&mut tree);
-        for i in 0..tree.get_node_count() {
+        for i in 0..tree.arena_len() {
             if i != tree.get_root().unwrap() {
                 assert_eq!(Some(1.0), tree.get_length(i));
             }
@@ -386,7 +379,7 @@ mod tests {
         let mut tree =
             NewickImporter::read_tree(BufReader::new(s.as_bytes())).expect("error in parsing");
         from_annotation(&mut tree,"count",10.0);
-        for i in 0..tree.get_node_count() {
+        for i in 0..tree.arena_len() {
             if i != tree.get_root().unwrap() {
                 assert_eq!(Some(1.0), tree.get_length(i));
             }
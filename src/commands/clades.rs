@@ -1,6 +1,7 @@
-use rebl::tree::mutable_tree::{MutableTree, TreeIndex};
+use crate::commands::parallel;
+use rebl::tree::mutable_tree::{MutableTree, PostOrderIterator, TreeIndex};
 use rebl::tree::AnnotationValue;
-use std::collections::HashSet;
+use std::collections::{HashSet, VecDeque};
 use structopt::StructOpt;
 
 use rand::seq::SliceRandom;
@@ -8,6 +9,7 @@ use rebl::io::parser::tree_importer::TreeImporter;
 use std::error::Error;
 use std::io::Write;
 
+#[allow(dead_code)]
 #[derive(Debug, StructOpt)]
 pub struct SharedOptions {
     #[structopt(
@@ -59,21 +61,19 @@ pub enum SubCommands {
 //TODO set random seed.
 pub fn run<R: std::io::Read, T: TreeImporter<R>>(
     mut trees: T,
+    handle: &mut dyn Write,
     cmd: SubCommands,
+    threads: usize,
 ) -> Result<(), Box<dyn Error>> {
-    let stdout = std::io::stdout(); // get the global stdout entity
-    let mut handle = stdout.lock(); // acquire a lock on it
-
-    while trees.has_tree() {
-        let mut tree = trees.read_next_tree()?;
+    parallel::for_each_tree(&mut trees, handle, threads, |_, mut tree, out| {
         match cmd {
             SubCommands::Collapse {
                 ref annotation,
                 ref value,
                 min_size,
             } => {
-                let new_tree = collapse_uniform_clades(&mut tree, &annotation, &value, min_size);
-                writeln!(handle, "{}", new_tree)?;
+                let new_tree = collapse_uniform_clades(&mut tree, annotation, value, min_size);
+                writeln!(out, "{}", new_tree)?;
             }
             SubCommands::Label {
                 ref annotation,
@@ -81,12 +81,12 @@ pub fn run<R: std::io::Read, T: TreeImporter<R>>(
                 ref prefix,
                 ref internal,
             } => {
-                annotate_uniform_clades(&mut tree, &annotation, &value, &prefix, &internal);
-                writeln!(handle, "{}", tree)?;
+                annotate_uniform_clades(&mut tree, annotation, value, prefix, internal);
+                writeln!(out, "{}", tree)?;
             }
         }
-    }
-    Ok(())
+        Ok(())
+    })
 }
 
 pub fn collapse_uniform_clades(
@@ -163,63 +163,126 @@ fn annotate_uniform_clades(
     }
 }
 
+type Groups = (bool, VecDeque<VecDeque<TreeIndex>>);
+
+/// Whether the subtree below `node_ref` is all in the target, and the groups of tips and
+/// internal nodes that form monophyletic clades of the target within it. Nodes are
+/// visited in postorder and their results kept until their parent is reached.
 fn get_monophyletic_groups(
     tree: &MutableTree,
     node_ref: TreeIndex,
     key: &str,
     target_annotation: &str,
 ) -> (bool, Vec<Vec<TreeIndex>>) {
-    if tree.is_external(node_ref) {
-        if let Some(annotation) = tree.get_annotation(node_ref, key) {
-            match annotation {
-                AnnotationValue::Discrete(s) => {
-                    return if s == target_annotation {
-                        (true, vec![vec![node_ref]])
-                    } else {
-                        (false, vec![vec![]])
-                    };
-                }
-                _ => {
-                    panic!("not a discrete trait")
+    let mut results: Vec<Option<Groups>> = vec![None; tree.arena_len()];
+    for node in PostOrderIterator::new(Some(node_ref), tree) {
+        let result = if tree.is_external(node) {
+            tip_group(tree, node, key, target_annotation)
+        } else {
+            let child_output = tree
+                .get_children(node)
+                .iter()
+                .map(|child| results[*child].take().expect("children are visited first"))
+                .collect::<Vec<Groups>>();
+            let am_i_a_root = child_output
+                .iter()
+                .map(|t| t.0)
+                .fold(true, |acc, b| acc & b);
+            if am_i_a_root {
+                let mut combined_child_tips = merge(child_output.into_iter().flat_map(|t| t.1));
+                combined_child_tips.push_back(node);
+                (true, VecDeque::from([combined_child_tips]))
+            } else {
+                let child_tips = merge(child_output.into_iter().map(|t| t.1));
+                (false, child_tips)
+            }
+        };
+        results[node] = Some(result);
+    }
+    let (monophyletic, groups) = results[node_ref].take().expect("the start node is visited last");
+    (
+        monophyletic,
+        groups.into_iter().map(Vec::from).collect(),
+    )
+}
+
+/// Join lists in order. The others are added to either end of the longest one so the
+/// groups of long ladders are not copied at each node.
+fn merge<T>(lists: impl IntoIterator<Item = VecDeque<T>>) -> VecDeque<T> {
+    let mut lists = lists.into_iter().collect::<Vec<VecDeque<T>>>();
+    let longest = match (0..lists.len()).max_by_key(|i| lists[*i].len()) {
+        Some(longest) => longest,
+        None => return VecDeque::new(),
+    };
+    let after = lists.split_off(longest + 1);
+    let mut merged = lists.pop().expect("the longest list is last");
+    for list in lists.into_iter().rev() {
+        for item in list.into_iter().rev() {
+            merged.push_front(item);
+        }
+    }
+    for list in after {
+        merged.extend(list);
+    }
+    merged
+}
+
+fn tip_group(
+    tree: &MutableTree,
+    node_ref: TreeIndex,
+    key: &str,
+    target_annotation: &str,
+) -> Groups {
+    if let Some(annotation) = tree.get_annotation(node_ref, key) {
+        match annotation {
+            AnnotationValue::Discrete(s) => {
+                if s == target_annotation {
+                    (true, VecDeque::from([VecDeque::from([node_ref])]))
+                } else {
+                    (false, VecDeque::from([VecDeque::new()]))
                 }
             }
-        } else {
-            return (false, vec![vec![]]);
+            _ => {
+                panic!("not a discrete trait")
+            }
         }
+    } else {
         // ignoring empty nodes they are counted
         // panic!("Annotation not found on a tip: {}. all tips must be annotated", tree.get_taxon(node_ref).unwrap_or("no label"));
+        (false, VecDeque::from([VecDeque::new()]))
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::test_utils;
+    use rebl::io::parser::newick_importer::NewickImporter;
+    use std::io::BufReader;
 
-    let mut child_output = vec![];
-    for child in tree.get_children(node_ref).iter() {
-        child_output.push(get_monophyletic_groups(
-            tree,
-            *child,
-            key,
-            &target_annotation,
+    #[test]
+    fn label_deep_ladder() {
+        let depth = 100_000;
+        let mut tree = NewickImporter::read_tree(BufReader::new(
+            test_utils::deep_ladder(depth).as_bytes(),
         ))
-    }
-    let am_i_a_root = child_output
-        .iter()
-        .map(|t| t.0)
-        .fold(true, |acc, b| acc & b);
-    if am_i_a_root {
-        let mut combined_child_tips = child_output
-            .into_iter()
-            .map(|t| t.1)
-            .flatten()
-            .flatten()
-            .collect::<Vec<TreeIndex>>();
-        combined_child_tips.push(node_ref);
-        (true, vec![combined_child_tips])
-    } else {
-        let child_tips = child_output
-            .into_iter()
-            .map(|t| t.1)
-            .fold(vec![], |mut acc, next| {
-                acc.extend(next);
-                acc
-            });
-        (false, child_tips)
+        .unwrap();
+        let us = format!("t{}", depth);
+        for tip in tree.external_nodes.clone() {
+            let location = if tree.get_taxon(tip) == Some(us.as_str()) { "US" } else { "UK" };
+            tree.annotate_node(
+                tip,
+                "location".to_string(),
+                AnnotationValue::Discrete(location.to_string()),
+            );
+        }
+        annotate_uniform_clades(&mut tree, "location", "UK", &None, &false);
+        let clade = Some(AnnotationValue::Discrete("_UK.0".to_string()));
+        for taxon in ["t0", "t99999"] {
+            let tip = tree.get_taxon_node(taxon).unwrap();
+            assert_eq!(clade.as_ref(), tree.get_annotation(tip, "Clade"));
+        }
+        let us = tree.get_taxon_node(&us).unwrap();
+        assert_eq!(None, tree.get_annotation(us, "Clade"));
     }
 }
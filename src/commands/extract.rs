@@ -1,8 +1,10 @@
 use std::error::Error;
-use rebl::tree::mutable_tree::MutableTree;
+use crate::commands::stats;
+use rebl::tree::Tree;
 use structopt::StructOpt;
 
 use rebl::io::parser::tree_importer::TreeImporter;
+use rebl::io::writer::table_writer::TableWriter;
 use rebl::tree::AnnotationValue;
 use std::io::Write;
 
@@ -27,6 +29,18 @@ pub enum SubCommands {
         )]
         index: Option<usize>,
     },
+    /// Extract a tsv with one row per node: node and parent ids, taxon, label, length,
+    /// height and all annotations. This can be read back as a tree.
+    Table,
+    /// Extract a tsv with one row per tree: its id and the tree annotations, such as the
+    /// posterior and lnP in BEAST tree files. Annotation columns come from the first tree.
+    TreeAnnotations {
+        #[structopt(
+            long,
+            help = "add the nodes, tips, rootHeight, sumbl and meanbl columns from stats"
+        )]
+        stats: bool,
+    },
     ///Extract annotation transitions in tree
     Transitions{
         #[structopt(short, long, help = "name of the discrete annotation")]
@@ -36,78 +50,89 @@ pub enum SubCommands {
 
 pub fn run<R: std::io::Read, T: TreeImporter<R>>(
     trees: T,
+    handle: &mut dyn Write,
     cmd: SubCommands,
 ) -> Result<(), Box<dyn Error>> {
     match cmd {
-        SubCommands::Taxa => taxa(trees),
-        SubCommands::Annotations => annotations(trees),
-        SubCommands::Tree { id, index } => tree(trees, id, index),
-        SubCommands::Transitions{key}=>transitions(trees,key)
+        SubCommands::Taxa => taxa(trees, handle),
+        SubCommands::Annotations => annotations(trees, handle),
+        SubCommands::Tree { id, index } => tree(trees, handle, id, index),
+        SubCommands::Table => table(trees, handle),
+        SubCommands::TreeAnnotations { stats } => tree_annotations(trees, handle, stats),
+        SubCommands::Transitions{key}=>transitions(trees, handle, key)
     }
 }
 
-fn taxa<R: std::io::Read, T: TreeImporter<R>>(mut trees: T) -> Result<(), Box<dyn Error>> {
-    let stdout = std::io::stdout(); // get the global stdout entity
-    let mut handle = stdout.lock(); // acquire a lock on it
+fn taxa<R: std::io::Read, T: TreeImporter<R>>(
+    mut trees: T,
+    handle: &mut dyn Write,
+) -> Result<(), Box<dyn Error>> {
     while trees.has_tree() {
         let tree = trees.read_next_tree()?;
-        let mut i = 0;
-        while i < tree.get_external_node_count() {
-            if let Some(tip) = tree.get_external_node(i) {
-                if let Some(taxa) = &tip.taxon {
-                    writeln!(handle, "{}", taxa)?;
-                }
-            }
-            i += 1;
+        write_taxa(&tree, handle)?;
+    }
+    Ok(())
+}
+
+fn write_taxa<T: Tree>(tree: &T, handle: &mut dyn Write) -> Result<(), Box<dyn Error>> {
+    for tip in tree.get_external_nodes() {
+        if let Some(taxa) = tree.get_taxon(tip) {
+            writeln!(handle, "{}", taxa)?;
         }
     }
     Ok(())
 }
 
-fn annotations<R: std::io::Read, T: TreeImporter<R>>(mut trees: T) -> Result<(), Box<dyn Error>> {
-    let stdout = std::io::stdout(); // get the global stdout entity
-    let mut handle = stdout.lock(); // acquire a lock on it
-    let mut i =0;
-    
+fn annotations<R: std::io::Read, T: TreeImporter<R>>(
+    mut trees: T,
+    handle: &mut dyn Write,
+) -> Result<(), Box<dyn Error>> {
     //get annotation keys from first tree
     let tree = trees.read_next_tree()?;
-    let annotations = tree
-        .annotation_type
-        .keys()
-        .cloned()
-        .collect::<Vec<String>>();
+    let annotations = annotation_keys(&tree);
     let header = annotations.join("\t");
 
-     writeln!(handle, "tree\ttaxa\t{}", header)?;
-        // process first tree
-      for node_ref in tree.external_nodes.iter() {
-            let annotation_string = annotations.iter()
-                .map(|k| annotation_value_string(tree.get_annotation(*node_ref, k)))
-                .collect::<Vec<String>>()
-                .join("\t");
-            if let Some(taxa) = tree.get_taxon(*node_ref) {
-                writeln!(handle, "{}\t{}\t{}", i, taxa, annotation_string)?;
-            } else {
-                writeln!(handle, "{}\t\t{}", i,annotation_string)?;
-            }
-        }
-        i+=1;
-
-
+    writeln!(handle, "tree\ttaxa\t{}", header)?;
+    write_tip_annotations(&tree, 0, &annotations, handle)?;
+    let mut i = 1;
     while trees.has_tree() {
         let tree = trees.read_next_tree()?;
-        for node_ref in tree.external_nodes.iter() {
-            let annotation_string = annotations.iter()
-                .map(|k| annotation_value_string(tree.get_annotation(*node_ref, k)))
-                .collect::<Vec<String>>()
-                .join("\t");
-            if let Some(taxa) = tree.get_taxon(*node_ref) {
-                writeln!(handle, "{}\t{}\t{}", i, taxa, annotation_string)?;
-            } else {
-                writeln!(handle, "{}\t\t{}", i,annotation_string)?;
-            }
+        write_tip_annotations(&tree, i, &annotations, handle)?;
+        i += 1;
+    }
+    Ok(())
+}
+
+/// The keys annotated on any node, sorted
+fn annotation_keys<T: Tree>(tree: &T) -> Vec<String> {
+    let mut keys = tree
+        .preorder()
+        .into_iter()
+        .flat_map(|node| tree.get_annotations(node))
+        .map(|(key, _)| key.to_string())
+        .collect::<Vec<String>>();
+    keys.sort();
+    keys.dedup();
+    keys
+}
+
+fn write_tip_annotations<T: Tree>(
+    tree: &T,
+    i: usize,
+    annotations: &[String],
+    handle: &mut dyn Write,
+) -> Result<(), Box<dyn Error>> {
+    for node_ref in tree.get_external_nodes() {
+        let annotation_string = annotations
+            .iter()
+            .map(|k| annotation_value_string(tree.get_annotation(node_ref, k)))
+            .collect::<Vec<String>>()
+            .join("\t");
+        if let Some(taxa) = tree.get_taxon(node_ref) {
+            writeln!(handle, "{}\t{}\t{}", i, taxa, annotation_string)?;
+        } else {
+            writeln!(handle, "{}\t\t{}", i, annotation_string)?;
         }
-        i+=1;
     }
     Ok(())
 }
@@ -122,11 +147,10 @@ fn annotation_value_string(value: Option<&AnnotationValue>) -> String {
 
 fn tree<R: std::io::Read, T: TreeImporter<R>>(
     mut trees: T,
+    handle: &mut dyn Write,
     id: Option<String>,
     index: Option<usize>,
 ) -> Result<(), Box<dyn Error>> {
-    let stdout = std::io::stdout(); // get the global stdout entity
-    let mut handle = stdout.lock(); // acquire a lock on it
     let mut found = false;
 
     if let Some(i) = index {
@@ -156,17 +180,70 @@ fn tree<R: std::io::Read, T: TreeImporter<R>>(
     Ok(())
 }
 
+fn table<R: std::io::Read, T: TreeImporter<R>>(
+    mut trees: T,
+    handle: &mut dyn Write,
+) -> Result<(), Box<dyn Error>> {
+    let mut writer = TableWriter::new(handle);
+    while trees.has_tree() {
+        let mut tree = trees.read_next_tree()?;
+        if tree.branchlengths_known {
+            tree.calc_node_heights();
+        }
+        writer.write_tree(&tree)?;
+    }
+    writer.finish()?;
+    Ok(())
+}
+
+fn tree_annotations<R: std::io::Read, T: TreeImporter<R>>(
+    mut trees: T,
+    handle: &mut dyn Write,
+    with_stats: bool,
+) -> Result<(), Box<dyn Error>> {
+    let mut keys: Vec<String> = vec![];
+    let mut i = 0;
+    while trees.has_tree() {
+        let tree = trees.read_next_tree()?;
+        if i == 0 {
+            keys = tree.tree_annotation.keys().cloned().collect();
+            keys.sort();
+            let mut header = vec!["tree".to_string()];
+            header.extend(keys.iter().cloned());
+            if with_stats {
+                header.push(stats::GENERAL_STATS_HEADER.to_string());
+            }
+            writeln!(handle, "{}", header.join("\t"))?;
+        }
+        let mut row = vec![match tree.get_id() {
+            Some(id) => id.to_string(),
+            None => i.to_string(),
+        }];
+        row.extend(
+            keys.iter()
+                .map(|k| annotation_value_string(tree.tree_annotation.get(k))),
+        );
+        if with_stats {
+            row.push(stats::general_stats_row(&tree));
+        }
+        writeln!(handle, "{}", row.join("\t"))?;
+        i += 1;
+    }
+    Ok(())
+}
+
 struct Transition {
-    id: usize,
     source: String,
     destination:String,
     time:f64
 
 }
 
-fn transitions<R: std::io::Read, T: TreeImporter<R>>(mut trees: T,    key: String) -> Result<(), Box<dyn Error>> {
-    let stdout = std::io::stdout(); // get the global stdout entity
-    let mut handle = stdout.lock(); // acquire a lock on it
+fn transitions<R: std::io::Read, T: TreeImporter<R>>(
+    mut trees: T,
+    handle: &mut dyn Write,
+    key: String,
+) -> Result<(), Box<dyn Error>> {
      writeln!(
             handle,
             "tree\tsource\tdestination\theight"
@@ -200,24 +277,82 @@ fn transitions<R: std::io::Read, T: TreeImporter<R>>(mut trees: T,    key: Strin
     Ok(())
 }
 
-fn get_transitions(tree: &MutableTree,key: &String)->Vec<Transition> {
+fn get_transitions<T: Tree>(tree: &T, key: &str) -> Vec<Transition> {
     let mut transitions:Vec<Transition> = vec![];
 
     traverse(tree,tree.get_root().unwrap(),key,&mut transitions);
 
-    return transitions
+    transitions
 
 }
-fn traverse(tree: &MutableTree, node: usize,key: &String,transitions:&mut Vec<Transition>){
-    if let Some(value) = tree.get_annotation(node, key){
-        for child in tree.get_children(node){
-           let child_annotation = tree.get_annotation(child, key).unwrap_or_else(||  panic!("All nodes must be annotated. found a node without {}", key));
-           if child_annotation!=value {
-            transitions.push( Transition { id: transitions.len(), source: value.to_string(), destination: child_annotation.to_string(), time: tree.get_height(child).unwrap() })
+/// Record a transition for each branch whose ends differ, visiting nodes in preorder with
+/// their parent's value
+fn traverse<'a, T: Tree>(
+    tree: &'a T,
+    node: T::Node<'a>,
+    key: &str,
+    transitions: &mut Vec<Transition>,
+) {
+    let mut stack: Vec<(T::Node<'a>, Option<&AnnotationValue>)> = vec![(node, None)];
+    while let Some((node, parent_value)) = stack.pop() {
+        let value = tree.get_annotation(node, key).unwrap_or_else(||  panic!("All nodes must be annotated. found a node without {}", key));
+        if let Some(parent_value) = parent_value {
+           if value != parent_value {
+            transitions.push( Transition { source: parent_value.to_string(), destination: value.to_string(), time: tree.get_height(node).unwrap() })
            }
-           traverse(tree, child, key,transitions)
         }
-    }else{
-        panic!("All nodes must be annotated. found a node without {}", key)
+        stack.extend(tree.get_children(node).into_iter().rev().map(|child| (child, Some(value))));
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::{get_transitions, tree_annotations};
+    use crate::commands::test_utils;
+    use rebl::io::parser::newick_importer::NewickImporter;
+    use rebl::io::parser::nexus_importer::NexusImporter;
+    use rebl::tree::AnnotationValue;
+    use std::io::BufReader;
+
+    #[test]
+    fn tree_annotation_table() {
+        let nexus = "#NEXUS
+        BEGIN TREES;
+        TREE STATE_0 [&lnP=-10.5,posterior=-3] = [&R] ((A:1,B:1):1,C:2);
+        TREE STATE_1000 [&lnP=-9.5,posterior=-2] = [&R] ((A:1,C:1):2,B:2);
+        END;";
+        let mut out = vec![];
+        tree_annotations(NexusImporter::from_reader(nexus.as_bytes()), &mut out, true).unwrap();
+        assert_eq!(
+            "tree\tlnP\tposterior\tnodes\ttips\trootHeight\tsumbl\tmeanbl
+STATE_0\t-10.5\t-3\t5\t3\t2.00e0\t5.00e0\t1.25e0
+STATE_1000\t-9.5\t-2\t5\t3\t3.00e0\t6.00e0\t1.50e0
+",
+            String::from_utf8(out).unwrap()
+        );
+    }
+
+    #[test]
+    fn transitions_on_a_deep_ladder() {
+        let depth = 100_000;
+        let mut tree = NewickImporter::read_tree(BufReader::new(
+            test_utils::deep_ladder(depth).as_bytes(),
+        ))
+        .unwrap();
+        for node in 0..tree.arena_len() {
+            let odd_tip = tree
+                .get_taxon(node)
+                .is_some_and(|taxon| taxon[1..].parse::<usize>().unwrap() % 2 == 1);
+            let location = if odd_tip { "US" } else { "UK" };
+            tree.annotate_node(
+                node,
+                "location".to_string(),
+                AnnotationValue::Discrete(location.to_string()),
+            );
+        }
+        tree.calc_node_heights();
+        let transitions = get_transitions(&tree, "location");
+        assert_eq!(depth / 2, transitions.len());
+        assert_eq!("UK", transitions[0].source);
+        assert_eq!("US", transitions[0].destination);
+    }
+}
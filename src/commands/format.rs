@@ -1,32 +1,190 @@
+use std::collections::HashSet;
 use std::error::Error;
 use structopt::StructOpt;
 
 use rebl::io::parser::tree_importer::TreeImporter;
-use rebl::tree::AnnotationValue;
-use std::io::{Write, BufWriter};
-use std::fs::File;
+use rebl::io::serialization::write_json;
+use rebl::io::writer::auspice_writer::{write_auspice, AuspiceFormat};
+use rebl::io::writer::newick_writer::{write_formatted_newick, AnnotationDialect, NewickFormat};
+use rebl::io::writer::nexus_writer::NexusWriter;
+use rebl::io::writer::phyloxml_writer::PhyloXmlWriter;
+use std::io::Write;
 
 #[derive(Debug, StructOpt)]
 pub enum SubCommands {
-    /// Nexus format
-    nexus,
-    /// Newick
-    newick,
+    /// Nexus format with a taxa block and translation table built from the first tree
+    Nexus,
+    /// fertree's JSON representation with one tree per line
+    Json,
+    /// Nextstrain Auspice v2 JSON. One document is written per tree
+    Auspice {
+        #[structopt(long, help = "title shown in auspice. Defaults to the tree id")]
+        title: Option<String>,
+        #[structopt(
+            long,
+            help = "decimal date of the most recent tip. Used to write num_date for each node"
+        )]
+        date: Option<f64>,
+        #[structopt(
+            long,
+            default_value = "mutations",
+            help = "annotation with comma separated mutations from TreeTime"
+        )]
+        mutations: String,
+    },
+    /// PhyloXML with annotations written as confidence values and properties
+    Phyloxml,
+    /// Newick with control over annotations, labels and branch lengths
+    Newick {
+        #[structopt(long, help = "don't write any node annotations")]
+        no_annotations: bool,
+        #[structopt(
+            long,
+            number_of_values = 1,
+            help = "annotation key to drop. Can be used multiple times"
+        )]
+        drop_annotation: Vec<String>,
+        #[structopt(long, help = "write annotations as NHX comments ([&&NHX:key=value])")]
+        nhx: bool,
+        #[structopt(long, help = "don't write labels on internal nodes")]
+        no_internal_labels: bool,
+        #[structopt(
+            long,
+            help = "don't write the tree id, tree annotations or rooting before each tree"
+        )]
+        no_tree_annotations: bool,
+        #[structopt(
+            long,
+            conflicts_with = "significant-digits",
+            help = "number of decimal places for branch lengths"
+        )]
+        precision: Option<usize>,
+        #[structopt(long, help = "number of significant digits for branch lengths")]
+        significant_digits: Option<usize>,
+        #[structopt(
+            long,
+            help = "only write the topology and taxa (no lengths, labels or annotations)"
+        )]
+        topology: bool,
+    },
 }
 
 pub fn run<R: std::io::Read, T: TreeImporter<R>>(
     trees: T,
+    handle: &mut dyn Write,
     cmd: SubCommands,
 ) -> Result<(), Box<dyn Error>> {
     match cmd {
-        SubCommands::nexus => {
-            println!("nexus not implemented");
-            Ok(())
-        },
-        SubCommands::newick =>{
-            println!("newick not implemented");
-            Ok(())
-        } ,
+        SubCommands::Nexus => nexus(trees, handle),
+        SubCommands::Phyloxml => phyloxml(trees, handle),
+        SubCommands::Json => json(trees, handle),
+        SubCommands::Auspice {
+            title,
+            date,
+            mutations,
+        } => {
+            let format = AuspiceFormat {
+                title,
+                num_date: date.is_some(),
+                mutations_key: mutations,
+            };
+            auspice(trees, handle, &format, date)
+        }
+        SubCommands::Newick {
+            no_annotations,
+            drop_annotation,
+            nhx,
+            no_internal_labels,
+            no_tree_annotations,
+            precision,
+            significant_digits,
+            topology,
+        } => {
+            let format = if topology {
+                NewickFormat::topology()
+            } else {
+                NewickFormat {
+                    annotations: !no_annotations,
+                    dialect: if nhx {
+                        AnnotationDialect::Nhx
+                    } else {
+                        AnnotationDialect::Beast
+                    },
+                    excluded_annotations: drop_annotation.into_iter().collect::<HashSet<String>>(),
+                    internal_labels: !no_internal_labels,
+                    branch_lengths: true,
+                    precision,
+                    significant_digits,
+                    tree_annotations: !no_tree_annotations,
+                }
+            };
+            newick(trees, handle, &format)
+        }
     }
 }
 
+fn nexus<R: std::io::Read, T: TreeImporter<R>>(
+    mut trees: T,
+    handle: &mut dyn Write,
+) -> Result<(), Box<dyn Error>> {
+    let mut writer = NexusWriter::new(handle);
+    while trees.has_tree() {
+        let tree = trees.read_next_tree()?;
+        writer.write_tree(&tree)?;
+    }
+    writer.finish()?;
+    Ok(())
+}
+
+fn phyloxml<R: std::io::Read, T: TreeImporter<R>>(
+    mut trees: T,
+    handle: &mut dyn Write,
+) -> Result<(), Box<dyn Error>> {
+    let mut writer = PhyloXmlWriter::new(handle);
+    while trees.has_tree() {
+        let tree = trees.read_next_tree()?;
+        writer.write_tree(&tree)?;
+    }
+    writer.finish()?;
+    Ok(())
+}
+
+fn json<R: std::io::Read, T: TreeImporter<R>>(
+    mut trees: T,
+    handle: &mut dyn Write,
+) -> Result<(), Box<dyn Error>> {
+    while trees.has_tree() {
+        let tree = trees.read_next_tree()?;
+        writeln!(handle, "{}", write_json(&tree))?;
+    }
+    Ok(())
+}
+
+fn auspice<R: std::io::Read, T: TreeImporter<R>>(
+    mut trees: T,
+    handle: &mut dyn Write,
+    format: &AuspiceFormat,
+    date: Option<f64>,
+) -> Result<(), Box<dyn Error>> {
+    while trees.has_tree() {
+        let mut tree = trees.read_next_tree()?;
+        if let Some(date) = date {
+            tree.calc_relative_node_heights(date);
+        }
+        write_auspice(handle, &tree, format)?;
+        writeln!(handle)?;
+    }
+    Ok(())
+}
+
+fn newick<R: std::io::Read, T: TreeImporter<R>>(
+    mut trees: T,
+    handle: &mut dyn Write,
+    format: &NewickFormat,
+) -> Result<(), Box<dyn Error>> {
+    while trees.has_tree() {
+        let tree = trees.read_next_tree()?;
+        writeln!(handle, "{}", write_formatted_newick(&tree, format))?;
+    }
+    Ok(())
+}
@@ -1,7 +1,9 @@
+pub mod alignment;
 pub mod annotate;
 pub mod branchlengths;
 pub mod clades;
 pub mod extract;
+pub mod parallel;
 pub mod prune;
 pub mod resolve;
 pub mod split;
@@ -9,6 +11,9 @@ pub mod stats;
 pub mod transmission_lineage;
 pub mod format;
 pub mod transmission_chain;
+#[cfg(test)]
+#[path = "../tree/test_utils.rs"]
+pub(crate) mod test_utils;
 
 pub mod command_io {
     use csv::Reader;
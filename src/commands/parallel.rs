@@ -0,0 +1,262 @@
+use rebl::io::parser::chained_importer::SourceTracker;
+use rebl::io::parser::tree_importer::TreeImporter;
+use rebl::tree::mutable_tree::MutableTree;
+use std::any::Any;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::io::Write;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
+use std::sync::mpsc;
+
+pub type ProcessResult = Result<(), Box<dyn Error + Send + Sync>>;
+
+/// Apply `process` to every remaining tree and write its output in input order.
+///
+/// `process` gets the tree's index, the tree and a buffer for its output. With one thread
+/// the trees are processed in turn. Otherwise trees are read on the calling thread and
+/// handed to a pool of `threads` workers (0 uses one per core). Output from trees that
+/// finish early is held until the trees before them have been written, and reading waits
+/// when too many trees are in flight so memory use stays bounded. The first error stops
+/// any more trees being read. The file each tree came from is carried with its output and
+/// put back in the importer's source tracker as the output is written, so output labelled
+/// by file is labelled with the tree's file rather than the one being read.
+pub fn for_each_tree<R, T, F>(
+    trees: &mut T,
+    handle: &mut dyn Write,
+    threads: usize,
+    process: F,
+) -> Result<(), Box<dyn Error>>
+where
+    T: TreeImporter<R>,
+    F: Fn(usize, MutableTree, &mut Vec<u8>) -> ProcessResult + Sync,
+{
+    if threads == 1 {
+        let mut index = 0;
+        while trees.has_tree() {
+            let tree = trees.read_next_tree()?;
+            let mut out = vec![];
+            process(index, tree, &mut out).map_err(|e| -> Box<dyn Error> { e })?;
+            handle.write_all(&out)?;
+            index += 1;
+        }
+        return Ok(());
+    }
+
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .build()?;
+    let max_in_flight = 4 * pool.current_num_threads();
+    debug!("processing trees with {} threads", pool.current_num_threads());
+    let (sender, receiver) = mpsc::channel();
+    let source = trees.source_tracker();
+    let mut output = OrderedOutput::new(handle, source.clone());
+    let mut read_error = None;
+    pool.in_place_scope(|scope| {
+        let mut read = 0;
+        while output.error.is_none() && trees.has_tree() {
+            while read - output.next >= max_in_flight {
+                let (index, path, result) = receiver.recv().expect("workers hold a sender");
+                output.push(index, path, result);
+            }
+            match trees.read_next_tree() {
+                Ok(tree) => {
+                    let path = source.as_ref().and_then(|s| s.lock().unwrap().clone());
+                    let sender = sender.clone();
+                    let process = &process;
+                    let index = read;
+                    scope.spawn(move |_| {
+                        let mut out = vec![];
+                        // a panic is sent back as an error so the reading loop stops
+                        // rather than waiting on a tree that will never arrive
+                        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                            process(index, tree, &mut out)
+                        }))
+                        .unwrap_or_else(|payload| Err(panic_message(payload).into()))
+                        .map(|_| out);
+                        // the receiver only goes away once every tree is written
+                        let _ = sender.send((index, path, result));
+                    });
+                    read += 1;
+                }
+                Err(e) => {
+                    read_error = Some(e);
+                    break;
+                }
+            }
+            while let Ok((index, path, result)) = receiver.try_recv() {
+                output.push(index, path, result);
+            }
+        }
+    });
+    drop(sender);
+    for (index, path, result) in receiver {
+        output.push(index, path, result);
+    }
+    if let Some(e) = output.error {
+        return Err(e);
+    }
+    match read_error {
+        Some(e) => Err(e.into()),
+        None => Ok(()),
+    }
+}
+
+fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    let message = match payload.downcast::<String>() {
+        Ok(message) => *message,
+        Err(payload) => match payload.downcast::<&str>() {
+            Ok(message) => message.to_string(),
+            Err(_) => "unknown error".to_string(),
+        },
+    };
+    format!("a worker panicked: {}", message)
+}
+
+type TreeOutput = Result<Vec<u8>, Box<dyn Error + Send + Sync>>;
+
+/// Holds finished trees until everything before them has been written.
+struct OrderedOutput<'a> {
+    handle: &'a mut dyn Write,
+    source: Option<SourceTracker>,
+    waiting: BTreeMap<usize, (Option<PathBuf>, TreeOutput)>,
+    next: usize,
+    error: Option<Box<dyn Error>>,
+}
+
+impl<'a> OrderedOutput<'a> {
+    fn new(handle: &'a mut dyn Write, source: Option<SourceTracker>) -> Self {
+        OrderedOutput {
+            handle,
+            source,
+            waiting: BTreeMap::new(),
+            next: 0,
+            error: None,
+        }
+    }
+
+    fn push(&mut self, index: usize, path: Option<PathBuf>, result: TreeOutput) {
+        self.waiting.insert(index, (path, result));
+        while let Some((path, result)) = self.waiting.remove(&self.next) {
+            self.next += 1;
+            if self.error.is_some() {
+                continue;
+            }
+            match result {
+                Ok(out) => {
+                    if let Some(source) = &self.source {
+                        *source.lock().unwrap() = path;
+                    }
+                    if let Err(e) = self.handle.write_all(&out) {
+                        self.error = Some(e.into());
+                    }
+                }
+                Err(e) => self.error = Some(e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rebl::io::parser::chained_importer::ChainedImporter;
+    use rebl::io::parser::newick_importer::NewickImporter;
+    use rebl::io::writer::source_column::SourceColumnWriter;
+    use std::io::BufReader;
+
+    fn tip_counts(threads: usize) -> String {
+        let input: String = (2..60)
+            .map(|n| {
+                let tips: Vec<String> = (0..n).map(|i| format!("t{}", i)).collect();
+                format!("({});", tips.join(","))
+            })
+            .collect();
+        let mut trees = NewickImporter::from_reader(BufReader::new(input.as_bytes()));
+        let mut out = vec![];
+        for_each_tree(&mut trees, &mut out, threads, |index, tree, out| {
+            writeln!(out, "{}\t{}", index, tree.get_external_node_count())?;
+            Ok(())
+        })
+        .unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn ordered_output() {
+        let sequential = tip_counts(1);
+        assert!(sequential.starts_with("0\t2\n1\t3\n"));
+        assert_eq!(sequential, tip_counts(4));
+    }
+
+    fn labelled_tip_counts(paths: &[PathBuf], threads: usize) -> String {
+        let mut trees = ChainedImporter::from_paths(paths.to_vec());
+        let source = trees.source_tracker().unwrap();
+        let mut out = vec![];
+        {
+            let mut writer = SourceColumnWriter::new(&mut out, source, false);
+            for_each_tree(&mut trees, &mut writer, threads, |index, tree, out| {
+                writeln!(out, "{}\t{}", index, tree.get_external_node_count())?;
+                Ok(())
+            })
+            .unwrap();
+        }
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn rows_keep_their_file() {
+        let paths: Vec<PathBuf> = ["first", "second"]
+            .iter()
+            .map(|name| {
+                let path = std::env::temp_dir()
+                    .join(format!("{}_parallel_{}.nwk", std::process::id(), name));
+                let trees: String = (0..40).map(|_| "(A,B,C);\n").collect();
+                std::fs::write(&path, trees).unwrap();
+                path
+            })
+            .collect();
+        let sequential = labelled_tip_counts(&paths, 1);
+        let parallel = labelled_tip_counts(&paths, 4);
+        for path in paths.iter() {
+            std::fs::remove_file(path).unwrap();
+        }
+        let first = format!("{}\t39\t3", paths[0].display());
+        assert!(sequential.contains(&first));
+        assert_eq!(sequential, parallel);
+    }
+
+    #[test]
+    fn worker_error() {
+        let mut trees = NewickImporter::from_reader(BufReader::new("(A,B);(A,B,C);".as_bytes()));
+        let mut out = vec![];
+        let result = for_each_tree(&mut trees, &mut out, 2, |index, _tree, out| {
+            if index == 1 {
+                return Err("bad tree".into());
+            }
+            writeln!(out, "{}", index)?;
+            Ok(())
+        });
+        assert_eq!("bad tree", result.unwrap_err().to_string());
+        assert_eq!("0\n", String::from_utf8(out).unwrap());
+    }
+
+    #[test]
+    fn worker_panic() {
+        let input: String = (0..100).map(|_| "(A,B);").collect();
+        let mut trees = NewickImporter::from_reader(BufReader::new(input.as_bytes()));
+        let mut out = vec![];
+        let result = for_each_tree(&mut trees, &mut out, 2, |index, _tree, out| {
+            if index == 1 {
+                panic!("bad tree");
+            }
+            writeln!(out, "{}", index)?;
+            Ok(())
+        });
+        assert_eq!(
+            "a worker panicked: bad tree",
+            result.unwrap_err().to_string()
+        );
+        assert_eq!("0\n", String::from_utf8(out).unwrap());
+    }
+}
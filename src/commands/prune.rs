@@ -1,5 +1,6 @@
+use crate::commands::parallel;
 use rand::prelude::SliceRandom;
-use rand::{seq::IteratorRandom, thread_rng};
+use rand::thread_rng;
 use rebl::io::parser::tree_importer::TreeImporter;
 use rebl::tree::mutable_tree::MutableTree;
 use std::collections::HashSet;
@@ -9,7 +10,6 @@ use std::io::Write;
 use std::io::{BufRead, BufReader};
 use std::path;
 use structopt::StructOpt;
-// use rayon::prelude::*;
 
 #[derive(Debug, StructOpt)]
 pub enum SubCommands {
@@ -41,54 +41,69 @@ pub enum SubCommands {
 
 pub fn run<R: std::io::Read, T: TreeImporter<R>>(
     mut trees: T,
+    handle: &mut dyn Write,
     cmd: SubCommands,
+    threads: usize,
 ) -> Result<(), Box<dyn Error>> {
-    let stdout = std::io::stdout(); // get the global stdout entity
-    let mut handle = stdout.lock(); // acquire a lock on it
-    let mut rng = thread_rng();
-    let mut taxa: HashSet<String> = HashSet::new();
-
-    // let mut tree = trees.read_next_tree()?;
+    let prune = |tree: &mut MutableTree, taxa: &HashSet<String>, keep_single_children: bool| {
+        if keep_single_children {
+            MutableTree::get_ancestral_tree(tree, taxa)
+        } else {
+            MutableTree::from_tree(tree, taxa)
+        }
+    };
     match cmd {
-        SubCommands::Sample { n, ref all , keep_single_children:keepSingleChildren} => {
-
-            while trees.has_tree() {
-                let mut tree = trees.read_next_tree()?;
-                let n = if n > 1.0 { n.round() as usize } else { (n * tree.get_external_node_count() as f64).round() as usize };
-                if !all || taxa.is_empty() {
-                    taxa = tree
-                        .external_nodes
-                        .choose_multiple(&mut rng, n)
-                        // .iter()
-                        .map(|nref| tree.get_taxon(*nref))
-                        .map(|n| String::from(n.unwrap()))
-                        .collect();
+        SubCommands::Sample { n, all , keep_single_children} => {
+            let sample_size = |tree: &MutableTree| {
+                if n > 1.0 { n.round() as usize } else { (n * tree.get_external_node_count() as f64).round() as usize }
+            };
+            let sample = |tree: &MutableTree| -> HashSet<String> {
+                tree.external_nodes
+                    .choose_multiple(&mut thread_rng(), sample_size(tree))
+                    .map(|nref| tree.get_taxon(*nref))
+                    .map(|n| String::from(n.unwrap()))
+                    .collect()
+            };
+            if all {
+                // the first tree picks the taxa used for all the others
+                if !trees.has_tree() {
+                    return Ok(());
                 }
+                let mut tree = trees.read_next_tree()?;
+                let taxa = sample(&tree);
                 debug!("{:?}", taxa);
-                let new_tree = if keepSingleChildren==true {MutableTree::get_ancestral_tree(&mut tree, &taxa)}else{MutableTree::from_tree(&mut tree, &taxa)};
-                writeln!(handle, "{}", new_tree)?;
+                writeln!(handle, "{}", prune(&mut tree, &taxa, keep_single_children))?;
+                parallel::for_each_tree(&mut trees, handle, threads, |_, mut tree, out| {
+                    writeln!(out, "{}", prune(&mut tree, &taxa, keep_single_children))?;
+                    Ok(())
+                })?;
+            } else {
+                parallel::for_each_tree(&mut trees, handle, threads, |_, mut tree, out| {
+                    let taxa = sample(&tree);
+                    debug!("{:?}", taxa);
+                    writeln!(out, "{}", prune(&mut tree, &taxa, keep_single_children))?;
+                    Ok(())
+                })?;
             }
         }
         SubCommands::Keep {
-            taxon_list: taxonList,
-            keep_single_children:keepSingleChildren
+            taxon_list,
+            keep_single_children
         } => {
-            let file = BufReader::new(File::open(&taxonList)?);
-            taxa = file.lines().map(|x| x.unwrap()).collect();
-            while trees.has_tree() {
-                let mut tree = trees.read_next_tree()?;
-                let new_tree = if keepSingleChildren ==true {MutableTree::get_ancestral_tree(&mut tree, &taxa)} else {MutableTree::from_tree(&mut tree, &taxa)};
-                writeln!(handle, "{}", new_tree)?;
-            }
+            let file = BufReader::new(File::open(&taxon_list)?);
+            let taxa: HashSet<String> = file.lines().map(|x| x.unwrap()).collect();
+            parallel::for_each_tree(&mut trees, handle, threads, |_, mut tree, out| {
+                writeln!(out, "{}", prune(&mut tree, &taxa, keep_single_children))?;
+                Ok(())
+            })?;
         }
         SubCommands::Remove {
-            taxon_list: taxonList,
-            keep_single_children:keepSingleChildren
+            taxon_list,
+            keep_single_children
         } => {
-            let file = BufReader::new(File::open(&taxonList)?);
-            taxa = file.lines().map(|x| x.unwrap()).collect();
-            while trees.has_tree() {
-                let mut tree = trees.read_next_tree()?;
+            let file = BufReader::new(File::open(&taxon_list)?);
+            let taxa: HashSet<String> = file.lines().map(|x| x.unwrap()).collect();
+            parallel::for_each_tree(&mut trees, handle, threads, |_, mut tree, out| {
                 let mut taxa_to_keep: HashSet<String> = tree
                     .external_nodes
                     .iter()
@@ -97,9 +112,9 @@ pub fn run<R: std::io::Read, T: TreeImporter<R>>(
                     .collect::<HashSet<String>>();
 
                 taxa_to_keep.retain(|s| taxa.contains(s));
-                let new_tree = if keepSingleChildren {MutableTree::get_ancestral_tree(&mut tree, &taxa_to_keep)} else {MutableTree::from_tree(&mut tree, &taxa_to_keep)};
-                writeln!(handle, "{}", new_tree)?;
-            }
+                writeln!(out, "{}", prune(&mut tree, &taxa_to_keep, keep_single_children))?;
+                Ok(())
+            })?;
         }
     }
 
@@ -1,3 +1,4 @@
+use crate::commands::parallel;
 use rand::{thread_rng, Rng};
 use rebl::io::parser::tree_importer::TreeImporter;
 use rebl::tree::mutable_tree::{MutableTree, TreeIndex};
@@ -20,16 +21,15 @@ struct Polytomy {
 
 pub fn run<R: std::io::Read, T: TreeImporter<R>>(
     mut trees: T,
+    handle: &mut dyn Write,
     cmd: SubCommands,
+    threads: usize,
 ) -> Result<(), Box<dyn Error>> {
-    let stdout = std::io::stdout(); // get the global stdout entity
-    let mut handle = stdout.lock(); // acquire a lock on it
-    while trees.has_tree() {
-        let mut tree = trees.read_next_tree()?;
+    parallel::for_each_tree(&mut trees, handle, threads, |_, mut tree, out| {
         resolve(&mut tree, &cmd);
-        writeln!(handle, "{}", tree)?;
-    }
-    Ok(())
+        writeln!(out, "{}", tree)?;
+        Ok(())
+    })
 }
 
 // collect all poltyomies and child vectors in a stuct
@@ -139,44 +139,30 @@ fn insert_nodes(tree: &mut MutableTree, node_ref: TreeIndex) {
     // split kids into two groups
     // if group is 1 add it as child
     //if group is add internal node as child and repeat
-    let mut kids = vec![];
-    for child in tree.get_children(node_ref) {
-        let removed = tree.remove_child(node_ref, child);
-        if let Some(c) = removed {
-            kids.push(c);
-        }
-    }
-    // TODO maybe not recursive.
+    let mut polytomies = vec![node_ref];
     let mut rng = thread_rng();
-    let n: usize = rng.gen_range(1..kids.len());
-
-    let first_family = &kids[0..n];
-    let second_family = &kids[n..kids.len()];
-
-    if first_family.len() == 1 {
-        tree.add_child(node_ref, first_family[0]);
-        tree.set_parent(node_ref, first_family[0]);
-    } else {
-        let still_polytomy = first_family.len() > 2;
-        let kido = tree.make_internal_node(first_family.to_owned());
-        tree.add_child(node_ref, kido);
-        tree.set_parent(node_ref, kido);
-        insert_nodes(tree, kido);
-        if still_polytomy {
-            insert_nodes(tree, kido);
+    while let Some(node_ref) = polytomies.pop() {
+        let mut kids = vec![];
+        for child in tree.get_children(node_ref) {
+            let removed = tree.remove_child(node_ref, child);
+            if let Some(c) = removed {
+                kids.push(c);
+            }
         }
-    }
-
-    if second_family.len() == 1 {
-        tree.add_child(node_ref, second_family[0]);
-        tree.set_parent(node_ref, second_family[0]);
-    } else {
-        let still_polytomy = second_family.len() > 2;
-        let kido = tree.make_internal_node(second_family.to_owned());
-        tree.add_child(node_ref, kido);
-        tree.set_parent(node_ref, kido);
-        if still_polytomy {
-            insert_nodes(tree, kido);
+        let n: usize = rng.gen_range(1..kids.len());
+
+        for family in [&kids[0..n], &kids[n..kids.len()]] {
+            if family.len() == 1 {
+                tree.add_child(node_ref, family[0]);
+                tree.set_parent(node_ref, family[0]);
+            } else {
+                let kido = tree.make_internal_node(family.to_owned());
+                tree.add_child(node_ref, kido);
+                tree.set_parent(node_ref, kido);
+                if family.len() > 2 {
+                    polytomies.push(kido);
+                }
+            }
         }
     }
 }
@@ -195,7 +181,7 @@ mod tests {
         println!("{}", tree.branchlengths_known);
         resolve(&mut tree, &SubCommands::Zero);
         println!("{}", tree.branchlengths_known);
-        println!("{}", tree.to_string());
+        println!("{}", tree);
         let mut bl = 0.0;
         for node in tree.nodes {
             if let Some(l) = node.length {
@@ -212,8 +198,23 @@ mod tests {
         tree.calc_node_heights();
         let starting_height = tree.get_height(tree.root.unwrap());
         resolve(&mut tree, &SubCommands::Evenly);
-        println!("{}", tree.to_string());
+        println!("{}", tree);
 
         assert_eq!(starting_height, tree.get_height(tree.root.unwrap()));
     }
+
+    #[test]
+    fn large_polytomy() {
+        let tips = 10_000;
+        let tree_string = format!(
+            "({});",
+            (0..tips).map(|i| format!("t{}:1", i)).collect::<Vec<String>>().join(",")
+        );
+        let mut tree = NewickImporter::read_tree(BufReader::new(tree_string.as_bytes())).unwrap();
+        resolve(&mut tree, &SubCommands::Zero);
+        assert_eq!(2 * tips - 1, tree.preorder_iter().count());
+        assert!(tree
+            .preorder_iter()
+            .all(|node| tree.is_external(node) || tree.get_num_children(node) == 2));
+    }
 }
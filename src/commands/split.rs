@@ -1,7 +1,6 @@
 use rebl::io::parser::tree_importer::TreeImporter;
 use rebl::io::writer::newick_writer::write_newick_subtree;
 use rebl::tree::mutable_tree::{MutableTree, TreeIndex};
-use std::collections::HashSet;
 use std::error::Error;
 use std::io::Write;
 
@@ -20,76 +19,92 @@ struct SubtreeSearcher {
 
 impl SubtreeSearcher {
     fn collate_subtrees(&mut self, min_size: usize) {
-        let root = self.tree.get_root().unwrap();
         self.subtrees = vec![];
-        self.get_subtrees(root, min_size, 0);
-    }
-    fn get_subtrees(&mut self, node: TreeIndex, min_size: usize, level: usize) -> usize {
-        return if self.tree.is_external(node) {
-            1
-        } else {
-            let mut tips = 0;
-            for child in self.tree.get_children(node) {
-                tips += self.get_subtrees(child, min_size, level + 1);
+        let node_count = self.tree.arena_len();
+        let mut levels = vec![0; node_count];
+        for node in self.tree.preorder_iter() {
+            if let Some(parent) = self.tree.get_parent(node) {
+                levels[node] = levels[parent] + 1;
             }
-            if tips >= min_size {
-                let subtree = Subtree {
-                    root: node,
-                    tips,
-                    level,
-                };
-                self.subtrees.push(subtree);
-                return 0;
-            } else if Some(node) == self.tree.get_root() {
-                if self.strict && tips < min_size && !self.subtrees.is_empty() {
-                    let earliest_subtree = self.subtrees.iter().fold(
-                        &Subtree {
-                            root: usize::MAX,
-                            tips: usize::MIN,
-                            level: usize::MAX,
-                        },
-                        |a, b| {
-                            if a.level < b.level {
-                                a
-                            } else if b.level < a.level {
-                                b
-                            } else if a.tips < b.tips {
-                                a
-                            } else {
-                                b
-                            }
-                        },
-                    );
-
-                    //if this is slow could make subtree mutable
-                    let new_tip_count = tips + earliest_subtree.tips;
-                    let root_subtree = Subtree {
-                        root: node,
-                        tips: tips + earliest_subtree.tips,
-                        level,
-                    };
-                    //TODO error
-                    let index = self
-                        .subtrees
-                        .iter()
-                        .position(|x| *x == *earliest_subtree)
-                        .expect("subtree not found");
-                    self.subtrees.swap_remove(index);
-                    self.subtrees.push(root_subtree);
-
-                    return new_tip_count;
-                } else {
-                    let subtree = Subtree {
+        }
+        // tips below each node that are not already in a subtree
+        let mut tip_counts = vec![0; node_count];
+        for node in self.tree.postorder_iter() {
+            tip_counts[node] = if self.tree.is_external(node) {
+                1
+            } else {
+                let tips = self
+                    .tree
+                    .get_children(node)
+                    .iter()
+                    .map(|child| tip_counts[*child])
+                    .sum();
+                Self::add_subtree(
+                    &mut self.subtrees,
+                    Subtree {
                         root: node,
                         tips,
-                        level,
-                    };
-                    self.subtrees.push(subtree);
-                    return 0;
-                }
-            }
+                        level: levels[node],
+                    },
+                    min_size,
+                    self.strict,
+                )
+            };
+        }
+    }
+
+    /// Keep the subtree if it is big enough. The root, at level 0, always makes a subtree
+    /// and in strict mode a small root takes over the earliest, smallest subtree instead. Returns the
+    /// tips left for the parent.
+    fn add_subtree(
+        subtrees: &mut Vec<Subtree>,
+        subtree: Subtree,
+        min_size: usize,
+        strict: bool,
+    ) -> usize {
+        if subtree.tips >= min_size {
+            subtrees.push(subtree);
+            0
+        } else if subtree.level == 0 && strict && !subtrees.is_empty() {
+            let earliest_subtree = subtrees.iter().fold(
+                &Subtree {
+                    root: usize::MAX,
+                    tips: usize::MIN,
+                    level: usize::MAX,
+                },
+                |a, b| {
+                    if a.level < b.level {
+                        a
+                    } else if b.level < a.level {
+                        b
+                    } else if a.tips < b.tips {
+                        a
+                    } else {
+                        b
+                    }
+                },
+            );
+
+            let root_subtree = Subtree {
+                root: subtree.root,
+                tips: subtree.tips + earliest_subtree.tips,
+                level: subtree.level,
+            };
+            //TODO error
+            let index = subtrees
+                .iter()
+                .position(|x| *x == *earliest_subtree)
+                .expect("subtree not found");
+            subtrees.swap_remove(index);
+            let tips = root_subtree.tips;
+            subtrees.push(root_subtree);
             tips
-        };
+        } else if subtree.level == 0 {
+            subtrees.push(subtree);
+            0
+        } else {
+            subtree.tips
+        }
     }
 
     fn finalize_selection(&mut self) {
@@ -103,18 +118,16 @@ impl SubtreeSearcher {
 
 pub fn run<R: std::io::Read, T: TreeImporter<R>>(
     mut trees: T,
+    handle: &mut dyn Write,
     min_clade_size: Option<usize>,
     explore: bool,
     strict: bool,
 ) -> Result<(), Box<dyn Error>> {
-    let stdout = std::io::stdout(); // get the global stdout entity
-    let mut handle = stdout.lock(); // acquire a lock on it
-
     if explore && min_clade_size.is_some() {
         warn!("Because explore is set. No trees will be written");
     }
     while trees.has_tree() {
-        let mut starting_tree = trees.read_next_tree()?;
+        let starting_tree = trees.read_next_tree()?;
         // starting_tree.calc_node_heights();
         trace!("starting to split");
         let mut searcher = SubtreeSearcher {
@@ -156,7 +169,7 @@ pub fn run<R: std::io::Read, T: TreeImporter<R>>(
             }
             // TODO making these trees is much too slow
             if !explore {
-                let mut i = 0;
+                let i = 0;
                 for subtree in searcher.subtrees {
                     debug!("writing tree: {} - {} tips", i, subtree.tips);
                     writeln!(handle, "{}", write_newick_subtree(&searcher.tree, subtree.root))?;
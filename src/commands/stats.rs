@@ -1,4 +1,6 @@
 use rebl::io::parser::tree_importer::TreeImporter;
+use rebl::tree::Tree;
+use std::collections::HashMap;
 use std::error::Error;
 use std::io::Write;
 use structopt::StructOpt;
@@ -8,75 +10,154 @@ pub enum SubCommands {
     Nodes,
 }
 
-fn general_stats<R: std::io::Read, T: TreeImporter<R>>(mut trees: T) -> Result<(), Box<dyn Error>> {
-    let stdout = std::io::stdout(); // get the global stdout entity
-    let mut handle = stdout.lock(); // acquire a lock on it
-    writeln!(handle, "nodes\ttips\trootHeight\tsumbl\tmeanbl")?;
+/// Columns written by [general_stats_row]
+pub const GENERAL_STATS_HEADER: &str = "nodes\ttips\trootHeight\tsumbl\tmeanbl";
+
+fn general_stats<R: std::io::Read, T: TreeImporter<R>>(
+    mut trees: T,
+    handle: &mut dyn Write,
+) -> Result<(), Box<dyn Error>> {
+    writeln!(handle, "{}", GENERAL_STATS_HEADER)?;
 
     while trees.has_tree() {
-        let mut tree = trees.read_next_tree()?;
-        let root = tree.get_root().unwrap();
-        let nodes = tree.get_node_count();
-        // let internal = tree.get_internal_node_count();
-        let tips = tree.get_external_node_count();
-        let mut bl = Vec::with_capacity(tree.get_node_count());
-        bl.resize(tree.get_node_count(), 0.0);
-        for node_ref in tree.preorder_iter() {
-            if node_ref != tree.get_root().expect("stats assume rooted nodes") {
-                if let Some(node) = tree.get_node(node_ref) {
-                    if let Some(length) = node.length {
-                        bl[node_ref] = length;
-                    }
-                }
-            }
-        }
-        let sum_bl = bl.iter().fold(0.0, |acc, x| acc + x);
-        let mean_bl = sum_bl / ((tree.get_node_count() as f64) - 1.0); //no branch on root
-        tree.calc_node_heights();
-        let root_height = tree.get_height(root).unwrap();
-        writeln!(
-            handle,
-            "{}\t{}\t{:.2e}\t{:.2e}\t{:.2e}",
-            nodes, tips, root_height, sum_bl, mean_bl
-        )?;
+        let tree = trees.read_next_tree()?;
+        writeln!(handle, "{}", general_stats_row(&tree))?;
     }
     Ok(())
 }
 
-fn nodes<R: std::io::Read, T: TreeImporter<R>>(mut trees: T) -> Result<(), Box<dyn Error>> {
-    let stdout = std::io::stdout(); // get the global stdout entity
-    let mut handle = stdout.lock(); // acquire a lock on it
+/// Node and tip counts, root height and the total and mean branch length as a tab
+/// separated row.
+pub fn general_stats_row<T: Tree>(tree: &T) -> String {
+    let root = tree.get_root().expect("stats assume rooted nodes");
+    let nodes = tree.get_node_count();
+    let tips = tree.get_external_node_count();
+    let sum_bl = tree
+        .preorder()
+        .into_iter()
+        .filter(|node| *node != root)
+        .filter_map(|node| tree.get_length(node))
+        .fold(0.0, |acc, x| acc + x);
+    let mean_bl = sum_bl / ((nodes as f64) - 1.0); //no branch on root
+    let root_height = tree.get_height(root).unwrap();
+    format!(
+        "{}\t{}\t{:.2e}\t{:.2e}\t{:.2e}",
+        nodes, tips, root_height, sum_bl, mean_bl
+    )
+}
+
+fn nodes<R: std::io::Read, T: TreeImporter<R>>(
+    mut trees: T,
+    handle: &mut dyn Write,
+) -> Result<(), Box<dyn Error>> {
     writeln!(handle, "tree\theight\tlength\tchildren\tsiblings\ttaxa")?;
     let mut t = 0; //TODO use id if in tree maybe every tree gets an id in parser
     while trees.has_tree() {
         let mut tree = trees.read_next_tree()?;
         tree.calc_node_heights();
-        for i in 0..tree.get_node_count() {
-            let taxa = tree.get_taxon(i).unwrap_or("");
-            let height = tree.get_height(i).expect("Heights should be calculated");
-            let mut length = f64::NAN;
-            let mut siblings = f64::NAN;
-            if let Some(p) = tree.get_parent(i) {
-                length = tree.get_height(p).expect("Heights should be calculated") - height;
-                siblings = (tree.get_num_children(p) as f64) - 1.0; // don't count me!
-            }
-            let children = tree.get_num_children(i);
-            writeln!(handle, "{}\t{}\t{}\t{}\t{}\t{}", t, height, length,children,siblings,taxa)?;
-        }
+        write_node_rows(&tree, t, handle)?;
         t += 1;
     }
 
     Ok(())
 }
 
+/// One row per node with its height, the length of its branch from the parent's height
+/// and the number of children and siblings. Heights and parents are found once for the
+/// whole tree so this is linear in the number of nodes for any tree.
+fn write_node_rows<T: Tree>(
+    tree: &T,
+    t: usize,
+    handle: &mut dyn Write,
+) -> Result<(), Box<dyn Error>> {
+    let heights: HashMap<T::Node<'_>, f64> = tree.get_heights().into_iter().collect();
+    let mut parents = HashMap::new();
+    for node in tree.preorder() {
+        for child in tree.get_children(node) {
+            parents.insert(child, node);
+        }
+    }
+    for node in tree.get_nodes() {
+        let taxa = tree.get_taxon(node).unwrap_or("");
+        let height = heights[&node];
+        let mut length = f64::NAN;
+        let mut siblings = f64::NAN;
+        if let Some(p) = parents.get(&node) {
+            length = heights[p] - height;
+            siblings = (tree.get_num_children(*p) as f64) - 1.0; // don't count me!
+        }
+        let children = tree.get_num_children(node);
+        writeln!(handle, "{}\t{}\t{}\t{}\t{}\t{}", t, height, length, children, siblings, taxa)?;
+    }
+    Ok(())
+}
+
 pub fn run<R: std::io::Read, T: TreeImporter<R>>(
     trees: T,
+    handle: &mut dyn Write,
     cmd: Option<SubCommands>,
 ) -> Result<(), Box<dyn Error>> {
     //TODO move tree reading and output buffer handling out here and pass to commands
 
     match cmd {
-        None => general_stats(trees),
-        Some(SubCommands::Nodes) => nodes(trees),
+        None => general_stats(trees, handle),
+        Some(SubCommands::Nodes) => nodes(trees, handle),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{general_stats_row, write_node_rows};
+    use rebl::io::parser::newick_importer::NewickImporter;
+    use rebl::tree::fixed_tree::FixedNode;
+    use std::io::BufReader;
+
+    fn tip(taxon: &str, length: f64) -> FixedNode {
+        let mut node = FixedNode::new();
+        node.taxon = Some(taxon.to_string());
+        node.length = Some(length);
+        node
+    }
+
+    #[test]
+    fn stats_from_either_tree() {
+        let tree =
+            NewickImporter::read_tree(BufReader::new("((A:1,B:1):1,C:2);".as_bytes())).unwrap();
+        let mut ab = FixedNode::new();
+        ab.length = Some(1.0);
+        ab.children = vec![tip("A", 1.0), tip("B", 1.0)];
+        let mut root = FixedNode::new();
+        root.children = vec![ab, tip("C", 2.0)];
+        assert_eq!("5\t3\t2.00e0\t5.00e0\t1.25e0", general_stats_row(&tree));
+        assert_eq!(general_stats_row(&tree), general_stats_row(&root));
+    }
+
+    #[test]
+    fn node_rows_from_either_tree() {
+        let mut tree =
+            NewickImporter::read_tree(BufReader::new("((A:1,B:1):1,C:2);".as_bytes())).unwrap();
+        tree.calc_node_heights();
+        let mut ab = FixedNode::new();
+        ab.length = Some(1.0);
+        ab.children = vec![tip("A", 1.0), tip("B", 1.0)];
+        let mut root = FixedNode::new();
+        root.children = vec![ab, tip("C", 2.0)];
+        let rows = |out: Vec<u8>| {
+            let mut rows = String::from_utf8(out)
+                .unwrap()
+                .lines()
+                .map(String::from)
+                .collect::<Vec<String>>();
+            rows.sort();
+            rows
+        };
+        let mut mutable = vec![];
+        write_node_rows(&tree, 0, &mut mutable).unwrap();
+        let mut fixed = vec![];
+        write_node_rows(&root, 0, &mut fixed).unwrap();
+        let fixed = rows(fixed);
+        assert_eq!(rows(mutable), fixed);
+        // the AB node is one above the tips and one below the root
+        assert!(fixed.contains(&"0\t1\t1\t2\t1\t".to_string()));
     }
 }
@@ -11,11 +11,9 @@ use std::io::Write;
 // For each node will assign an individual.
 // we will start at the tips and traverse backwards until we hit the root or a node with annotation type=E node (this will also get the individual's label)
 pub fn run<R:std::io::Read, T:TreeImporter<R>>(
-    mut trees:T
+    mut trees:T,
+    handle: &mut dyn Write,
 ) -> Result<(),Box<dyn Error>>{
-    let stdout = std::io::stdout(); // get the global stdout entity
-    let mut handle = stdout.lock(); // acquire a lock on it
-
     while trees.has_tree() {
         let mut tree = trees.read_next_tree()?;
         //TODO avoid parsing at each loop
@@ -30,6 +28,7 @@ pub fn run<R:std::io::Read, T:TreeImporter<R>>(
 
 fn annotate_nodes(tree: &mut MutableTree) -> Result<(), Box<dyn std::error::Error>> {
     // TODO: Implement the logic to annotate nodes
+    let re = regex::Regex::new(r"^E").unwrap();
     for i in 0..tree.get_external_node_count(){
         // Your logic here for each external node
         
@@ -45,7 +44,6 @@ fn annotate_nodes(tree: &mut MutableTree) -> Result<(), Box<dyn std::error::Erro
         while let Some(parent) = tree.get_parent(node){
             if let Some(node_type) = tree.get_annotation(parent, "type") {
                 let node_type_str = node_type.to_string();
-                let re = regex::Regex::new(r"^E").unwrap();
                 if re.is_match(&node_type_str) || tree.get_root()==Some(parent){
                     tree.annotate_node(parent, "id".to_string(), rebl::tree::AnnotationValue::Discrete(taxon.to_string()));
                     break;
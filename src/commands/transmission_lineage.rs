@@ -1,13 +1,13 @@
 use crate::commands::command_io;
+use crate::commands::parallel;
 use rebl::io::parser::tree_importer::TreeImporter;
-use rebl::tree::mutable_tree::MutableTree;
-use rebl::tree::AnnotationValue;
+use rebl::tree::{AnnotationValue, Tree};
 use std::collections::HashSet;
 use std::error::Error;
 use std::io::Write;
 use std::path;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 struct TransmissionLineage {
     taxa: Vec<String>,
     tmrca: f64,
@@ -19,7 +19,7 @@ struct TransmissionLineage {
 }
 
 impl TransmissionLineage {
-    fn add_taxa(&mut self, tree: &MutableTree, node: usize) {
+    fn add_taxa<'a, T: Tree>(&mut self, tree: &'a T, node: T::Node<'a>) {
         let taxa = tree.get_taxon(node).unwrap().to_string();
         self.taxa.push(taxa);
         let height = tree
@@ -35,6 +35,10 @@ impl TransmissionLineage {
     }
 }
 
+/// Heights and parents are looked up for every node visited, so trees searched should
+/// answer these in constant time as a [MutableTree](rebl::tree::mutable_tree::MutableTree)
+/// with calculated heights does.
+#[derive(Clone)]
 struct LineageFinder {
     lineages: Vec<TransmissionLineage>,
     key: String,
@@ -62,10 +66,33 @@ impl LineageFinder {
             lag,
         }
     }
-    fn clear(&mut self) {
-        self.lineages = vec![];
+    /// Find the lineages in the subtree below `node`, visiting nodes in preorder. Nodes
+    /// still to be visited are kept on a stack with the lineage their parent is in.
+    fn find_lineages<'a, T: Tree>(
+        &mut self,
+        tree: &'a T,
+        node: T::Node<'a>,
+        lineage_index: Option<usize>,
+    ) {
+        let mut stack = vec![(node, lineage_index)];
+        while let Some((node, lineage_index)) = stack.pop() {
+            let child_index = self.visit_node(tree, node, lineage_index);
+            stack.extend(
+                tree.get_children(node)
+                    .into_iter()
+                    .rev()
+                    .map(|child| (child, child_index)),
+            );
+        }
     }
-    fn find_lineages(&mut self, tree: &MutableTree, node: usize, lineage_index: Option<usize>) {
+
+    /// Add the node to a lineage or start a new one. Returns the lineage of its children.
+    fn visit_node<'a, T: Tree>(
+        &mut self,
+        tree: &'a T,
+        node: T::Node<'a>,
+        lineage_index: Option<usize>,
+    ) -> Option<usize> {
         if let Some(mut parent) = tree.get_parent(node) {
             let annotation = tree.get_annotation(node, &self.key);
 
@@ -79,6 +106,7 @@ impl LineageFinder {
                             let l = &mut self.lineages[li];
                             l.add_taxa(tree, node);
                         }
+                        None
                     } else {
                         // if it respects the lag
 
@@ -114,21 +142,13 @@ impl LineageFinder {
                             };
                             self.lineages.push(new_lineage);
                             trace!("adding new lineage due to gap in sampling");
-                            for child in tree.get_children(node) {
-                                self.find_lineages(tree, child, Some(id));
-                            }
+                            Some(id)
+                        } else if self.will_be_sampled_before_lag(tree, node, 0.0) {
+                            lineage_index
                         } else {
-                            let current_index = if self.will_be_sampled_before_lag(tree, node, 0.0)
-                            {
-                                lineage_index
-                            } else {
-                                None
-                            };
-                            for child in tree.get_children(node) {
-                                self.find_lineages(tree, child, current_index);
-                            }
+                            None
                         }
-                    };
+                    }
                 } else if tree.get_height(node).expect("nodes should have heights") >= self.cutoff
                     && self.will_be_sampled_before_lag(tree, node, 0.0)
                 {
@@ -172,20 +192,15 @@ impl LineageFinder {
                             let l = &mut self.lineages[id];
                             l.add_taxa(tree, node);
                         }
+                        None
                     } else {
-                        for child in tree.get_children(node) {
-                            self.find_lineages(tree, child, Some(id));
-                        }
+                        Some(id)
                     }
                 } else {
-                    for child in tree.get_children(node) {
-                        self.find_lineages(tree, child, None);
-                    }
+                    None
                 }
             } else {
-                for child in tree.get_children(node) {
-                    self.find_lineages(tree, child, None);
-                }
+                None
             }
         } else {
             //At the root
@@ -205,51 +220,48 @@ impl LineageFinder {
                     last_seen: tree.get_height(tree.get_root().unwrap()).unwrap(),
                 };
                 self.lineages.push(new_lineage);
-                for child in tree.get_children(node) {
-                    self.find_lineages(tree, child, Some(id));
-                }
+                Some(id)
             } else {
-                for child in tree.get_children(node) {
-                    self.find_lineages(tree, child, None);
-                }
+                None
             }
         }
     }
 
-    fn will_be_sampled_before_lag(
+    /// Whether a tip in the location can be reached from `node` through nodes in the
+    /// location within the lag. Each node is searched with the lag accumulated above it.
+    fn will_be_sampled_before_lag<'a, T: Tree>(
         &self,
-        tree: &MutableTree,
-        node: usize,
+        tree: &'a T,
+        node: T::Node<'a>,
         current_lag: f64,
     ) -> bool {
-        let mut respects = false;
-        let default_location = AnnotationValue::Discrete("unknown".parse().unwrap());
-        let node_annotation = tree
-            .get_annotation(node, &self.key)
-            .unwrap_or(&default_location);
         if self.lag == f64::INFINITY {
-            respects = true;
-        } else if node_annotation != &self.value || current_lag > self.lag {
-            respects = false;
-        } else if tree.is_external(node) {
-            respects = true;
-        } else {
-            for child in tree.get_children(node) {
+            return true;
+        }
+        let default_location = AnnotationValue::Discrete("unknown".parse().unwrap());
+        let mut stack = vec![(node, current_lag)];
+        while let Some((node, current_lag)) = stack.pop() {
+            let node_annotation = tree
+                .get_annotation(node, &self.key)
+                .unwrap_or(&default_location);
+            if node_annotation != &self.value || current_lag > self.lag {
+                continue;
+            } else if tree.is_external(node) {
+                return true;
+            }
+            for child in tree.get_children(node).into_iter().rev() {
                 let l = tree.get_length(child).unwrap() + current_lag;
-                respects = respects || self.will_be_sampled_before_lag(tree, child, l);
-                if respects {
-                    break;
-                }
+                stack.push((child, l));
             }
         }
-        respects
+        false
     }
     // helper function to be called when checking if a new tl needs to be inserted.
     // this makes assumptions
-    fn has_been_sampled_within_lag(
+    fn has_been_sampled_within_lag<'a, T: Tree>(
         &mut self,
-        tree: &MutableTree,
-        mut node: usize,
+        tree: &'a T,
+        mut node: T::Node<'a>,
         current_lag: f64,
     ) -> bool {
         let mut respects = false;
@@ -260,7 +272,7 @@ impl LineageFinder {
         let mut new_lag = current_lag;
         while current_lag < self.lag && parent.is_some() && !respects {
             let default_location = AnnotationValue::Discrete("unknown".parse().unwrap());
-            let mut parent_annotation = tree
+            let parent_annotation = tree
                 .get_annotation(parent.unwrap(), &self.key)
                 .unwrap_or(&default_location);
             if parent_annotation != &self.value {
@@ -280,8 +292,10 @@ impl LineageFinder {
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn run<R: std::io::Read, T: TreeImporter<R>>(
     mut trees: T,
+    handle: &mut dyn Write,
     ignore_taxa: Option<path::PathBuf>,
     key: String,
     value: String,
@@ -289,10 +303,9 @@ pub fn run<R: std::io::Read, T: TreeImporter<R>>(
     origin: Option<f64>,
     cutoff: Option<f64>,
     lag: Option<f64>,
+    threads: usize,
 ) -> Result<(), Box<dyn Error>> {
     let ignore = command_io::parse_taxa(ignore_taxa)?;
-    let stdout = std::io::stdout(); // get the global stdout entity
-    let mut handle = stdout.lock(); // acquire a lock on it
     if taxa_flag {
         writeln!(
             handle,
@@ -304,20 +317,17 @@ pub fn run<R: std::io::Read, T: TreeImporter<R>>(
             "tree\tlineage\tntaxa\ttmrca\tptmrca\tsource\tfirst_seen\tlast_seen"
         )?;
     }
-    let mut count = 0;
     let most_recent_intro = cutoff.unwrap_or(f64::NEG_INFINITY);
     let max_lag = lag.unwrap_or(f64::INFINITY);
 
-    let mut lineage_finder = LineageFinder::new(
+    let lineage_finder = LineageFinder::new(
         key,
         AnnotationValue::Discrete(value),
         ignore,
         most_recent_intro,
         max_lag,
     );
-    while trees.has_tree() {
-        let mut tree = trees.read_next_tree()?;
-
+    parallel::for_each_tree(&mut trees, handle, threads, |count, mut tree, out| {
         if let Some(most_recent_sample) = origin {
             tree.calc_relative_node_heights(most_recent_sample);
         } else {
@@ -325,11 +335,12 @@ pub fn run<R: std::io::Read, T: TreeImporter<R>>(
         }
         //if clades then annotate internal nodes with labels
 
+        let mut lineage_finder = lineage_finder.clone();
         lineage_finder.find_lineages(&tree, tree.get_root().unwrap(), None);
         for l in &lineage_finder.lineages {
             if taxa_flag {
                 writeln!(
-                    handle,
+                    out,
                     "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
                     count,
                     l.id,
@@ -343,7 +354,7 @@ pub fn run<R: std::io::Read, T: TreeImporter<R>>(
                 )?;
             } else {
                 writeln!(
-                    handle,
+                    out,
                     "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
                     count,
                     l.id,
@@ -356,14 +367,13 @@ pub fn run<R: std::io::Read, T: TreeImporter<R>>(
                 )?;
             }
         }
-        count += 1;
-        lineage_finder.clear()
-    }
-    Ok(())
+        Ok(())
+    })
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::commands::test_utils;
     use crate::commands::transmission_lineage::LineageFinder;
     use rebl::io::parser::newick_importer::NewickImporter;
     use rebl::tree::AnnotationValue;
@@ -448,4 +458,36 @@ mod tests {
         lf.find_lineages(&tree, tree.get_root().unwrap(), None);
         assert_eq!(2, lf.lineages.len());
     }
+
+    #[test]
+    fn find_lineages_deep_ladder() {
+        let depth = 100_000;
+        let mut tree = NewickImporter::read_tree(BufReader::new(
+            test_utils::deep_ladder(depth).as_bytes(),
+        ))
+        .expect("error in parsing");
+        let root = tree.get_root().unwrap();
+        let us = tree.get_taxon_node(&format!("t{}", depth)).unwrap();
+        for node in 0..tree.arena_len() {
+            let location = if node == root || node == us { "US" } else { "UK" };
+            tree.annotate_node(
+                node,
+                "location".to_string(),
+                AnnotationValue::Discrete(location.to_string()),
+            );
+        }
+        tree.calc_node_heights();
+        let mut lf = LineageFinder::new(
+            "location".to_string(),
+            AnnotationValue::Discrete("UK".to_string()),
+            HashSet::new(),
+            f64::NEG_INFINITY,
+            f64::INFINITY,
+        );
+
+        lf.find_lineages(&tree, tree.get_root().unwrap(), None);
+        assert_eq!(1, lf.lineages.len());
+        assert_eq!(depth, lf.lineages[0].taxa.len());
+        assert_eq!("US", lf.lineages[0].source);
+    }
 }
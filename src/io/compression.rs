@@ -0,0 +1,181 @@
+use flate2::read::MultiGzDecoder;
+use flate2::write::GzEncoder;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Result, Write};
+use std::path::Path;
+
+/// Compression of a tree file. This is determined by the file extension.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("gz") || ext.eq_ignore_ascii_case("gzip") => {
+                Compression::Gzip
+            }
+            Some(ext) if ext.eq_ignore_ascii_case("zst") || ext.eq_ignore_ascii_case("zstd") => {
+                Compression::Zstd
+            }
+            _ => Compression::None,
+        }
+    }
+}
+
+/// Open a file for reading, decompressing it on the fly if the extension is .gz or .zst
+pub fn open_reader(path: &Path) -> Result<Box<dyn Read>> {
+    let file = File::open(path)?;
+    Ok(match Compression::from_path(path) {
+        Compression::None => Box::new(file),
+        Compression::Gzip => Box::new(MultiGzDecoder::new(BufReader::new(file))),
+        Compression::Zstd => Box::new(zstd::Decoder::new(file)?),
+    })
+}
+
+/// Create a buffered file for writing, compressing it on the fly if the extension is .gz or .zst.
+/// Call [finish](FileWriter::finish) once everything is written.
+pub fn create_writer(path: &Path) -> Result<FileWriter> {
+    let file = BufWriter::new(File::create(path)?);
+    let encoder = match Compression::from_path(path) {
+        Compression::None => Encoder::Plain(file),
+        Compression::Gzip => Encoder::Gzip(GzEncoder::new(file, flate2::Compression::default())),
+        Compression::Zstd => Encoder::Zstd(zstd::Encoder::new(file, 0)?),
+    };
+    Ok(FileWriter {
+        encoder: Some(encoder),
+    })
+}
+
+/// A file opened by [create_writer]. Dropping it also finishes the compressed stream but
+/// any error in writing the end of the file is then lost.
+pub struct FileWriter {
+    encoder: Option<Encoder>,
+}
+
+enum Encoder {
+    Plain(BufWriter<File>),
+    Gzip(GzEncoder<BufWriter<File>>),
+    Zstd(zstd::Encoder<'static, BufWriter<File>>),
+}
+
+impl Encoder {
+    fn finish(self) -> Result<()> {
+        match self {
+            Encoder::Plain(mut file) => file.flush(),
+            Encoder::Gzip(encoder) => encoder.finish()?.flush(),
+            Encoder::Zstd(encoder) => encoder.finish()?.flush(),
+        }
+    }
+}
+
+impl FileWriter {
+    /// Write the end of the compressed stream and flush everything to the file
+    pub fn finish(mut self) -> Result<()> {
+        match self.encoder.take() {
+            Some(encoder) => encoder.finish(),
+            None => Ok(()),
+        }
+    }
+
+    fn encoder(&mut self) -> &mut dyn Write {
+        match self.encoder.as_mut().expect("the writer is only finished once") {
+            Encoder::Plain(file) => file,
+            Encoder::Gzip(encoder) => encoder,
+            Encoder::Zstd(encoder) => encoder,
+        }
+    }
+}
+
+impl Write for FileWriter {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.encoder().write(buf)
+    }
+    fn flush(&mut self) -> Result<()> {
+        self.encoder().flush()
+    }
+}
+
+impl Drop for FileWriter {
+    fn drop(&mut self) {
+        if let Some(encoder) = self.encoder.take() {
+            if let Err(e) = encoder.finish() {
+                warn!("could not finish writing a file: {}", e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::parser::newick_importer::NewickImporter;
+    use crate::io::parser::tree_importer::TreeImporter;
+    use std::path::PathBuf;
+
+    fn round_trip(name: &str) {
+        let path: PathBuf = std::env::temp_dir().join(format!("{}_{}", std::process::id(), name));
+        {
+            let mut writer = create_writer(&path).unwrap();
+            writeln!(writer, "((A:1,B:1):1,C:2);").unwrap();
+            writeln!(writer, "((A:1,C:1):1,B:2);").unwrap();
+            writer.finish().unwrap();
+        }
+        let mut trees = NewickImporter::from_reader(open_reader(&path).unwrap());
+        let mut count = 0;
+        while trees.has_tree() {
+            trees.read_next_tree().unwrap();
+            count += 1;
+        }
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(2, count);
+    }
+
+    #[test]
+    fn extension() {
+        assert_eq!(
+            Compression::Gzip,
+            Compression::from_path(Path::new("run1.trees.gz"))
+        );
+        assert_eq!(
+            Compression::Zstd,
+            Compression::from_path(Path::new("run1.trees.zst"))
+        );
+        assert_eq!(
+            Compression::None,
+            Compression::from_path(Path::new("run1.trees"))
+        );
+    }
+
+    #[test]
+    fn gzip() {
+        round_trip("trees.nwk.gz");
+    }
+
+    #[test]
+    fn zstd() {
+        round_trip("trees.nwk.zst");
+    }
+
+    #[test]
+    fn plain() {
+        round_trip("trees.nwk");
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn finish_reports_errors() {
+        for name in ["full.nwk", "full.nwk.gz", "full.nwk.zst"].iter() {
+            let path = std::env::temp_dir().join(format!("{}_{}", std::process::id(), name));
+            std::os::unix::fs::symlink("/dev/full", &path).unwrap();
+            let mut writer = create_writer(&path).unwrap();
+            writeln!(writer, "((A:1,B:1):1,C:2);").unwrap();
+            let finished = writer.finish();
+            std::fs::remove_file(&path).unwrap();
+            assert!(finished.is_err(), "{}", name);
+        }
+    }
+}
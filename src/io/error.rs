@@ -1,16 +1,118 @@
 use std::error::Error;
 use std::fmt;
 
+/// Where in the input a parser was when something went wrong.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Position {
+    /// 0 based index of the tree being read
+    pub tree: usize,
+    /// number of bytes read from the input up to and including the first byte of the
+    /// problem, which for a bad token is where it starts
+    pub offset: usize,
+    /// 1 based line number
+    pub line: usize,
+    /// 1 based column of the first byte of the problem
+    pub column: usize,
+}
+
+impl Default for Position {
+    fn default() -> Self {
+        Position {
+            tree: 0,
+            offset: 0,
+            line: 1,
+            column: 0,
+        }
+    }
+}
+
+impl Position {
+    pub(crate) fn advance(&mut self, byte: u8) {
+        self.offset += 1;
+        if byte == b'\n' {
+            self.line += 1;
+            self.column = 0;
+        } else {
+            self.column += 1;
+        }
+    }
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "tree {}, line {}, column {} (byte {})",
+            self.tree, self.line, self.column, self.offset
+        )
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum IoError {
     Eof,
     Format(String),
     DuplicateTaxon(String),
+    Io(String),
+    Parse {
+        position: Position,
+        expected: String,
+        found: String,
+    },
     Other,
 }
 impl Error for IoError {}
 impl fmt::Display for IoError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "need a more informative error")
+        match self {
+            IoError::Eof => write!(f, "unexpected end of input"),
+            IoError::Format(message) => write!(f, "badly formatted input: {}", message),
+            IoError::DuplicateTaxon(taxon) => write!(f, "taxon {} appears more than once", taxon),
+            IoError::Io(message) => write!(f, "could not read input: {}", message),
+            IoError::Parse {
+                position,
+                expected,
+                found,
+            } => write!(
+                f,
+                "parsing error at {}: expected {} but found '{}'",
+                position, expected, found
+            ),
+            IoError::Other => write!(f, "unknown error while reading trees"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn position() {
+        let mut position = Position::default();
+        for byte in "(a,\nb)".bytes() {
+            position.advance(byte);
+        }
+        assert_eq!(6, position.offset);
+        assert_eq!(2, position.line);
+        assert_eq!(2, position.column);
+    }
+
+    #[test]
+    fn display() {
+        let error = IoError::Parse {
+            position: Position {
+                tree: 2,
+                offset: 10,
+                line: 3,
+                column: 4,
+            },
+            expected: "a branch length".to_string(),
+            found: "x".to_string(),
+        };
+        assert_eq!(
+            "parsing error at tree 2, line 3, column 4 (byte 10): expected a branch length but found 'x'",
+            error.to_string()
+        );
     }
 }
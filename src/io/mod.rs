@@ -1,5 +1,6 @@
 //TODO better export api
 pub mod error;
 pub mod parser;
-mod serialization;
+pub mod compression;
+pub mod serialization;
 pub mod writer;
@@ -0,0 +1,370 @@
+use crate::alignment::Alignment;
+use crate::io::error::IoError;
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read};
+
+type Result<T> = std::result::Result<T, IoError>;
+
+/// Read an alignment from FASTA or nexus, detected from the first non-whitespace
+/// character: `>` for FASTA and `#` for nexus.
+pub fn read_alignment<R: Read>(mut reader: R) -> Result<Alignment> {
+    let mut text = String::new();
+    reader
+        .read_to_string(&mut text)
+        .map_err(|e| IoError::Io(e.to_string()))?;
+    match text.trim_start().chars().next() {
+        Some('>') => read_fasta(text.as_bytes()),
+        Some('#') => read_nexus_alignment(text.as_bytes()),
+        Some(c) => Err(IoError::Format(format!(
+            "alignments must be FASTA ('>') or nexus ('#NEXUS') but the input starts with '{}'",
+            c
+        ))),
+        None => Err(IoError::Eof),
+    }
+}
+
+/// Read a FASTA file. The whole header line after `>` is the taxon and whitespace in
+/// sequences is removed.
+pub fn read_fasta<R: Read>(reader: R) -> Result<Alignment> {
+    let mut alignment = Alignment::new();
+    let mut current: Option<(String, String)> = None;
+    for (i, line) in BufReader::new(reader).lines().enumerate() {
+        let line = line.map_err(|e| IoError::Io(e.to_string()))?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with(';') {
+            continue;
+        }
+        if let Some(header) = line.strip_prefix('>') {
+            if let Some((taxon, sequence)) = current.take() {
+                add(&mut alignment, taxon, sequence)?;
+            }
+            current = Some((header.trim().to_string(), String::new()));
+        } else {
+            match current.as_mut() {
+                Some((_, sequence)) => sequence.extend(line.split_whitespace()),
+                None => {
+                    return Err(IoError::Format(format!(
+                        "sequence on line {} before the first '>' header",
+                        i + 1
+                    )))
+                }
+            }
+        }
+    }
+    if let Some((taxon, sequence)) = current {
+        add(&mut alignment, taxon, sequence)?;
+    }
+    Ok(alignment)
+}
+
+/// Read the MATRIX of the first DATA or CHARACTERS block in a nexus file.
+///
+/// Sequences may be split across lines, or interleaved if the FORMAT command says so. A
+/// MATCHCHAR in the FORMAT command is replaced by the character from the first taxon.
+/// If NTAX or NCHAR are given they are checked against the matrix.
+pub fn read_nexus_alignment<R: Read>(mut reader: R) -> Result<Alignment> {
+    let mut text = String::new();
+    reader
+        .read_to_string(&mut text)
+        .map_err(|e| IoError::Io(e.to_string()))?;
+    let text = strip_comments(&text);
+    let text = text.trim_start();
+    if !text.to_ascii_uppercase().starts_with("#NEXUS") {
+        return Err(IoError::Format("nexus files must start with #NEXUS".to_string()));
+    }
+    let text = &text["#NEXUS".len()..];
+
+    let mut in_block = false;
+    let mut options: HashMap<String, String> = HashMap::new();
+    let mut interleave = false;
+    for command in text.split(';') {
+        let command = command.trim();
+        let (name, rest) = match command.find(char::is_whitespace) {
+            Some(i) => (&command[..i], command[i..].trim()),
+            None => (command, ""),
+        };
+        let name = name.to_ascii_uppercase();
+        if name == "BEGIN" {
+            let block = rest.to_ascii_uppercase();
+            in_block = block == "DATA" || block == "CHARACTERS";
+            if !in_block {
+                debug!("skipping nexus block {}", rest);
+            }
+        } else if name == "END" || name == "ENDBLOCK" {
+            in_block = false;
+        } else if in_block && (name == "DIMENSIONS" || name == "FORMAT") {
+            for (key, value) in parse_options(rest) {
+                if key == "INTERLEAVE" {
+                    interleave = !value.eq_ignore_ascii_case("no");
+                } else {
+                    options.insert(key, value);
+                }
+            }
+        } else if in_block && name == "MATRIX" {
+            let nchar = parse_count(&options, "NCHAR")?;
+            let ntax = parse_count(&options, "NTAX")?;
+            let matchchar = options.get("MATCHCHAR").and_then(|m| m.chars().next());
+            let mut alignment = read_matrix(rest, nchar.filter(|_| !interleave))?;
+            if let Some(matchchar) = matchchar {
+                replace_matchchar(&mut alignment, matchchar);
+            }
+            if let Some(ntax) = ntax {
+                if ntax != alignment.len() {
+                    return Err(IoError::Format(format!(
+                        "NTAX is {} but the matrix has {} taxa",
+                        ntax,
+                        alignment.len()
+                    )));
+                }
+            }
+            if let Some(nchar) = nchar {
+                if let Some((taxon, sequence)) = alignment.iter().find(|(_, s)| s.len() != nchar) {
+                    return Err(IoError::Format(format!(
+                        "NCHAR is {} but the sequence of {} has {} characters",
+                        nchar,
+                        taxon,
+                        sequence.len()
+                    )));
+                }
+            }
+            return Ok(alignment);
+        }
+    }
+    Err(IoError::Format(
+        "no MATRIX found in a DATA or CHARACTERS block".to_string(),
+    ))
+}
+
+fn add(alignment: &mut Alignment, taxon: String, sequence: String) -> Result<()> {
+    if alignment.contains(&taxon) {
+        return Err(IoError::DuplicateTaxon(taxon));
+    }
+    alignment.add(taxon, sequence);
+    Ok(())
+}
+
+/// Remove nexus comments, which may be nested
+fn strip_comments(text: &str) -> String {
+    let mut depth = 0;
+    let mut quote = None;
+    let mut stripped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match (c, quote) {
+            ('[', None) => depth += 1,
+            (']', None) if depth > 0 => depth -= 1,
+            _ if depth > 0 => {}
+            ('\'', None) | ('"', None) => {
+                quote = Some(c);
+                stripped.push(c);
+            }
+            (c, Some(q)) if c == q => {
+                quote = None;
+                stripped.push(c);
+            }
+            _ => stripped.push(c),
+        }
+    }
+    stripped
+}
+
+/// key=value pairs and flags from a DIMENSIONS or FORMAT command. Keys are upper case.
+fn parse_options(command: &str) -> Vec<(String, String)> {
+    let tokens = tokenize(&command.replace('=', " = "));
+    let mut options = vec![];
+    let mut i = 0;
+    while i < tokens.len() {
+        let key = tokens[i].to_ascii_uppercase();
+        if tokens.get(i + 1).map(|t| t.as_str()) == Some("=") {
+            options.push((key, tokens.get(i + 2).cloned().unwrap_or_default()));
+            i += 3;
+        } else {
+            options.push((key, String::new()));
+            i += 1;
+        }
+    }
+    options
+}
+
+fn parse_count(options: &HashMap<String, String>, key: &str) -> Result<Option<usize>> {
+    match options.get(key) {
+        Some(value) => value
+            .parse()
+            .map(Some)
+            .map_err(|_| IoError::Format(format!("{} should be an integer not {}", key, value))),
+        None => Ok(None),
+    }
+}
+
+/// With `nchar` each sequence is read until it has that many characters, so rows can be
+/// split over lines. Without it each line is a taxon followed by its sequence, and taxa
+/// that appear again are appended to as in interleaved matrices.
+fn read_matrix(matrix: &str, nchar: Option<usize>) -> Result<Alignment> {
+    let mut alignment = Alignment::new();
+    match nchar {
+        Some(nchar) => {
+            let mut current: Option<(String, String)> = None;
+            for token in tokenize(matrix) {
+                current = match current {
+                    None => Some((token, String::new())),
+                    Some((taxon, mut sequence)) => {
+                        sequence.push_str(&token);
+                        if sequence.len() >= nchar {
+                            add(&mut alignment, taxon, sequence)?;
+                            None
+                        } else {
+                            Some((taxon, sequence))
+                        }
+                    }
+                };
+            }
+            if let Some((taxon, sequence)) = current {
+                add(&mut alignment, taxon, sequence)?;
+            }
+        }
+        None => {
+            for line in matrix.lines() {
+                let mut tokens = tokenize(line).into_iter();
+                if let Some(taxon) = tokens.next() {
+                    alignment.extend(&taxon, &tokens.collect::<String>());
+                }
+            }
+        }
+    }
+    Ok(alignment)
+}
+
+fn replace_matchchar(alignment: &mut Alignment, matchchar: char) {
+    let first: Vec<char> = match alignment.iter().next() {
+        Some((_, sequence)) => sequence.chars().collect(),
+        None => return,
+    };
+    let mut replaced = Alignment::new();
+    for (taxon, sequence) in alignment.iter() {
+        let sequence = sequence
+            .chars()
+            .enumerate()
+            .map(|(i, c)| match first.get(i) {
+                Some(f) if c == matchchar => *f,
+                _ => c,
+            })
+            .collect();
+        replaced.add(taxon.to_string(), sequence);
+    }
+    *alignment = replaced;
+}
+
+/// Split on whitespace keeping quoted tokens together. Doubled quotes inside a quoted
+/// token stand for the quote itself.
+fn tokenize(text: &str) -> Vec<String> {
+    let mut tokens = vec![];
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c.is_whitespace() {
+            continue;
+        }
+        let mut token = String::new();
+        if c == '\'' || c == '"' {
+            while let Some(next) = chars.next() {
+                if next == c {
+                    if chars.peek() == Some(&c) {
+                        token.push(c);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                } else {
+                    token.push(next);
+                }
+            }
+        } else {
+            token.push(c);
+            while let Some(next) = chars.peek() {
+                if next.is_whitespace() {
+                    break;
+                }
+                token.push(*next);
+                chars.next();
+            }
+        }
+        tokens.push(token);
+    }
+    tokens
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fasta() {
+        let fasta = ">A\nACGT\nAC\n\n>B sample 2\nAC GT\n-A\n";
+        let alignment = read_alignment(fasta.as_bytes()).unwrap();
+        assert_eq!(vec!["A", "B sample 2"], alignment.taxa());
+        assert_eq!(Some("ACGTAC"), alignment.get("A"));
+        assert_eq!(Some("ACGT-A"), alignment.get("B sample 2"));
+        assert!(matches!(
+            read_fasta(">A\nAC\n>A\nGT\n".as_bytes()),
+            Err(IoError::DuplicateTaxon(_))
+        ));
+        assert!(read_fasta("ACGT\n".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn nexus_data_block() {
+        let nexus = "#NEXUS
+        BEGIN TAXA;
+            DIMENSIONS NTAX=3;
+            TAXLABELS A B 'C d';
+        END;
+        BEGIN DATA;
+            DIMENSIONS NTAX=3 NCHAR=8;
+            FORMAT DATATYPE=DNA MISSING=? GAP=- MATCHCHAR=.;
+            MATRIX
+            A ACGT
+              ACGT [split over two lines]
+            B ..-. ..?.
+            'C d' TTTTACGT
+            ;
+        END;
+        BEGIN TREES;
+            TREE t1 = ((A,B),'C d');
+        END;";
+        let alignment = read_alignment(nexus.as_bytes()).unwrap();
+        assert_eq!(vec!["A", "B", "C d"], alignment.taxa());
+        assert_eq!(Some("ACGTACGT"), alignment.get("A"));
+        assert_eq!(Some("AC-TAC?T"), alignment.get("B"));
+        assert_eq!(Some("TTTTACGT"), alignment.get("C d"));
+    }
+
+    #[test]
+    fn interleaved_characters_block() {
+        let nexus = "#NEXUS
+        BEGIN CHARACTERS;
+            DIMENSIONS NCHAR=6;
+            FORMAT DATATYPE=DNA INTERLEAVE;
+            MATRIX
+            A ACG
+            B AC-
+            A TTT
+            B TTA
+            ;
+        END;";
+        let alignment = read_nexus_alignment(nexus.as_bytes()).unwrap();
+        assert_eq!(Some("ACGTTT"), alignment.get("A"));
+        assert_eq!(Some("AC-TTA"), alignment.get("B"));
+    }
+
+    #[test]
+    fn nexus_dimension_errors() {
+        let nexus = "#NEXUS
+        BEGIN DATA;
+            DIMENSIONS NTAX=3 NCHAR=4;
+            MATRIX
+            A ACGT
+            B ACGT
+            ;
+        END;";
+        assert!(read_nexus_alignment(nexus.as_bytes()).is_err());
+        assert!(read_nexus_alignment("#NEXUS\nBEGIN TREES;\nEND;".as_bytes()).is_err());
+    }
+}
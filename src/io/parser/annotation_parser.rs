@@ -10,11 +10,15 @@ type Node<'i> = pest_consume::Node<'i, Rule, ()>;
 #[grammar = "./io/parser/tree_annotation.pest"]
 pub struct AnnotationParser;
 
+#[allow(clippy::result_large_err)]
 #[pest_consume::parser]
 impl AnnotationParser {
     fn annotation(input: Node) -> PestResult<(String, AnnotationValue)> {
         Ok(match_nodes!(input.into_children();
-            [key(k),value(v)]=>(k,v),
+            [key(k),value(v)]=>{
+                let v = interval_for_key(&k, v);
+                (k,v)
+            },
             [key(k)]=>(k,AnnotationValue::Boolean(true))
         ))
     }
@@ -37,6 +41,7 @@ impl AnnotationParser {
 
     fn node_annotation(input: Node) -> PestResult<HashMap<String, AnnotationValue>> {
         Ok(match_nodes!(input.into_children();
+            [nhx_annotation(annotation_map)]=>annotation_map,
             [annotation_set(annotations)]=>{
                 let mut annotation_map = HashMap::new();
                 for (key,value) in annotations{
@@ -47,6 +52,35 @@ impl AnnotationParser {
         ))
     }
 
+    fn nhx_annotation(input: Node) -> PestResult<HashMap<String, AnnotationValue>> {
+        Ok(match_nodes!(input.into_children();
+            [nhx_field(fields)..]=>fields.collect()
+        ))
+    }
+    fn nhx_field(input: Node) -> PestResult<(String, AnnotationValue)> {
+        Ok(match_nodes!(input.into_children();
+            [nhx_key(k),nhx_value(v)]=>(k,v),
+            [nhx_key(k)]=>(k,AnnotationValue::Discrete(String::new()))
+        ))
+    }
+    fn nhx_key(input: Node) -> PestResult<String> {
+        Ok(input.as_str().to_string())
+    }
+    /// NHX values are not quoted so numbers are told apart from text by their characters
+    fn nhx_value(input: Node) -> PestResult<AnnotationValue> {
+        let value = input.as_str();
+        let numeric = value
+            .chars()
+            .all(|c| c.is_ascii_digit() || matches!(c, '.' | '-' | '+' | 'e' | 'E'));
+        if let (true, Ok(integer)) = (numeric, value.parse::<i64>()) {
+            return Ok(AnnotationValue::Integer(integer));
+        }
+        match value.parse::<f64>() {
+            Ok(number) if numeric => Ok(AnnotationValue::Continuous(number)),
+            _ => Ok(AnnotationValue::Discrete(value.to_string())),
+        }
+    }
+
     fn key(input: Node) -> PestResult<String> {
         Ok(match_nodes!(input.into_children();
           [unquoted_key(n)]=>n,
@@ -69,30 +103,43 @@ impl AnnotationParser {
         ))
     }
     fn single_inner(input: Node) -> PestResult<String> {
-        let name = input.as_str();
-        Ok(name.to_string())
+        Ok(input.as_str().replace("''", "'"))
     }
     fn double_inner(input: Node) -> PestResult<String> {
-        let name = input.as_str();
-        Ok(name.to_string())
+        Ok(input.as_str().replace("\"\"", "\""))
     }
     fn empty_string(input: Node) -> PestResult<String> {
         Ok(String::new())
     }
     fn value(input: Node) -> PestResult<AnnotationValue> {
         Ok(match_nodes!(input.into_children();
+            [integer(n)]=>n,
             [continuous(n)]=>n,
+            [boolean(n)]=>n,
             [discrete(n)]=>n,
             [set(n)]=>n
         ))
     }
+    fn boolean(input: Node) -> PestResult<AnnotationValue> {
+        Ok(AnnotationValue::Boolean(input.as_str() == "true"))
+    }
     fn one_entry(input: Node) -> PestResult<AnnotationValue> {
         Ok(match_nodes!(input.into_children();
+            [integer(n)]=>n,
             [continuous(n)]=>n,
             [discrete(n)]=>n,
             [markovjump(n)]=>n
         ))
     }
+    fn integer(input: Node) -> PestResult<AnnotationValue> {
+        match input.as_str().parse::<i64>() {
+            Ok(i) => Ok(AnnotationValue::Integer(i)),
+            // too big for an integer
+            Err(_) => Ok(AnnotationValue::Continuous(
+                input.as_str().parse::<f64>().map_err(|e| input.error(e))?,
+            )),
+        }
+    }
     fn continuous(input: Node) -> PestResult<AnnotationValue> {
         let x = input
             .as_str()
@@ -142,6 +189,7 @@ impl AnnotationParser {
     }
 }
 
+#[allow(clippy::result_large_err)]
 impl AnnotationParser {
     pub(crate) fn parse_annotation(s: &str) -> PestResult<HashMap<String, AnnotationValue>> {
         let inputs = AnnotationParser::parse(Rule::node_annotation, s)?;
@@ -149,11 +197,42 @@ impl AnnotationParser {
         let input = inputs.single()?;
         AnnotationParser::node_annotation(input)
     }
-    pub fn parse_annotation_value(s: &str) -> PestResult<AnnotationValue> {
+    /// Parse the value of annotation `key`. See [interval_for_key] for when a pair of
+    /// numbers is an interval.
+    pub fn parse_annotation_value(key: &str, s: &str) -> PestResult<AnnotationValue> {
         let inputs = AnnotationParser::parse(Rule::value, s)?;
         // There should be a single root node in the parsed tree
         let input = inputs.single()?;
-        AnnotationParser::value(input)
+        Ok(interval_for_key(key, AnnotationParser::value(input)?))
+    }
+}
+
+/// A pair of numbers is written the same way as a set of two numbers, so it is only read
+/// as an interval when the key names one, as BEAST's `_95%_HPD` and `_range` keys do.
+pub fn interval_for_key(key: &str, value: AnnotationValue) -> AnnotationValue {
+    if !(key.ends_with("HPD") || key.ends_with("_range")) {
+        return value;
+    }
+    match &value {
+        AnnotationValue::Set(values) if values.len() == 2 => {
+            match (values[0].as_f64(), values[1].as_f64()) {
+                (Some(lower), Some(upper)) => AnnotationValue::Interval(lower, upper),
+                _ => value,
+            }
+        }
+        _ => value,
+    }
+}
+
+/// Remove the `[&R]` and `[&U]` root markers from a comment before a tree and return the
+/// rooting they give.
+pub fn take_rooting(annotation: &mut HashMap<String, AnnotationValue>) -> Option<bool> {
+    let rooted = annotation.remove("R").is_some();
+    let unrooted = annotation.remove("U").is_some();
+    match (rooted, unrooted) {
+        (_, true) => Some(false),
+        (true, false) => Some(true),
+        (false, false) => None,
     }
 }
 
@@ -250,4 +329,88 @@ mod tests {
         );
         assert_eq!(parsed, exp);
     }
+
+    #[test]
+    fn nhx() {
+        let mut exp = HashMap::new();
+        exp.insert(
+            "S".to_owned(),
+            AnnotationValue::Discrete("homo sapiens".to_owned()),
+        );
+        exp.insert("D".to_owned(), AnnotationValue::Discrete("Y".to_owned()));
+        exp.insert("B".to_owned(), AnnotationValue::Integer(95));
+        exp.insert("E".to_owned(), AnnotationValue::Discrete("".to_owned()));
+        assert_eq!(
+            AnnotationParser::parse_annotation("[&&NHX:S=homo sapiens:D=Y:B=95:E=]").unwrap(),
+            exp
+        );
+        assert_eq!(
+            HashMap::new(),
+            AnnotationParser::parse_annotation("[&&NHX]").unwrap()
+        );
+    }
+
+    #[test]
+    fn dates_are_discrete() {
+        let parsed =
+            AnnotationParser::parse_annotation("[&date=2020-01-05,n=12b,x=1.5.2,y=-3]").unwrap();
+        assert_eq!(
+            Some(&AnnotationValue::Discrete("2020-01-05".to_owned())),
+            parsed.get("date")
+        );
+        assert_eq!(Some(&AnnotationValue::Discrete("12b".to_owned())), parsed.get("n"));
+        assert_eq!(Some(&AnnotationValue::Discrete("1.5.2".to_owned())), parsed.get("x"));
+        assert_eq!(Some(&AnnotationValue::Integer(-3)), parsed.get("y"));
+    }
+
+    #[test]
+    fn two_number_sets() {
+        let parsed = AnnotationParser::parse_annotation(
+            "[&p.set={0.3,0.7},n.set={1,2},height_range={1,2}]",
+        )
+        .unwrap();
+        assert_eq!(
+            Some(&AnnotationValue::Set(vec![
+                AnnotationValue::Continuous(0.3),
+                AnnotationValue::Continuous(0.7)
+            ])),
+            parsed.get("p.set")
+        );
+        assert_eq!(
+            Some(&AnnotationValue::Set(vec![
+                AnnotationValue::Integer(1),
+                AnnotationValue::Integer(2)
+            ])),
+            parsed.get("n.set")
+        );
+        assert_eq!(
+            Some(&AnnotationValue::Interval(1.0, 2.0)),
+            parsed.get("height_range")
+        );
+    }
+
+    #[test]
+    fn integer_and_interval() {
+        let mut exp = HashMap::new();
+        exp.insert("count".to_owned(), AnnotationValue::Integer(12));
+        exp.insert("rate".to_owned(), AnnotationValue::Continuous(1e-3));
+        exp.insert(
+            "height_95%_HPD".to_owned(),
+            AnnotationValue::Interval(1.2, 3.4),
+        );
+        exp.insert(
+            "location.set".to_owned(),
+            AnnotationValue::Set(vec![
+                AnnotationValue::Discrete("UK".to_owned()),
+                AnnotationValue::Discrete("US".to_owned()),
+            ]),
+        );
+        assert_eq!(
+            AnnotationParser::parse_annotation(
+                "[&count=12,rate=1e-3,height_95%_HPD={1.2,3.4},location.set={UK,US}]"
+            )
+            .unwrap(),
+            exp
+        );
+    }
 }
@@ -0,0 +1,298 @@
+use crate::io::error::IoError;
+use crate::tree::mutable_tree::{MutableTree, TreeIndex};
+use crate::tree::AnnotationValue;
+use serde_json::Value;
+
+type Result<T> = std::result::Result<T, IoError>;
+
+/// Convert an Auspice v2 document to a tree.
+///
+/// Branch lengths are taken from the difference in `div` between a node and its parent,
+/// falling back to `num_date` when there is no divergence. If every node has a `num_date`
+/// it is used as the node height. Other node attributes become annotations and
+/// `branch_attrs` mutations are joined into a TreeTime style `mutations` annotation.
+/// The meta title is used as the tree id.
+pub fn read_auspice(document: &Value) -> Result<MutableTree> {
+    if let Some(version) = document.get("version").and_then(Value::as_str) {
+        if version != "v2" {
+            return Err(IoError::Format(format!(
+                "only auspice v2 JSON is supported. Found version {}",
+                version
+            )));
+        }
+    }
+    let root_json = document
+        .get("tree")
+        .filter(|t| t.is_object())
+        .ok_or_else(|| IoError::Format("auspice JSON without a tree object".to_string()))?;
+
+    let mut tree = MutableTree::new();
+    let mut dates = vec![];
+    let root = read_nodes(&mut tree, root_json, &mut dates);
+    tree.set_root(Some(root));
+    if dates.len() == tree.get_node_count() {
+        for (node, date) in dates.into_iter() {
+            tree.set_height(node, date);
+        }
+        tree.heights_known = true;
+    }
+    tree.branchlengths_known = true;
+
+    if let Some(title) = document.pointer("/meta/title").and_then(Value::as_str) {
+        tree.set_id(title.to_string());
+    }
+    Ok(tree)
+}
+
+/// Divergence and date of a node, used to set the lengths of its children
+struct Timing {
+    div: Option<f64>,
+    date: Option<f64>,
+}
+
+impl Timing {
+    fn of(json: &Value) -> Self {
+        let node_attrs = json.get("node_attrs");
+        Timing {
+            div: node_attrs
+                .and_then(|attrs| attrs.get("div"))
+                .and_then(Value::as_f64),
+            date: node_attrs
+                .and_then(|attrs| attrs.pointer("/num_date/value"))
+                .and_then(Value::as_f64),
+        }
+    }
+}
+
+enum Step<'a> {
+    Enter(&'a Value),
+    Exit(&'a Value),
+}
+
+/// A node whose children are still being read
+struct Open {
+    timing: Timing,
+    children: Vec<TreeIndex>,
+}
+
+/// Read the nested nodes in postorder. A node with children is made once its last child
+/// has been read, so until then its children are collected in `open`.
+fn read_nodes(
+    tree: &mut MutableTree,
+    root_json: &Value,
+    dates: &mut Vec<(TreeIndex, f64)>,
+) -> TreeIndex {
+    let mut steps = vec![Step::Enter(root_json)];
+    let mut open: Vec<Open> = vec![];
+    let mut root = None;
+    while let Some(step) = steps.pop() {
+        let (json, node, timing) = match step {
+            Step::Enter(json) => {
+                let timing = Timing::of(json);
+                match json.get("children").and_then(Value::as_array) {
+                    Some(children) if !children.is_empty() => {
+                        steps.push(Step::Exit(json));
+                        steps.extend(children.iter().rev().map(Step::Enter));
+                        open.push(Open {
+                            timing,
+                            children: Vec::with_capacity(children.len()),
+                        });
+                        continue;
+                    }
+                    _ => {
+                        let name = json.get("name").and_then(Value::as_str).unwrap_or("");
+                        let node = tree
+                            .make_external_node(name, None)
+                            .expect("Failed to make tip");
+                        (json, node, timing)
+                    }
+                }
+            }
+            Step::Exit(json) => {
+                let Open { timing, children } = open.pop().expect("entered before exit");
+                let node = tree.make_internal_node(children);
+                if let Some(name) = json.get("name").and_then(Value::as_str) {
+                    if !name.is_empty() {
+                        tree.label_node(node, name.to_string());
+                    }
+                }
+                (json, node, timing)
+            }
+        };
+        let parent = open.last().map(|parent| &parent.timing);
+        read_attributes(tree, json, node, &timing, parent, dates);
+        match open.last_mut() {
+            Some(parent) => parent.children.push(node),
+            None => root = Some(node),
+        }
+    }
+    root.expect("the root is read last")
+}
+
+fn read_attributes(
+    tree: &mut MutableTree,
+    json: &Value,
+    node: TreeIndex,
+    timing: &Timing,
+    parent: Option<&Timing>,
+    dates: &mut Vec<(TreeIndex, f64)>,
+) {
+    if let Some(parent) = parent {
+        let length = match (parent.div, timing.div, parent.date, timing.date) {
+            (Some(parent_div), Some(div), _, _) => div - parent_div,
+            (_, _, Some(parent_date), Some(date)) => date - parent_date,
+            _ => 0.0,
+        };
+        tree.set_length(node, length);
+    }
+    if let Some(date) = timing.date {
+        dates.push((node, date));
+    }
+
+    if let Some(attrs) = json.get("node_attrs").and_then(Value::as_object) {
+        for (key, value) in attrs.iter() {
+            if key == "div" || key == "num_date" {
+                continue;
+            }
+            let value = match value.get("value") {
+                Some(value) => value,
+                None => value,
+            };
+            if let Some(annotation) = annotation_value(value) {
+                tree.annotate_node(node, key.clone(), annotation);
+            }
+        }
+    }
+    if let Some(mutations) = json
+        .pointer("/branch_attrs/mutations")
+        .and_then(Value::as_object)
+    {
+        let mut joined = vec![];
+        // nucleotide mutations first and without a gene prefix
+        if let Some(nuc) = mutations.get("nuc").and_then(Value::as_array) {
+            joined.extend(nuc.iter().filter_map(Value::as_str).map(String::from));
+        }
+        for (gene, gene_mutations) in mutations.iter().filter(|(gene, _)| *gene != "nuc") {
+            if let Some(gene_mutations) = gene_mutations.as_array() {
+                joined.extend(
+                    gene_mutations
+                        .iter()
+                        .filter_map(Value::as_str)
+                        .map(|m| format!("{}:{}", gene, m)),
+                );
+            }
+        }
+        tree.annotate_node(
+            node,
+            "mutations".to_string(),
+            AnnotationValue::Discrete(joined.join(",")),
+        );
+    }
+}
+
+fn annotation_value(value: &Value) -> Option<AnnotationValue> {
+    match value {
+        Value::String(s) => Some(AnnotationValue::Discrete(s.clone())),
+        Value::Number(n) => match n.as_i64() {
+            Some(i) => Some(AnnotationValue::Integer(i)),
+            None => n.as_f64().map(AnnotationValue::Continuous),
+        },
+        Value::Bool(b) => Some(AnnotationValue::Boolean(*b)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::parser::json_importer::JsonImporter;
+    use crate::io::parser::newick_importer::NewickImporter;
+    use crate::io::parser::tree_importer::TreeImporter;
+    use crate::io::writer::auspice_writer::{write_auspice, AuspiceFormat};
+    use crate::tree::test_utils;
+    use std::io::BufReader;
+
+    const AUSPICE: &str = r#"{
+      "version": "v2",
+      "meta": {"title": "example", "panels": ["tree"]},
+      "tree": {
+        "name": "root",
+        "node_attrs": {"div": 0, "num_date": {"value": 2019.5}},
+        "children": [
+          {
+            "name": "A",
+            "node_attrs": {"div": 0.001, "num_date": {"value": 2020.5}, "country": {"value": "UK"}},
+            "branch_attrs": {"mutations": {"nuc": ["A1G"], "S": ["D614G"]}}
+          },
+          {
+            "name": "B",
+            "node_attrs": {"div": 0.002, "num_date": {"value": 2020.0}, "country": {"value": "USA"}}
+          }
+        ]
+      }
+    }"#;
+
+    #[test]
+    fn import() {
+        let mut trees = JsonImporter::from_reader(AUSPICE.as_bytes());
+        assert!(trees.has_tree());
+        let tree = trees.read_next_tree().unwrap();
+        assert!(!trees.has_tree());
+        assert_eq!(Some("example"), tree.get_id());
+        let root = tree.get_root().unwrap();
+        assert_eq!(Some("root"), tree.get_label(root));
+        assert_eq!(Some(2019.5), tree.get_height(root));
+        let a = tree.get_taxon_node("A").unwrap();
+        assert_eq!(Some(0.001), tree.get_length(a));
+        assert_eq!(Some(2020.5), tree.get_height(a));
+        assert_eq!(
+            Some(&AnnotationValue::Discrete("UK".to_string())),
+            tree.get_annotation(a, "country")
+        );
+        assert_eq!(
+            Some(&AnnotationValue::Discrete("A1G,S:D614G".to_string())),
+            tree.get_annotation(a, "mutations")
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        let s = "((A[&country=\"UK\",mutations=\"A1G,S:D614G\"]:1,B[&country=\"USA\"]:2)[&mutations=\"C7T\"]:1,C[&country=\"UK\"]:2);";
+        let tree = NewickImporter::read_tree(BufReader::new(s.as_bytes())).unwrap();
+        let mut json = vec![];
+        write_auspice(&mut json, &tree, &AuspiceFormat::default()).unwrap();
+        let json = String::from_utf8(json).unwrap();
+        let documents = format!("{}\n{}", json, json);
+        let mut trees = JsonImporter::from_reader(documents.as_bytes());
+        trees.skip_tree();
+        let read = trees.read_next_tree().unwrap();
+        for taxon in ["A", "B", "C"].iter() {
+            let node = tree.get_taxon_node(taxon).unwrap();
+            let read_node = read.get_taxon_node(taxon).unwrap();
+            assert_eq!(tree.get_length(node), read.get_length(read_node));
+            assert_eq!(
+                tree.get_node(node).unwrap().annotations,
+                read.get_node(read_node).unwrap().annotations
+            );
+        }
+    }
+
+    #[test]
+    fn deep_round_trip() {
+        let depth = 100_000;
+        let tree = NewickImporter::read_tree(BufReader::new(
+            test_utils::deep_ladder(depth).as_bytes(),
+        ))
+        .unwrap();
+        let mut json = vec![];
+        write_auspice(&mut json, &tree, &AuspiceFormat::default()).unwrap();
+        let mut trees = JsonImporter::from_reader(json.as_slice());
+        let read = trees.read_next_tree().unwrap();
+        assert_eq!(tree.get_node_count(), read.get_node_count());
+        let t0 = read.get_taxon_node("t0").unwrap();
+        assert_eq!(depth, read.ancestors(t0).count());
+        for node in read.preorder_iter().skip(1) {
+            assert_eq!(Some(1.0), read.get_length(node));
+        }
+    }
+}
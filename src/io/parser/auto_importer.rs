@@ -0,0 +1,241 @@
+use crate::io::compression;
+use crate::io::error::IoError;
+use crate::io::parser::json_importer::JsonImporter;
+use crate::io::parser::newick_importer::NewickImporter;
+use crate::io::parser::nexus_importer::NexusImporter;
+use crate::io::parser::phyloxml_importer::PhyloXmlImporter;
+use crate::io::parser::table_importer::TableImporter;
+use crate::io::parser::tree_importer::TreeImporter;
+use crate::tree::mutable_tree::MutableTree;
+use std::io::{Chain, Cursor, Read};
+use std::path::Path;
+use std::str::FromStr;
+
+type Result<T> = std::result::Result<T, IoError>;
+type PeekedReader<R> = Chain<Cursor<Vec<u8>>, R>;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TreeFormat {
+    Newick,
+    Nexus,
+    PhyloXml,
+    Json,
+    Table,
+}
+
+impl TreeFormat {
+    /// Guess the format from the first non-whitespace byte of a file.
+    pub fn detect(first_byte: Option<u8>) -> Result<Self> {
+        match first_byte {
+            // empty input is treated as a newick file with no trees
+            None | Some(b'(') | Some(b'[') => Ok(TreeFormat::Newick),
+            Some(b'#') => Ok(TreeFormat::Nexus),
+            Some(b'<') => Ok(TreeFormat::PhyloXml),
+            Some(b'{') => Ok(TreeFormat::Json),
+            // the header of a node table
+            Some(c) if c.is_ascii_alphabetic() => Ok(TreeFormat::Table),
+            Some(c) => Err(IoError::Format(format!(
+                "could not detect tree format. File starts with '{}'",
+                char::from(c)
+            ))),
+        }
+    }
+}
+
+impl FromStr for TreeFormat {
+    type Err = IoError;
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "newick" => Ok(TreeFormat::Newick),
+            "nexus" => Ok(TreeFormat::Nexus),
+            "phyloxml" => Ok(TreeFormat::PhyloXml),
+            "json" | "auspice" => Ok(TreeFormat::Json),
+            "table" | "tsv" => Ok(TreeFormat::Table),
+            _ => Err(IoError::Format(format!("unknown tree format {}", s))),
+        }
+    }
+}
+
+/// A tree importer that picks the right parser for the input by peeking at the first
+/// non-whitespace bytes. `#NEXUS` is read as nexus, `(` as newick, `<` as PhyloXML, `{`
+/// as JSON (fertree's own or Auspice) and a letter as the header of a node table.
+pub enum AutoImporter<R: Read> {
+    Newick(NewickImporter<PeekedReader<R>>),
+    Nexus(NexusImporter<PeekedReader<R>>),
+    PhyloXml(PhyloXmlImporter<PeekedReader<R>>),
+    Json(JsonImporter<PeekedReader<R>>),
+    Table(TableImporter<PeekedReader<R>>),
+}
+
+impl<R: Read> AutoImporter<R> {
+    pub fn from_reader(mut reader: R) -> Result<Self> {
+        let (peeked, first_byte) = peek(&mut reader)?;
+        let format = TreeFormat::detect(first_byte)?;
+        debug!("detected {:?} input", format);
+        AutoImporter::build(Cursor::new(peeked).chain(reader), format)
+    }
+    /// Skip detection and read the input as the provided format.
+    pub fn with_format(reader: R, format: TreeFormat) -> Result<Self> {
+        AutoImporter::build(Cursor::new(vec![]).chain(reader), format)
+    }
+
+    fn build(reader: PeekedReader<R>, format: TreeFormat) -> Result<Self> {
+        match format {
+            TreeFormat::Newick => Ok(AutoImporter::Newick(NewickImporter::from_reader(reader))),
+            TreeFormat::Nexus => Ok(AutoImporter::Nexus(NexusImporter::from_reader(reader))),
+            TreeFormat::PhyloXml => Ok(AutoImporter::PhyloXml(PhyloXmlImporter::from_reader(
+                reader,
+            ))),
+            TreeFormat::Json => Ok(AutoImporter::Json(JsonImporter::from_reader(reader))),
+            TreeFormat::Table => Ok(AutoImporter::Table(TableImporter::from_reader(reader))),
+        }
+    }
+
+    pub fn format(&self) -> TreeFormat {
+        match self {
+            AutoImporter::Newick(_) => TreeFormat::Newick,
+            AutoImporter::Nexus(_) => TreeFormat::Nexus,
+            AutoImporter::PhyloXml(_) => TreeFormat::PhyloXml,
+            AutoImporter::Json(_) => TreeFormat::Json,
+            AutoImporter::Table(_) => TreeFormat::Table,
+        }
+    }
+}
+
+impl AutoImporter<Box<dyn Read>> {
+    /// Open any tree file. Compressed files are decompressed based on their extension.
+    pub fn from_path(path: &Path) -> Result<Self> {
+        let reader = compression::open_reader(path).map_err(|e| IoError::Io(e.to_string()))?;
+        AutoImporter::from_reader(reader)
+    }
+}
+
+/// Read up to and including the first non-whitespace byte.
+fn peek<R: Read>(reader: &mut R) -> Result<(Vec<u8>, Option<u8>)> {
+    let mut peeked = vec![];
+    let mut buf: [u8; 1] = [0; 1];
+    loop {
+        match reader.read(&mut buf) {
+            Ok(0) => return Ok((peeked, None)),
+            Ok(_) => {
+                peeked.push(buf[0]);
+                if !char::from(buf[0]).is_whitespace() {
+                    return Ok((peeked, Some(buf[0])));
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(IoError::Io(e.to_string())),
+        }
+    }
+}
+
+impl<R: Read> Iterator for AutoImporter<R> {
+    type Item = MutableTree;
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            AutoImporter::Newick(importer) => importer.next(),
+            AutoImporter::Nexus(importer) => importer.next(),
+            AutoImporter::PhyloXml(importer) => importer.next(),
+            AutoImporter::Json(importer) => importer.next(),
+            AutoImporter::Table(importer) => importer.next(),
+        }
+    }
+}
+
+impl<R: Read> TreeImporter<R> for AutoImporter<R> {
+    fn has_tree(&mut self) -> bool {
+        match self {
+            AutoImporter::Newick(importer) => importer.has_tree(),
+            AutoImporter::Nexus(importer) => importer.has_tree(),
+            AutoImporter::PhyloXml(importer) => importer.has_tree(),
+            AutoImporter::Json(importer) => importer.has_tree(),
+            AutoImporter::Table(importer) => importer.has_tree(),
+        }
+    }
+    fn read_next_tree(&mut self) -> Result<MutableTree> {
+        match self {
+            AutoImporter::Newick(importer) => importer.read_next_tree(),
+            AutoImporter::Nexus(importer) => importer.read_next_tree(),
+            AutoImporter::PhyloXml(importer) => importer.read_next_tree(),
+            AutoImporter::Json(importer) => importer.read_next_tree(),
+            AutoImporter::Table(importer) => importer.read_next_tree(),
+        }
+    }
+    fn skip_tree(&mut self) {
+        match self {
+            AutoImporter::Newick(importer) => importer.skip_tree(),
+            AutoImporter::Nexus(importer) => importer.skip_tree(),
+            AutoImporter::PhyloXml(importer) => importer.skip_tree(),
+            AutoImporter::Json(importer) => importer.skip_tree(),
+            AutoImporter::Table(importer) => importer.skip_tree(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn newick() {
+        let importer = AutoImporter::from_reader("\n  ((A:1,B:1):1,C:2);".as_bytes()).unwrap();
+        assert_eq!(TreeFormat::Newick, importer.format());
+        assert_eq!(1, importer.count());
+    }
+
+    #[test]
+    fn nexus() {
+        let nexus = "  #NEXUS
+        BEGIN TREES;
+        TREE tree0 = ((A:1,B:1):1,C:2);
+        TREE tree1 = ((A:1,C:1):1,B:2);
+        END;";
+        let importer = AutoImporter::from_reader(nexus.as_bytes()).unwrap();
+        assert_eq!(TreeFormat::Nexus, importer.format());
+        assert_eq!(2, importer.count());
+    }
+
+    #[test]
+    fn phyloxml() {
+        let xml = "<?xml version=\"1.0\"?>
+        <phyloxml><phylogeny><clade><clade><name>A</name></clade><clade><name>B</name></clade></clade></phylogeny></phyloxml>";
+        let importer = AutoImporter::from_reader(xml.as_bytes()).unwrap();
+        assert_eq!(TreeFormat::PhyloXml, importer.format());
+        assert_eq!(1, importer.count());
+    }
+
+    #[test]
+    fn auspice() {
+        let json = r#"{"version": "v2", "tree": {"name": "root", "children": [{"name": "A"}, {"name": "B"}]}}"#;
+        let importer = AutoImporter::from_reader(json.as_bytes()).unwrap();
+        assert_eq!(TreeFormat::Json, importer.format());
+        assert_eq!(1, importer.count());
+    }
+
+    #[test]
+    fn override_format() {
+        let importer =
+            AutoImporter::with_format("((A:1,B:1):1,C:2);".as_bytes(), TreeFormat::Newick)
+                .unwrap();
+        assert_eq!(1, importer.count());
+    }
+
+    #[test]
+    fn table() {
+        let table = "node\tparent\nr\t\nA\tr\nB\tr\n";
+        let importer = AutoImporter::from_reader(table.as_bytes()).unwrap();
+        assert_eq!(TreeFormat::Table, importer.format());
+        assert_eq!(1, importer.count());
+    }
+
+    #[test]
+    fn from_str() {
+        assert_eq!(TreeFormat::PhyloXml, "phyloXML".parse().unwrap());
+        assert!("fasta".parse::<TreeFormat>().is_err());
+    }
+
+    #[test]
+    fn unknown() {
+        assert!(AutoImporter::from_reader("1,2,3".as_bytes()).is_err());
+    }
+}
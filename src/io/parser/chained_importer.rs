@@ -0,0 +1,304 @@
+use crate::io::compression::{self, Compression};
+use crate::io::error::IoError;
+use crate::io::parser::auto_importer::{AutoImporter, TreeFormat};
+use crate::io::parser::sampled_importer::{Burnin, SampledImporter};
+use crate::io::parser::tree_importer::TreeImporter;
+use crate::tree::mutable_tree::MutableTree;
+use std::collections::{HashSet, VecDeque};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+type Result<T> = std::result::Result<T, IoError>;
+type FileImporter = SampledImporter<Box<dyn Read>, AutoImporter<Box<dyn Read>>>;
+
+/// The file the last tree read came from. Shared so output can be labelled while the
+/// importer is owned by a command.
+pub type SourceTracker = Arc<Mutex<Option<PathBuf>>>;
+
+/// Reads trees from several files one after the other as if they were one input.
+///
+/// Files are opened when the previous one is used up. Each file's format is detected
+/// separately unless a format is given. With `prefix_ids` the tree ids are prefixed with
+/// the file stem (run1.trees.gz gives run1_STATE_0), and trees without an id are named by
+/// their index in the file. Files with the same stem get a count so their ids stay unique
+/// (d1/run.trees and d2/run.trees give run_0 and run_2_0).
+///
+/// A burn-in and thinning are applied to each file separately, as each is expected to be
+/// its own run.
+pub struct ChainedImporter {
+    paths: VecDeque<(PathBuf, String)>,
+    current: Option<FileImporter>,
+    current_path: Option<PathBuf>,
+    current_prefix: String,
+    format: Option<TreeFormat>,
+    prefix_ids: bool,
+    burnin: Burnin,
+    thin: usize,
+    source: SourceTracker,
+    pending_error: Option<IoError>,
+}
+
+impl ChainedImporter {
+    pub fn from_paths(paths: Vec<PathBuf>) -> Self {
+        let prefixes = id_prefixes(&paths);
+        ChainedImporter {
+            paths: paths.into_iter().zip(prefixes).collect(),
+            current: None,
+            current_path: None,
+            current_prefix: String::new(),
+            format: None,
+            prefix_ids: false,
+            burnin: Burnin::default(),
+            thin: 1,
+            source: Arc::new(Mutex::new(None)),
+            pending_error: None,
+        }
+    }
+
+    /// Read every file as this format instead of detecting it
+    pub fn with_format(mut self, format: TreeFormat) -> Self {
+        self.format = Some(format);
+        self
+    }
+
+    pub fn prefix_ids(mut self, prefix_ids: bool) -> Self {
+        self.prefix_ids = prefix_ids;
+        self
+    }
+
+    /// Drop the burn-in from each file and then keep every `thin`th tree.
+    pub fn sample(mut self, burnin: Burnin, thin: usize) -> Self {
+        self.burnin = burnin;
+        self.thin = thin;
+        self
+    }
+
+    fn open_importer(&self, path: &Path) -> Result<AutoImporter<Box<dyn Read>>> {
+        let reader = compression::open_reader(path)
+            .map_err(|e| IoError::Io(format!("{}: {}", path.display(), e)))?;
+        match self.format {
+            Some(format) => AutoImporter::with_format(reader, format),
+            None => AutoImporter::from_reader(reader),
+        }
+    }
+
+    fn open(&self, path: &Path) -> Result<FileImporter> {
+        let burnin = match self.burnin {
+            Burnin::Trees(n) => n,
+            Burnin::Fraction(_) => {
+                // a fraction needs the number of trees so the file is read through once first
+                let mut importer = self.open_importer(path)?;
+                let mut total = 0;
+                while importer.has_tree() {
+                    importer.skip_tree();
+                    total += 1;
+                }
+                self.burnin.trees(total)
+            }
+        };
+        Ok(SampledImporter::new(
+            self.open_importer(path)?,
+            burnin,
+            self.thin,
+        ))
+    }
+
+    /// Make sure the current importer has a tree, moving through the files if needed.
+    fn advance(&mut self) -> Result<bool> {
+        loop {
+            if let Some(importer) = self.current.as_mut() {
+                if importer.has_tree() {
+                    return Ok(true);
+                }
+            }
+            match self.paths.pop_front() {
+                Some((path, prefix)) => {
+                    debug!("reading trees from {}", path.display());
+                    self.current = Some(self.open(&path)?);
+                    self.current_path = Some(path);
+                    self.current_prefix = prefix;
+                }
+                None => {
+                    self.current = None;
+                    return Ok(false);
+                }
+            }
+        }
+    }
+}
+
+/// The file stem of each path without any compression extension. Later files with a stem
+/// that is already used get the lowest free count appended.
+fn id_prefixes(paths: &[PathBuf]) -> Vec<String> {
+    let stems: Vec<String> = paths
+        .iter()
+        .map(|path| {
+            let path = match Compression::from_path(path) {
+                Compression::None => path.as_path(),
+                _ => Path::new(path.file_stem().unwrap_or_default()),
+            };
+            path.file_stem()
+                .map(|stem| stem.to_string_lossy().to_string())
+                .unwrap_or_default()
+        })
+        .collect();
+    let mut used: HashSet<String> = HashSet::new();
+    stems
+        .iter()
+        .map(|stem| {
+            let mut prefix = stem.clone();
+            let mut count = 2;
+            while used.contains(&prefix) || (count > 2 && stems.contains(&prefix)) {
+                prefix = format!("{}_{}", stem, count);
+                count += 1;
+            }
+            used.insert(prefix.clone());
+            prefix
+        })
+        .collect()
+}
+
+impl Iterator for ChainedImporter {
+    type Item = MutableTree;
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.has_tree() {
+            let tree = self.read_next_tree();
+            match tree {
+                Ok(t) => Some(t),
+                Err(e) => {
+                    warn!("{}", e);
+                    None
+                }
+            }
+        } else {
+            None
+        }
+    }
+}
+
+impl TreeImporter<Box<dyn Read>> for ChainedImporter {
+    fn has_tree(&mut self) -> bool {
+        if self.pending_error.is_some() {
+            return true;
+        }
+        match self.advance() {
+            Ok(found) => found,
+            Err(e) => {
+                self.pending_error = Some(e);
+                true
+            }
+        }
+    }
+
+    fn read_next_tree(&mut self) -> Result<MutableTree> {
+        if let Some(e) = self.pending_error.take() {
+            return Err(e);
+        }
+        if !self.advance()? {
+            return Err(IoError::Eof);
+        }
+        let importer = self.current.as_mut().unwrap();
+        let mut tree = importer.read_next_tree()?;
+        let index = importer.last_index().unwrap_or_default();
+        if self.prefix_ids {
+            let id = match tree.get_id() {
+                Some(id) => id.to_string(),
+                None => index.to_string(),
+            };
+            tree.set_id(format!("{}_{}", self.current_prefix, id));
+        }
+        *self.source.lock().unwrap() = self.current_path.clone();
+        Ok(tree)
+    }
+
+    fn skip_tree(&mut self) {
+        if let Ok(true) = self.advance() {
+            self.current.as_mut().unwrap().skip_tree();
+        }
+    }
+
+    fn source_tracker(&self) -> Option<SourceTracker> {
+        Some(Arc::clone(&self.source))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn write_file(name: &str, contents: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("{}_{}", std::process::id(), name));
+        let mut file = compression::create_writer(&path).unwrap();
+        file.write_all(contents.as_bytes()).unwrap();
+        file.finish().unwrap();
+        path
+    }
+
+    #[test]
+    fn chain() {
+        let newick = write_file("chain1.nwk.gz", "((A:1,B:1):1,C:2);\n((A:1,C:1):1,B:2);\n");
+        let nexus = write_file(
+            "chain2.trees",
+            "#NEXUS\nBEGIN TREES;\nTREE STATE_0 = ((A:1,B:1):1,C:2);\nEND;\n",
+        );
+        let mut trees =
+            ChainedImporter::from_paths(vec![newick.clone(), nexus.clone()]).prefix_ids(true);
+        let source = trees.source_tracker().unwrap();
+        let mut ids = vec![];
+        while trees.has_tree() {
+            let tree = trees.read_next_tree().unwrap();
+            ids.push(tree.get_id().unwrap().to_string());
+        }
+        assert_eq!(Some(nexus.clone()), *source.lock().unwrap());
+        std::fs::remove_file(&newick).unwrap();
+        std::fs::remove_file(&nexus).unwrap();
+        let stem = |path: &PathBuf| {
+            path.file_name()
+                .unwrap()
+                .to_string_lossy()
+                .split('.')
+                .next()
+                .unwrap()
+                .to_string()
+        };
+        assert_eq!(
+            vec![
+                format!("{}_0", stem(&newick)),
+                format!("{}_1", stem(&newick)),
+                format!("{}_STATE_0", stem(&nexus)),
+            ],
+            ids
+        );
+    }
+
+    #[test]
+    fn unique_prefixes() {
+        let paths: Vec<PathBuf> = ["d1/run.trees", "d2/run.trees.gz", "run_2.nwk", "v1.2.trees"]
+            .iter()
+            .map(PathBuf::from)
+            .collect();
+        assert_eq!(vec!["run", "run_3", "run_2", "v1.2"], id_prefixes(&paths));
+    }
+
+    #[test]
+    fn burnin_per_file() {
+        let first = write_file("burnin1.nwk", "(A,B);(A,B,C);(A,B,C,D);(A,B,C,D,E);\n");
+        let second = write_file("burnin2.nwk", "(A,B);(A,B,C);\n");
+        let counts: Vec<usize> = ChainedImporter::from_paths(vec![first.clone(), second.clone()])
+            .sample(Burnin::Fraction(0.5), 1)
+            .map(|tree| tree.get_external_node_count())
+            .collect();
+        std::fs::remove_file(&first).unwrap();
+        std::fs::remove_file(&second).unwrap();
+        assert_eq!(vec![4, 5, 3], counts);
+    }
+
+    #[test]
+    fn missing_file() {
+        let mut trees = ChainedImporter::from_paths(vec![PathBuf::from("not/a/tree/file.nwk")]);
+        assert!(trees.has_tree());
+        assert!(trees.read_next_tree().is_err());
+    }
+}
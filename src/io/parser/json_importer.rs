@@ -0,0 +1,136 @@
+use crate::io::error::IoError;
+use crate::io::parser::auspice_importer::read_auspice;
+use crate::io::parser::tree_importer::TreeImporter;
+use crate::tree::mutable_tree::MutableTree;
+use serde::Deserialize;
+use serde_json::de::IoRead;
+use serde_json::{Deserializer, StreamDeserializer, Value};
+use std::io::Read;
+
+type Result<T> = std::result::Result<T, IoError>;
+
+/// Reads a stream of JSON documents, one tree per document.
+///
+/// Documents with a `version` and a `tree` are read as Nextstrain Auspice v2 JSON. All
+/// others are read as the fertree representation described in
+/// [serialization](crate::io::serialization).
+pub struct JsonImporter<R: Read> {
+    documents: StreamDeserializer<'static, IoRead<R>, Document>,
+    next_document: Option<std::result::Result<Document, serde_json::Error>>,
+}
+
+impl<R: Read> JsonImporter<R> {
+    pub fn from_reader(reader: R) -> Self {
+        let mut deserializer = Deserializer::from_reader(reader);
+        deserializer.disable_recursion_limit();
+        JsonImporter {
+            documents: deserializer.into_iter::<Document>(),
+            next_document: None,
+        }
+    }
+}
+
+/// A JSON document of any depth. Auspice nests each node in its parent, so the stack
+/// is grown on the heap as the document is read and the value is taken apart
+/// iteratively when it is dropped.
+struct Document(Value);
+
+impl<'de> Deserialize<'de> for Document {
+    fn deserialize<D: serde::Deserializer<'de>>(
+        deserializer: D,
+    ) -> std::result::Result<Self, D::Error> {
+        Value::deserialize(serde_stacker::Deserializer::new(deserializer)).map(Document)
+    }
+}
+
+impl Drop for Document {
+    fn drop(&mut self) {
+        let mut values = vec![self.0.take()];
+        while let Some(value) = values.pop() {
+            match value {
+                Value::Array(array) => values.extend(array),
+                Value::Object(object) => values.extend(object.into_iter().map(|(_, v)| v)),
+                _ => {}
+            }
+        }
+    }
+}
+
+fn read_document(mut document: Document) -> Result<MutableTree> {
+    if document.0.get("version").is_some() && document.0.get("tree").is_some() {
+        read_auspice(&document.0)
+    } else {
+        serde_json::from_value(document.0.take()).map_err(|e| IoError::Format(e.to_string()))
+    }
+}
+
+impl<R: Read> Iterator for JsonImporter<R> {
+    type Item = MutableTree;
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.has_tree() {
+            let tree = self.read_next_tree();
+            match tree {
+                Ok(t) => Some(t),
+                Err(e) => {
+                    warn!("{}", e);
+                    None
+                }
+            }
+        } else {
+            None
+        }
+    }
+}
+
+impl<R: Read> TreeImporter<R> for JsonImporter<R> {
+    fn has_tree(&mut self) -> bool {
+        if self.next_document.is_none() {
+            self.next_document = self.documents.next();
+        }
+        self.next_document.is_some()
+    }
+
+    fn read_next_tree(&mut self) -> Result<MutableTree> {
+        let document = match self.next_document.take().or_else(|| self.documents.next()) {
+            Some(document) => document,
+            None => return Err(IoError::Eof),
+        };
+        match document {
+            Ok(document) => read_document(document),
+            Err(e) => Err(IoError::Format(e.to_string())),
+        }
+    }
+
+    fn skip_tree(&mut self) {
+        if self.next_document.take().is_none() {
+            self.documents.next();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::parser::newick_importer::NewickImporter;
+    use crate::io::serialization::write_json;
+    use std::io::BufReader;
+
+    #[test]
+    fn fertree_json() {
+        let first = NewickImporter::read_tree(BufReader::new("((A:1,B:1):1,C:2);".as_bytes()))
+            .unwrap();
+        let second =
+            NewickImporter::read_tree(BufReader::new("((A:1,C:1):1,B:2);".as_bytes())).unwrap();
+        let json = format!("{}\n{}\n", write_json(&first), write_json(&second));
+        let trees = JsonImporter::from_reader(json.as_bytes()).collect::<Vec<MutableTree>>();
+        assert_eq!(2, trees.len());
+        assert_eq!(second.to_string(), trees[1].to_string());
+    }
+
+    #[test]
+    fn bad_json() {
+        let mut trees = JsonImporter::from_reader("{\"nodes\": 1}".as_bytes());
+        assert!(trees.has_tree());
+        assert!(trees.read_next_tree().is_err());
+    }
+}
@@ -1,4 +1,12 @@
+pub mod alignment_importer;
 pub mod annotation_parser;
+pub mod auspice_importer;
+pub mod auto_importer;
+pub mod chained_importer;
+pub mod json_importer;
 pub mod newick_importer;
 pub mod nexus_importer;
+pub mod phyloxml_importer;
+pub mod sampled_importer;
+pub mod table_importer;
 pub mod tree_importer;
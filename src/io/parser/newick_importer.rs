@@ -1,6 +1,7 @@
-use crate::io::error::IoError;
-use crate::io::parser::annotation_parser::AnnotationParser;
+use crate::io::error::{IoError, Position};
+use crate::io::parser::annotation_parser::{take_rooting, AnnotationParser};
 use crate::io::parser::tree_importer::TreeImporter;
+use crate::io::writer::newick_writer::TREE_ID_KEY;
 use crate::tree::mutable_tree::{MutableTree, TreeIndex};
 use crate::tree::AnnotationValue;
 use std::collections::HashMap;
@@ -15,6 +16,11 @@ pub struct NewickImporter<R> {
     reader: BufReader<R>,
     last_deliminator: u8,
     last_annotation: Option<HashMap<String, AnnotationValue>>,
+    position: Position,
+    /// where the token read last starts
+    token_start: Position,
+    /// an error found by has_tree, returned by the next read_next_tree
+    pending_error: Option<IoError>,
 }
 
 impl<R: std::io::Read> NewickImporter<R> {
@@ -26,6 +32,9 @@ impl<R: std::io::Read> NewickImporter<R> {
             reader: BufReader::new(reader),
             last_annotation: None,
             last_deliminator: b'\0',
+            position: Position::default(),
+            token_start: Position::default(),
+            pending_error: None,
         }
     }
     pub fn read_tree(input_reader: BufReader<R>) -> Result<MutableTree> {
@@ -36,34 +45,59 @@ impl<R: std::io::Read> NewickImporter<R> {
             reader: input_reader,
             last_annotation: None,
             last_deliminator: b'\0',
+            position: Position::default(),
+            token_start: Position::default(),
+            pending_error: None,
         };
 
         parser.read_next_tree()
     }
+    /// Read the internal node at the next '(' and everything below it. Each '(' starts a
+    /// list of children that becomes a node when its ')' is read.
     fn read_internal_node(&mut self) -> Result<TreeIndex> {
         self.read_byte()?;
         //assert =='('
-        let mut children = vec![self.read_branch()?];
-
-        // read subsequent children
-        while self.last_deliminator == b',' {
-            children.push(self.read_branch()?);
-        }
-
-        // should have had a closing ')'
-        if self.last_deliminator != b')' {
-            // throw new BadFormatException("Missing closing ')' in tree");
-            Err(IoError::Other)
-        } else {
-            let label = self.read_token(",:();")?;
-            let node = self.get_tree().make_internal_node(children);
-            if !label.is_empty() {
-                self.get_tree().label_node(node, label);
+        // the children read so far of each open node
+        let mut open: Vec<Vec<TreeIndex>> = vec![vec![]];
+        loop {
+            if self.next_byte()? == b'(' {
+                self.read_byte()?;
+                open.push(vec![]);
+                continue;
             }
-            self.annotation_node(node);
-            //TODO root branch length?
-            Ok(node)
+            let mut node = self.read_external_node()?;
+            // close the nodes that end after this one
+            loop {
+                self.read_branch_length(node)?;
+                open.last_mut().unwrap().push(node);
+                match self.last_deliminator {
+                    b',' => break,
+                    b')' => {
+                        let children = open.pop().unwrap();
+                        node = self.close_internal_node(children)?;
+                        if open.is_empty() {
+                            return Ok(node);
+                        }
+                    }
+                    c => {
+                        return Err(self.error(
+                            "',' or ')' after a node in an internal node",
+                            char::from(c).to_string(),
+                        ))
+                    }
+                }
+            }
+        }
+    }
+    /// Make the internal node after its ')' and read its label
+    fn close_internal_node(&mut self, children: Vec<TreeIndex>) -> Result<TreeIndex> {
+        let label = self.read_token(",:();")?;
+        let node = self.get_tree().make_internal_node(children);
+        if !label.is_empty() {
+            self.get_tree().label_node(node, label);
         }
+        self.annotation_node(node);
+        Ok(node)
     }
     fn read_external_node(&mut self) -> Result<TreeIndex> {
         let label = self.read_token(",:();")?;
@@ -75,18 +109,10 @@ impl<R: std::io::Read> NewickImporter<R> {
 
         Ok(node)
     }
-    fn read_branch(&mut self) -> Result<TreeIndex> {
+    fn read_branch_length(&mut self, branch: TreeIndex) -> Result<()> {
         let mut length = 0.0;
-
-        let branch = if self.next_byte()? == b'(' {
-            // is an internal node
-            self.read_internal_node()?
-        } else {
-            // is an external node
-            self.read_external_node()?
-        };
-        //TODO branch comments?
-
+        // comments between the colon and the length, or after the length, belong to the
+        // node too
         if self.last_deliminator == b':' {
             length = self.read_double(",():;")?;
             self.annotation_node(branch);
@@ -94,7 +120,7 @@ impl<R: std::io::Read> NewickImporter<R> {
 
         self.get_tree().set_length(branch, length);
 
-        Ok(branch)
+        Ok(())
     }
     fn unread_byte(&mut self, c: Byte) {
         self.last_byte = Some(c);
@@ -125,7 +151,6 @@ impl<R: std::io::Read> NewickImporter<R> {
 
     fn read_token(&mut self, deliminator: &str) -> Result<String> {
         let delims = deliminator.bytes().collect::<Vec<Byte>>();
-        let mut space = 0;
         let mut quote_char = b'\0';
 
         let mut done = false;
@@ -133,47 +158,38 @@ impl<R: std::io::Read> NewickImporter<R> {
         let mut quoted = false;
 
         self.next_byte()?;
-        let mut token = String::new();
+        self.token_start = self.position;
+        // names are read as bytes so multi-byte characters survive
+        let mut token: Vec<u8> = Vec::new();
         while !done {
-            let mut ch = self.read()?;
-            let is_space = char::from(ch).is_whitespace();
+            let ch = self.read()?;
             if quoted && ch == quote_char {
+                // a doubled quote stands for the quote itself
                 let ch2 = self.read()?;
                 if ch == ch2 {
-                    token.push(char::from(ch));
+                    token.push(ch);
                 } else {
-                    // self.last_deliminator=' ';
                     self.unread_byte(ch2);
-                    // done=true;
                     quoted = false;
                 }
+            } else if quoted {
+                token.push(ch);
             } else if first && (ch == b'\'' || ch == b'"') {
                 quoted = true;
                 quote_char = ch;
                 first = false;
-                space = 0;
             } else if ch == b'[' {
                 self.skip_comments(ch)?;
                 self.last_deliminator = b' ';
                 done = true
-            } else if quoted {
-                if is_space {
-                    space += 1;
-                    ch = b' ';
-                } else {
-                    space = 0;
-                }
-                if space < 2 {
-                    token.push(char::from(ch));
-                }
-            } else if is_space {
+            } else if char::from(ch).is_whitespace() {
                 self.last_deliminator = b' ';
                 done = true;
             } else if delims.contains(&ch) {
                 done = true;
                 self.last_deliminator = ch;
             } else {
-                token.push(char::from(ch));
+                token.push(ch);
                 first = false;
             }
         }
@@ -189,26 +205,26 @@ impl<R: std::io::Read> NewickImporter<R> {
             }
         }
 
-        Ok(token)
+        Ok(String::from_utf8(token)
+            .unwrap_or_else(|e| String::from_utf8_lossy(e.as_bytes()).into_owned()))
     }
 
     fn read_double(&mut self, deliminator: &str) -> Result<f64> {
         let s = self.read_token(deliminator)?;
-        //TODO capture this error
-
-        match s.parse() {
-            Ok(l) => Ok(l),
-            Err(e) => panic!("{}", e),
-        }
+        s.parse()
+            .map_err(|_| self.token_error("a number for the branch length", s))
     }
 
     fn read(&mut self) -> Result<Byte> {
         let mut buf: [u8; 1] = [0; 1];
         match self.last_byte {
             None => match self.reader.read(&mut buf) {
-                Ok(1) => Ok(buf[0]),
                 Ok(0) => Err(IoError::Eof),
-                _ => Err(IoError::Other),
+                Ok(_) => {
+                    self.position.advance(buf[0]);
+                    Ok(buf[0])
+                }
+                Err(e) => Err(IoError::Io(e.to_string())),
             },
             Some(c) => {
                 self.last_byte = None;
@@ -226,23 +242,34 @@ impl<R: std::io::Read> NewickImporter<R> {
         Ok(())
     }
     fn skip_comments(&mut self, c: Byte) -> Result<()> {
-        let mut comment = String::from(char::from(c));
+        let start = self.position;
+        let mut bytes = vec![c];
         let mut comment_depth = 1;
+        let mut quote = None;
         while comment_depth > 0 {
             let ch = self.read()?;
-            if ch == b'[' {
-                comment_depth += 1;
-            } else if ch == b']' {
-                comment_depth -= 1;
+            match (ch, quote) {
+                // brackets in quoted annotation values are part of the value
+                (b'\'', None) | (b'"', None) if bytes.get(1) == Some(&b'&') => quote = Some(ch),
+                (ch, Some(q)) if ch == q => quote = None,
+                (_, Some(_)) => {}
+                (b'[', None) => comment_depth += 1,
+                (b']', None) => comment_depth -= 1,
+                _ => {}
             }
-            comment.push(char::from(ch));
+            bytes.push(ch);
         }
+        let comment = String::from_utf8_lossy(&bytes);
         trace!("Comment: {}", comment);
-        if let Ok(annotation) = AnnotationParser::parse_annotation(comment.as_str()) {
-            self.last_annotation = Some(annotation);
+        if let Ok(annotation) = AnnotationParser::parse_annotation(&comment) {
+            // consecutive comments apply to the same node or tree
+            match self.last_annotation.as_mut() {
+                Some(last) => last.extend(annotation),
+                None => self.last_annotation = Some(annotation),
+            }
             Ok(())
         } else {
-            panic!("Error parsing annotation: {}", comment)
+            Err(self.error_at(start, "a comment of the form [&key=value,...]", comment))
         }
     }
 
@@ -266,6 +293,66 @@ impl<R: std::io::Read> NewickImporter<R> {
     fn get_tree(&mut self) -> &mut MutableTree {
         self.tree.as_mut().unwrap()
     }
+
+    fn error<S: Into<String>>(&self, expected: &str, found: S) -> IoError {
+        self.error_at(self.position, expected, found)
+    }
+
+    /// An error in the token read last, placed at its start
+    fn token_error<S: Into<String>>(&self, expected: &str, found: S) -> IoError {
+        self.error_at(self.token_start, expected, found)
+    }
+
+    fn error_at<S: Into<String>>(&self, position: Position, expected: &str, found: S) -> IoError {
+        IoError::Parse {
+            position,
+            expected: expected.to_string(),
+            found: found.into(),
+        }
+    }
+
+    fn parse_tree(&mut self) -> Result<MutableTree> {
+        let start = std::time::Instant::now();
+        self.tree = Some(MutableTree::new());
+        self.skip_until(b'(')?;
+        self.unread_byte(b'(');
+        // comments before the tree hold its id, annotations and rooting
+        if let Some(mut annotation) = self.last_annotation.take() {
+            if let Some(rooted) = take_rooting(&mut annotation) {
+                self.get_tree().set_rooted(rooted);
+            }
+            for (key, value) in annotation.into_iter() {
+                if key == TREE_ID_KEY {
+                    self.get_tree().set_id(value.to_string());
+                } else {
+                    self.get_tree().annotate_tree(key, value);
+                }
+            }
+        }
+
+        let root = self.read_internal_node()?;
+        if self.last_deliminator == b':' {
+            let _length = self.read_double(",():;")?;
+            warn!("Root lengths are ignored");
+            self.annotation_node(root);
+        }
+
+        // self.get_tree().set_length(branch, length);
+        //TODO hide node/node ref api
+        self.get_tree().set_root(Some(root));
+        self.get_tree().branchlengths_known = true;
+
+        match self.last_deliminator {
+            b';' => {
+                trace!(
+                    "Tree parsed in {} milli seconds ",
+                    start.elapsed().as_millis()
+                );
+                Ok(self.tree.take().unwrap())
+            }
+            c => Err(self.error("';' at the end of the tree", char::from(c).to_string())),
+        }
+    }
 }
 
 impl<R: std::io::Read> Iterator for NewickImporter<R> {
@@ -275,7 +362,10 @@ impl<R: std::io::Read> Iterator for NewickImporter<R> {
             let tree = self.read_next_tree();
             match tree {
                 Ok(node) => Some(node),
-                Err(e) => panic!("parsing error {}", e),
+                Err(e) => {
+                    warn!("{}", e);
+                    None
+                }
             }
         } else {
             None
@@ -285,55 +375,47 @@ impl<R: std::io::Read> Iterator for NewickImporter<R> {
 
 impl<R: std::io::Read> TreeImporter<R> for NewickImporter<R> {
     fn has_tree(&mut self) -> bool {
+        if self.pending_error.is_some() {
+            return true;
+        }
         match self.skip_until(b'(') {
             Ok(_byte) => {
                 self.unread_byte(b'(');
                 true
             }
             Err(IoError::Eof) => false,
-            Err(e) => panic!("parsing error: {}", e),
+            Err(e) => {
+                self.pending_error = Some(e);
+                true
+            }
         }
     }
     fn skip_tree(&mut self){
         if self.has_tree() {
-            self.skip_until(b';') ;
+            let _ = self.skip_until(b';');
+            self.last_annotation = None;
+            self.position.tree += 1;
         }
        
     }
     fn read_next_tree(&mut self) -> Result<MutableTree> {
-        let start = std::time::Instant::now();
-        self.tree = Some(MutableTree::new());
-        self.skip_until(b'(')?;
-        self.unread_byte(b'(');
-
-        let root = self.read_internal_node()?;
-        if self.last_deliminator == b':' {
-            let length = self.read_double(",():;")?;
-            warn!("Root lengths are ignored");
-        }
-
-        // self.get_tree().set_length(branch, length);
-        //TODO hide node/node ref api
-        self.get_tree().set_root(Some(root));
-        self.get_tree().branchlengths_known = true;
-
-        match self.last_deliminator {
-            b')' => Err(IoError::Other),
-            b';' => {
-                trace!(
-                    "Tree parsed in {} milli seconds ",
-                    start.elapsed().as_millis()
-                );
-                Ok(self.tree.take().unwrap())
-            }
-            _ => Err(IoError::Other),
+        if let Some(e) = self.pending_error.take() {
+            self.position.tree += 1;
+            return Err(e);
         }
+        let tree = match self.parse_tree() {
+            Err(IoError::Eof) => Err(self.error("the rest of the tree", "end of input")),
+            result => result,
+        };
+        self.position.tree += 1;
+        tree
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::tree::test_utils;
 
     #[test]
     fn general_parse() {
@@ -385,8 +467,6 @@ mod tests {
     #[test]
     fn quoted() {
         assert!(
-            true,
-            "{}",
             NewickImporter::read_tree(BufReader::new("('234] ':1,'here a *':1);".as_bytes()))
                 .is_ok()
         );
@@ -402,8 +482,8 @@ mod tests {
             "(a[&test=ok],b:[&jump={{0.1,U,me}}]1);".as_bytes(),
         ))
         .unwrap();
-        let mj = t.get_annotation(1, "jump");
-        let a = t.get_annotation(0, "test");
+        let _mj = t.get_annotation(1, "jump");
+        let _a = t.get_annotation(0, "test");
         assert!(NewickImporter::read_tree(BufReader::new(
             "(a[&test=ok],b:[&jump={{0.1,U,me}}]1);".as_bytes()
         ))
@@ -442,12 +522,170 @@ mod tests {
     #[test]
     fn should_error() {
         let out = NewickImporter::read_tree(BufReader::new("('234] ','here a *')".as_bytes()));
-        assert_eq!(true, out.is_err())
+        assert!(out.is_err())
     }
 
     #[test]
     fn should_error_again() {
         let out = NewickImporter::read_tree(BufReader::new("(a,b));".as_bytes()));
-        assert_eq!(true, out.is_err())
+        assert!(out.is_err())
+    }
+
+    #[test]
+    fn error_position() {
+        let mut trees = NewickImporter::from_reader("(a:1,b:2);\n(a:1,\nb:x);".as_bytes());
+        assert!(trees.read_next_tree().is_ok());
+        match trees.read_next_tree() {
+            Err(IoError::Parse {
+                position, found, ..
+            }) => {
+                assert_eq!(1, position.tree);
+                assert_eq!(3, position.line);
+                assert_eq!(3, position.column);
+                assert_eq!(20, position.offset);
+                assert_eq!("x", found);
+            }
+            other => panic!("expected a parse error found {:?}", other),
+        }
+    }
+
+    #[test]
+    fn error_before_first_tree() {
+        let mut trees = NewickImporter::from_reader("[not annotation] (a,b);".as_bytes());
+        assert!(trees.has_tree());
+        match trees.read_next_tree() {
+            Err(IoError::Parse { position, .. }) => {
+                assert_eq!(0, position.tree);
+                assert_eq!(1, position.line);
+                assert_eq!(1, position.column);
+            }
+            other => panic!("expected a parse error found {:?}", other),
+        }
+        assert!(trees.has_tree());
+        assert!(trees.read_next_tree().is_ok());
+    }
+
+    #[test]
+    fn unclosed_tree() {
+        let out = NewickImporter::read_tree(BufReader::new("((a,b),c".as_bytes()));
+        assert!(matches!(out, Err(IoError::Parse { .. })));
+    }
+
+    #[test]
+    fn mixed_annotation_types() {
+        let tree = NewickImporter::read_tree(BufReader::new(
+            "((A[&rate=1,h_HPD={1,2}]:1,B[&rate=0.5,h_HPD={1,2,3}]:1)[&rate=X]:1,C[&h_HPD={0.5,1}]:2);"
+                .as_bytes(),
+        ))
+        .unwrap();
+        let a = tree.get_taxon_node("A").unwrap();
+        let b = tree.get_taxon_node("B").unwrap();
+        let c = tree.get_taxon_node("C").unwrap();
+        // integers and numbers are widened to text once text is found
+        assert_eq!(
+            Some(&AnnotationValue::Discrete("1".to_string())),
+            tree.get_annotation(a, "rate")
+        );
+        assert_eq!(
+            Some(&AnnotationValue::Discrete("0.5".to_string())),
+            tree.get_annotation(b, "rate")
+        );
+        // intervals are widened to sets
+        assert_eq!(
+            Some(&AnnotationValue::Set(vec![
+                AnnotationValue::Continuous(0.5),
+                AnnotationValue::Continuous(1.0)
+            ])),
+            tree.get_annotation(c, "h_HPD")
+        );
+    }
+
+    #[test]
+    fn iso_dates() {
+        let tree = NewickImporter::read_tree(BufReader::new(
+            "[&date=2020-01-05] (A[&date=2020-01-05]:1,B[&date=2021-12-31]:1);".as_bytes(),
+        ))
+        .unwrap();
+        let a = tree.get_taxon_node("A").unwrap();
+        assert_eq!(
+            Some(&AnnotationValue::Discrete("2020-01-05".to_string())),
+            tree.get_annotation(a, "date")
+        );
+        assert_eq!(
+            Some(&AnnotationValue::Discrete("2020-01-05".to_string())),
+            tree.tree_annotation.get("date")
+        );
+    }
+
+    #[test]
+    fn integers_widen_to_continuous() {
+        let tree = NewickImporter::read_tree(BufReader::new(
+            "(A[&rate=1]:1,B[&rate=0.5]:1);".as_bytes(),
+        ))
+        .unwrap();
+        let a = tree.get_taxon_node("A").unwrap();
+        assert_eq!(
+            Some(&AnnotationValue::Continuous(1.0)),
+            tree.get_annotation(a, "rate")
+        );
+        assert_eq!(Some(1.0), tree.get_annotation(a, "rate").unwrap().as_f64());
+    }
+
+    #[test]
+    fn annotations_after_the_colon() {
+        let tree = NewickImporter::read_tree(BufReader::new(
+            "(A[&location=UK]:[&rate=0.2]0.013,B:0.5[&rate=0.1],(C:1,D:1)[&location=US]:[&rate=0.3]1):[&rate=1.0]0.1;".as_bytes(),
+        ))
+        .unwrap();
+        let a = tree.get_taxon_node("A").unwrap();
+        assert_eq!(Some(0.013), tree.get_length(a));
+        assert_eq!(
+            Some(&AnnotationValue::Discrete("UK".to_string())),
+            tree.get_annotation(a, "location")
+        );
+        assert_eq!(
+            Some(&AnnotationValue::Continuous(0.2)),
+            tree.get_annotation(a, "rate")
+        );
+        let b = tree.get_taxon_node("B").unwrap();
+        assert_eq!(
+            Some(&AnnotationValue::Continuous(0.1)),
+            tree.get_annotation(b, "rate")
+        );
+        let cd = tree.get_parent(tree.get_taxon_node("C").unwrap()).unwrap();
+        assert_eq!(Some(1.0), tree.get_length(cd));
+        assert_eq!(
+            Some(&AnnotationValue::Continuous(0.3)),
+            tree.get_annotation(cd, "rate")
+        );
+        let root = tree.get_root().unwrap();
+        assert_eq!(
+            Some(&AnnotationValue::Continuous(1.0)),
+            tree.get_annotation(root, "rate")
+        );
+    }
+
+    #[test]
+    fn root_markers() {
+        let mut trees =
+            NewickImporter::from_reader("[&R] (A:1,B:1);\n[&U](A:1,B:1);\n(A:1,B:1);".as_bytes());
+        let tree = trees.read_next_tree().unwrap();
+        assert_eq!(Some(true), tree.is_rooted());
+        assert!(tree.tree_annotation.is_empty());
+        let a = tree.get_taxon_node("A").unwrap();
+        assert!(tree.get_annotation(a, "R").is_none());
+        assert_eq!(Some(false), trees.read_next_tree().unwrap().is_rooted());
+        assert_eq!(None, trees.read_next_tree().unwrap().is_rooted());
+    }
+
+    #[test]
+    fn deep_ladder() {
+        let depth = 100_000;
+        let newick = test_utils::deep_ladder(depth);
+        let tree = NewickImporter::read_tree(BufReader::new(newick.as_bytes())).unwrap();
+        assert_eq!(depth + 1, tree.get_external_node_count());
+        let t0 = tree.get_taxon_node("t0").unwrap();
+        assert_eq!(depth, tree.ancestors(t0).count());
+        assert_eq!(newick, tree.to_string());
     }
 }
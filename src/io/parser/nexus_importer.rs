@@ -1,5 +1,5 @@
-use crate::io::error::IoError;
-use crate::io::parser::annotation_parser::AnnotationParser;
+use crate::io::error::{IoError, Position};
+use crate::io::parser::annotation_parser::{take_rooting, AnnotationParser};
 use crate::io::parser::tree_importer::TreeImporter;
 use crate::tree::mutable_tree::{MutableTree, TreeIndex};
 use crate::tree::AnnotationValue;
@@ -20,11 +20,18 @@ pub struct NexusImporter<R> {
     taxa_translation: Option<HashMap<String, String>>,
     // TODO make taxon
     reading_trees: bool,
+    position: Position,
+    /// where the token read last starts
+    token_start: Position,
+    /// an error found by has_tree, returned by the next read_next_tree
+    pending_error: Option<IoError>,
 }
 #[derive(Debug)]
 enum NexusBlock {
     Taxa,
     Trees,
+    /// any other block, such as DATA or CHARACTERS, which is skipped
+    Other(String),
 }
 
 impl<R: std::io::Read> NexusImporter<R> {
@@ -39,6 +46,9 @@ impl<R: std::io::Read> NexusImporter<R> {
             taxa_translation: None,
             last_deliminator: b'\0',
             reading_trees: false,
+            position: Position::default(),
+            token_start: Position::default(),
+            pending_error: None,
         }
     }
     #[allow(dead_code)]
@@ -53,6 +63,9 @@ impl<R: std::io::Read> NexusImporter<R> {
             taxa_translation: None,
             last_deliminator: b'\0',
             reading_trees: false,
+            position: Position::default(),
+            token_start: Position::default(),
+            pending_error: None,
         };
 
         parser.read_next_tree()
@@ -68,6 +81,13 @@ impl<R: std::io::Read> NexusImporter<R> {
                     self.reading_trees = true;
                     break;
                 }
+                Ok(NexusBlock::Other(name)) => {
+                    debug!("skipping nexus block {}", name);
+                    match self.skip_block() {
+                        Err(IoError::Eof) => break,
+                        result => result?,
+                    }
+                }
                 Err(IoError::Eof) => break,
                 Err(e) => {
                     warn!("{}", e);
@@ -90,20 +110,37 @@ impl<R: std::io::Read> NexusImporter<R> {
         } else if block.eq_ignore_ascii_case("trees") {
             Ok(NexusBlock::Trees)
         } else {
-            Err(IoError::Format(
-                "unsupported nexus block ".to_string() + &*block,
-            ))
+            Ok(NexusBlock::Other(block))
+        }
+    }
+
+    /// Skip the commands of a block up to and including its END; or ENDBLOCK;. Quoted
+    /// text and comments are passed over whole so a ; inside them does not end a command.
+    fn skip_block(&mut self) -> Result<()> {
+        let mut command: Vec<u8> = vec![];
+        let mut quote = None;
+        let mut comment_depth = 0;
+        loop {
+            let ch = self.read()?;
+            match (ch, quote) {
+                (b'[', None) => comment_depth += 1,
+                (b']', None) if comment_depth > 0 => comment_depth -= 1,
+                _ if comment_depth > 0 => {}
+                (ch, Some(q)) if ch == q => quote = None,
+                (_, Some(_)) => {}
+                (b'\'', None) | (b'"', None) => quote = Some(ch),
+                (b';', None) => {
+                    let name = String::from_utf8_lossy(&command);
+                    let name = name.trim();
+                    if name.eq_ignore_ascii_case("end") || name.eq_ignore_ascii_case("endblock") {
+                        return Ok(());
+                    }
+                    command.clear();
+                }
+                _ => command.push(ch),
+            }
         }
     }
-    // fn find_end_block(&mut self) -> Result<()> {
-    //     loop {
-    //         let token = self.read_token(";")?;
-    //         if token.eq_ignore_ascii_case("end") || token.eq_ignore_ascii_case("endblock") {
-    //             break;
-    //         }
-    //     }
-    //     Ok(())
-    // }
     fn read_taxa_block(&mut self) -> Result<()> {
         let mut taxa_count = 0;
         let token = self.read_token("")?;
@@ -111,7 +148,7 @@ impl<R: std::io::Read> NexusImporter<R> {
         if token.eq_ignore_ascii_case("DIMENSIONS") {
             let token2 = self.read_token("=;")?;
             if !token2.eq_ignore_ascii_case("NTAX") {
-                panic!("missing ntax tag")
+                return Err(self.token_error("NTAX in the DIMENSIONS command", token2));
             };
             taxa_count = self.read_int(";")?;
         };
@@ -122,7 +159,7 @@ impl<R: std::io::Read> NexusImporter<R> {
                 if !taxa.is_empty() {
                     let uniq = self.taxa.insert(taxa.to_string());
                     if !uniq {
-                        panic!("duplicated taxa: {}", &*taxa)
+                        return Err(IoError::DuplicateTaxon(taxa));
                     }
                 }
                 if self.last_deliminator == b';' {
@@ -130,12 +167,15 @@ impl<R: std::io::Read> NexusImporter<R> {
                 }
             }
             if taxa_count != self.taxa.len() {
-                panic!("taxa count does not match ntax tag")
+                return Err(self.error(
+                    &format!("{} taxa in TAXLABELS as given by NTAX", taxa_count),
+                    format!("{} taxa", self.taxa.len()),
+                ));
             };
             debug!("read taxa block with {} taxa", taxa_count);
             Ok(())
         } else {
-            panic!("Could not find taxlabels in taxa block")
+            Err(self.token_error("TAXLABELS in the TAXA block", taxalabels))
         }
     }
 
@@ -149,16 +189,15 @@ impl<R: std::io::Read> NexusImporter<R> {
             loop {
                 let key = self.read_token(",;")?;
                 if self.last_deliminator == b',' || self.last_deliminator == b';' {
-                    break Err(IoError::Format(
-                        "missing taxon label in translate section of trees block".to_string(),
+                    break Err(self.error(
+                        "a taxon label after the key in the TRANSLATE command",
+                        char::from(self.last_deliminator).to_string(),
                     ));
                 } else {
                     let taxon = self.read_token(",;")?;
                     //TODO build from Taxa block if needed
-                    if let Some(key) = taxa_map.insert(key, taxon) {
-                        break Err(IoError::Format(
-                            "translate map uses ".to_string() + &key + "twice",
-                        ));
+                    if taxa_map.insert(key.clone(), taxon).is_some() {
+                        break Err(self.token_error("unique keys in the TRANSLATE command", key));
                     }
                 }
                 if self.last_deliminator == b';' {
@@ -173,38 +212,60 @@ impl<R: std::io::Read> NexusImporter<R> {
         }
     }
 
+    /// Read the internal node at the next '(' and everything below it, as the newick
+    /// importer does.
     fn read_internal_node(&mut self) -> Result<TreeIndex> {
         self.read_byte()?;
         //assert =='('
-        let mut children = vec![self.read_branch()?];
-
-        // read subsequent children
-        while self.last_deliminator == b',' {
-            children.push(self.read_branch()?);
-        }
-
-        // should have had a closing ')'
-        if self.last_deliminator != b')' {
-            // throw new BadFormatException("Missing closing ')' in tree");
-            panic!(
-                "BadFormat should end internal node with ')' but found {}",
-                char::from(self.last_deliminator)
-            )
-        } else {
-            let label = self.read_token(",:();")?;
-            let node = self.get_tree().make_internal_node(children);
-            if !label.is_empty() {
-                self.get_tree().label_node(node, label);
+        // the children read so far of each open node
+        let mut open: Vec<Vec<TreeIndex>> = vec![vec![]];
+        loop {
+            if self.next_byte()? == b'(' {
+                self.read_byte()?;
+                open.push(vec![]);
+                continue;
             }
-            self.annotation_node(node);
-            //TODO root branch length?
-            Ok(node)
+            let mut node = self.read_external_node()?;
+            // close the nodes that end after this one
+            loop {
+                self.read_branch_length(node)?;
+                open.last_mut().unwrap().push(node);
+                match self.last_deliminator {
+                    b',' => break,
+                    b')' => {
+                        let children = open.pop().unwrap();
+                        node = self.close_internal_node(children)?;
+                        if open.is_empty() {
+                            return Ok(node);
+                        }
+                    }
+                    c => {
+                        return Err(self.error(
+                            "',' or ')' after a node in an internal node",
+                            char::from(c).to_string(),
+                        ))
+                    }
+                }
+            }
+        }
+    }
+    /// Make the internal node after its ')' and read its label
+    fn close_internal_node(&mut self, children: Vec<TreeIndex>) -> Result<TreeIndex> {
+        let label = self.read_token(",:();")?;
+        let node = self.get_tree().make_internal_node(children);
+        if !label.is_empty() {
+            self.get_tree().label_node(node, label);
         }
+        self.annotation_node(node);
+        Ok(node)
     }
     fn read_external_node(&mut self) -> Result<TreeIndex> {
         let mut label = self.read_token(",:();")?; //TODO end the nightmare of string str conversion
         if let Some(taxa_map) = &self.taxa_translation {
-            label = taxa_map.get(&*label).unwrap().to_string();
+            label = match taxa_map.get(&*label) {
+                Some(taxon) => taxon.to_string(),
+                None => return Err(self.token_error("a key from the TRANSLATE command", label)),
+            };
         }
         let node = self
             .get_tree()
@@ -216,16 +277,8 @@ impl<R: std::io::Read> NexusImporter<R> {
 
         Ok(node)
     }
-    fn read_branch(&mut self) -> Result<TreeIndex> {
+    fn read_branch_length(&mut self, branch: TreeIndex) -> Result<()> {
         let mut length = 0.0;
-
-        let branch = if self.next_byte()? == b'(' {
-            // is an internal node
-            self.read_internal_node()?
-        } else {
-            // is an external node
-            self.read_external_node()?
-        };
         trace!(
             "expect bl  for node {:?} with deliminator {}",
             branch,
@@ -239,7 +292,7 @@ impl<R: std::io::Read> NexusImporter<R> {
 
         self.get_tree().set_length(branch, length);
 
-        Ok(branch)
+        Ok(())
     }
     fn unread_byte(&mut self, c: Byte) {
         self.last_byte = Some(c);
@@ -277,9 +330,6 @@ impl<R: std::io::Read> NexusImporter<R> {
 
     fn read_token(&mut self, deliminator: &str) -> Result<String> {
         let delims = deliminator.bytes().collect::<Vec<Byte>>();
-        let mut space = 0;
-        // let mut ch = b'\0';
-        // let mut ch2 = b'\0';
         let mut quote_char = b'\0';
 
         let mut done = false;
@@ -287,47 +337,38 @@ impl<R: std::io::Read> NexusImporter<R> {
         let mut quoted = false;
 
         self.next_byte()?;
-        let mut token = String::new();
+        self.token_start = self.position;
+        // names are read as bytes so multi-byte characters survive
+        let mut token: Vec<u8> = Vec::new();
         while !done {
-            let mut ch = self.read()?;
-            let is_space = char::from(ch).is_whitespace();
+            let ch = self.read()?;
             if quoted && ch == quote_char {
+                // a doubled quote stands for the quote itself
                 let ch2 = self.read()?;
                 if ch == ch2 {
-                    token.push(char::from(ch));
+                    token.push(ch);
                 } else {
-                    // self.last_deliminator=' ';
                     self.unread_byte(ch2);
-                    // done=true;
                     quoted = false;
                 }
+            } else if quoted {
+                token.push(ch);
             } else if first && (ch == b'\'' || ch == b'"') {
                 quoted = true;
                 quote_char = ch;
                 first = false;
-                space = 0;
             } else if ch == b'[' {
                 self.skip_comments(ch)?;
                 self.last_deliminator = b' ';
                 done = true
-            } else if quoted {
-                if is_space {
-                    space += 1;
-                    ch = b' ';
-                } else {
-                    space = 0;
-                }
-                if space < 2 {
-                    token.push(char::from(ch));
-                }
-            } else if is_space {
+            } else if char::from(ch).is_whitespace() {
                 self.last_deliminator = b' ';
                 done = true;
             } else if delims.contains(&ch) {
                 done = true;
                 self.last_deliminator = ch;
             } else {
-                token.push(char::from(ch));
+                token.push(ch);
                 first = false;
             }
         }
@@ -342,36 +383,31 @@ impl<R: std::io::Read> NexusImporter<R> {
                 self.last_deliminator = self.read_byte()?;
             }
         }
-        self.last_token = token;
+        self.last_token = String::from_utf8(token)
+            .unwrap_or_else(|e| String::from_utf8_lossy(e.as_bytes()).into_owned());
         Ok(self.last_token.clone()) //TODO is this poor form
     }
 
     fn read_double(&mut self, deliminator: &str) -> Result<f64> {
         let s = self.read_token(deliminator)?;
-        //TODO capture this error
-
-        match s.parse() {
-            Ok(l) => Ok(l),
-            Err(e) => panic!("{} : {}", e, s),
-        }
+        s.parse()
+            .map_err(|_| self.token_error("a number for the branch length", s))
     }
     fn read_int(&mut self, deliminator: &str) -> Result<usize> {
         let s = self.read_token(deliminator)?;
-        //TODO capture this error
-
-        match s.parse() {
-            Ok(l) => Ok(l),
-            Err(e) => panic!("{}", e),
-        }
+        s.parse().map_err(|_| self.token_error("an integer", s))
     }
 
     fn read(&mut self) -> Result<Byte> {
         let mut buf: [u8; 1] = [0; 1];
         match self.last_byte {
             None => match self.reader.read(&mut buf) {
-                Ok(1) => Ok(buf[0]),
                 Ok(0) => Err(IoError::Eof),
-                _ => Err(IoError::Other),
+                Ok(_) => {
+                    self.position.advance(buf[0]);
+                    Ok(buf[0])
+                }
+                Err(e) => Err(IoError::Io(e.to_string())),
             },
             Some(c) => {
                 self.last_byte = None;
@@ -389,23 +425,34 @@ impl<R: std::io::Read> NexusImporter<R> {
         Ok(())
     }
     fn skip_comments(&mut self, c: Byte) -> Result<()> {
-        let mut comment = String::from(char::from(c));
+        let start = self.position;
+        let mut bytes = vec![c];
         let mut comment_depth = 1;
+        let mut quote = None;
         while comment_depth > 0 {
             let ch = self.read()?;
-            if ch == b'[' {
-                comment_depth += 1;
-            } else if ch == b']' {
-                comment_depth -= 1;
+            match (ch, quote) {
+                // brackets in quoted annotation values are part of the value
+                (b'\'', None) | (b'"', None) if bytes.get(1) == Some(&b'&') => quote = Some(ch),
+                (ch, Some(q)) if ch == q => quote = None,
+                (_, Some(_)) => {}
+                (b'[', None) => comment_depth += 1,
+                (b']', None) => comment_depth -= 1,
+                _ => {}
             }
-            comment.push(char::from(ch));
+            bytes.push(ch);
         }
+        let comment = String::from_utf8_lossy(&bytes);
         trace!("Comment: {}", comment);
-        if let Ok(annotation) = AnnotationParser::parse_annotation(comment.as_str()) {
-            self.last_annotation = Some(annotation);
+        if let Ok(annotation) = AnnotationParser::parse_annotation(&comment) {
+            // consecutive comments apply to the same node or tree
+            match self.last_annotation.as_mut() {
+                Some(last) => last.extend(annotation),
+                None => self.last_annotation = Some(annotation),
+            }
             Ok(())
         } else {
-            panic!("Error parsing annotation {}", comment)
+            Err(self.error_at(start, "a comment of the form [&key=value,...]", comment))
         }
     }
 
@@ -428,95 +475,133 @@ impl<R: std::io::Read> NexusImporter<R> {
     fn get_tree(&mut self) -> &mut MutableTree {
         self.tree.as_mut().unwrap()
     }
+
+    fn error<S: Into<String>>(&self, expected: &str, found: S) -> IoError {
+        self.error_at(self.position, expected, found)
+    }
+
+    /// An error in the token read last, placed at its start
+    fn token_error<S: Into<String>>(&self, expected: &str, found: S) -> IoError {
+        self.error_at(self.token_start, expected, found)
+    }
+
+    fn error_at<S: Into<String>>(&self, position: Position, expected: &str, found: S) -> IoError {
+        IoError::Parse {
+            position,
+            expected: expected.to_string(),
+            found: found.into(),
+        }
+    }
+
+    fn parse_tree(&mut self) -> Result<MutableTree> {
+        let start = std::time::Instant::now();
+        self.tree = Some(MutableTree::new());
+        if self.last_token.eq_ignore_ascii_case("UTREE") {
+            self.get_tree().set_rooted(false);
+        }
+        if self.last_byte == Some(b'*') {
+            // Star is used to specify a default tree - ignore it
+            self.read_byte()?;
+        }
+
+        let label = self.read_token("=;")?;
+        debug!("reading tree {}", label);
+        let tree_annotation = self.last_annotation.take();
+        // ignoring comment that may have been picked up
+        if self.last_deliminator != b'=' {
+            return Err(self.error(
+                &format!("'=' after the label of tree {}", label),
+                char::from(self.last_deliminator).to_string(),
+            ));
+        }
+
+        let next = self.next_byte()?;
+        if next != b'(' {
+            return Err(self.error(
+                "'(' to start the tree definition in the TREE command",
+                char::from(next).to_string(),
+            ));
+        }
+        let rooted_comment = self.last_annotation.take();
+        let root = self.read_internal_node()?;
+
+        if self.last_deliminator == b':' {
+            let _length = self.read_double(",():;")?;
+            warn!("Root lengths are ignored");
+            self.annotation_node(root);
+        }
+
+        self.get_tree().set_root(Some(root));
+        self.get_tree().branchlengths_known = true;
+        self.get_tree().set_id(label);
+
+        match self.last_deliminator {
+            b';' => {
+                trace!(
+                    "Tree parsed in {} milli seconds ",
+                    start.elapsed().as_millis()
+                );
+                for mut annotation in tree_annotation.into_iter().chain(rooted_comment) {
+                    if let Some(rooted) = take_rooting(&mut annotation) {
+                        self.get_tree().set_rooted(rooted);
+                    }
+                    for (key, value) in annotation.into_iter() {
+                        self.get_tree().annotate_tree(key, value);
+                    }
+                }
+                self.read_token(";")?;
+                Ok(self.tree.take().unwrap())
+            }
+            c => Err(self.error("';' at the end of the tree", char::from(c).to_string())),
+        }
+    }
 }
 
 impl<R: std::io::Read> TreeImporter<R> for NexusImporter<R> {
     fn has_tree(&mut self) -> bool {
+        if self.pending_error.is_some() {
+            return true;
+        }
         if !self.reading_trees {
-            self.prep_for_trees()
-                .expect("Parsing error prior. Never found trees");
+            if let Err(e) = self.prep_for_trees() {
+                self.pending_error = Some(e);
+                return true;
+            }
         }
         self.reading_trees && self.last_token.eq_ignore_ascii_case("TREE")
             || self.last_token.eq_ignore_ascii_case("UTREE")
     }
     fn skip_tree(&mut self) {
         if self.has_tree(){
-            self.skip_until(b';');
-            self.read_token(";");
+            let _ = self.skip_until(b';');
+            let _ = self.read_token(";");
+            self.position.tree += 1;
         }
     }
     fn read_next_tree(&mut self) -> Result<MutableTree> {
+        if let Some(e) = self.pending_error.take() {
+            return Err(e);
+        }
         if !self.reading_trees {
             self.prep_for_trees()?;
         }
         if self.last_token.eq_ignore_ascii_case("UTREE")
             || self.last_token.eq_ignore_ascii_case("TREE")
         {
-            let start = std::time::Instant::now();
-            self.tree = Some(MutableTree::new());
-            if self.last_byte == Some(b'*') {
-                // Star is used to specify a default tree - ignore it
-                self.read_byte()?;
-            }
-
-            let label = self.read_token("=;")?;
-            debug!("reading tree {}", label);
-            let tree_annotation = self.last_annotation.take();
-            // ignoring comment that may have been picked up
-            if self.last_deliminator != b'=' {
-                panic!("Missing  '=' or label for tree {}", label.as_str())
-            }
-
-            if self.next_byte()? != b'(' {
-                panic!(
-                    "{}",
-                    "Missing tree definition in TREE command of TREES block".to_string()
-                )
-            } else {
-                let rooted_comment = self.last_annotation.take();
-                let root = self.read_internal_node()?;
-
-                if self.last_deliminator == b':' {
-                    let length = self.read_double(",():;")?;
-                    warn!("Root lengths are ignored");
-                }
-
-                self.get_tree().set_root(Some(root));
-                self.get_tree().branchlengths_known = true;
-                self.get_tree().set_id(label);
-
-                match self.last_deliminator {
-                    b')' => Err(IoError::Format("Tree parsing ended with ')'".to_string())),
-                    b';' => {
-                        trace!(
-                            "Tree parsed in {} milli seconds ",
-                            start.elapsed().as_millis()
-                        );
-                        if let Some(annotation) = tree_annotation {
-                            for (key, value) in annotation.into_iter() {
-                                self.get_tree().annotate_tree(key, value);
-                            }
-                        }
-                        if let Some(annotation) = rooted_comment {
-                            for (key, value) in annotation.into_iter() {
-                                self.get_tree().annotate_tree(key, value);
-                            }
-                        }
-                        self.read_token(";")?;
-                        Ok(self.tree.take().unwrap())
-                    }
-                    _ => Err(IoError::Format(
-                        "You may need to read to check for a root branch or annotation".to_string(),
-                    )),
-                }
-            }
+            let tree = match self.parse_tree() {
+                Err(IoError::Eof) => Err(self.error("the rest of the tree", "end of input")),
+                result => result,
+            };
+            self.position.tree += 1;
+            tree
         } else if self.last_token.eq_ignore_ascii_case("ENDBLOCK")
             || self.last_token.eq_ignore_ascii_case("END")
         {
             Err(IoError::Eof)
         } else {
-            Err(IoError::Format(
-                String::from("unknown command in tree block") + &*self.last_token,
+            Err(self.token_error(
+                "TREE or END in the TREES block",
+                self.last_token.clone(),
             ))
         }
     }
@@ -533,7 +618,10 @@ impl<R: std::io::Read> Iterator for NexusImporter<R> {
             true => match self.read_next_tree() {
                 Ok(node) => Some(node),
                 Err(IoError::Eof) => None,
-                Err(e) => panic!("parsing error {}", e),
+                Err(e) => {
+                    warn!("{}", e);
+                    None
+                }
             },
         }
     }
@@ -542,6 +630,7 @@ impl<R: std::io::Read> Iterator for NexusImporter<R> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::tree::test_utils;
 
     #[test]
     fn test() {
@@ -642,6 +731,34 @@ mod tests {
             assert_eq!(vec!["tree1"], tree_ids)
     }
 
+    #[test]
+    fn skip_other_blocks() {
+        let nexus = "#NEXUS
+        BEGIN DATA;
+        DIMENSIONS NTAX=2 NCHAR=4;
+        FORMAT DATATYPE=DNA MISSING=? GAP=-;
+        MATRIX
+        [a comment; with a semicolon]
+        'Tip;0' ACGT
+        Tip1 AC-T
+        ;
+        END;
+        BEGIN CHARACTERS;
+        MATRIX end ACGT;
+        ENDBLOCK;
+        BEGIN ASSUMPTIONS;
+        END;
+        BEGIN TREES;
+        TREE tree0 = (Tip0:0.1,Tip1:0.1);
+        END;";
+        let mut trees = NexusImporter::from_reader(nexus.as_bytes());
+        assert!(trees.prep_for_trees().is_ok());
+        assert!(trees.has_tree());
+        let tree = trees.read_next_tree().unwrap();
+        assert_eq!(Some("tree0"), tree.get_id());
+        assert_eq!(2, tree.get_external_node_count());
+    }
+
     #[test]
     fn annotation() {
         let nexus = "#NEXUS
@@ -701,4 +818,109 @@ mod tests {
         }
         assert!(correct);
     }
+
+    #[test]
+    fn rooting_and_branch_comments() {
+        let nexus = "#NEXUS
+        BEGIN TREES;
+        TREE t0 = [&R] (A[&location=\"UK\"]:[&rate=0.1][&count=2]0.1,B:0.2)[&location=\"UK\"];
+        UTREE t1 = (A:0.1,B:0.2);
+        TREE t2 = (A:0.1,B:0.2);
+        END;";
+        let mut trees = NexusImporter::from_reader(nexus.as_bytes());
+        let tree = trees.read_next_tree().unwrap();
+        assert_eq!(Some(true), tree.is_rooted());
+        assert!(tree.tree_annotation.is_empty());
+        let a = tree.get_taxon_node("A").unwrap();
+        assert_eq!(
+            Some(&AnnotationValue::Discrete("UK".to_string())),
+            tree.get_annotation(a, "location")
+        );
+        assert_eq!(
+            Some(&AnnotationValue::Continuous(0.1)),
+            tree.get_annotation(a, "rate")
+        );
+        assert_eq!(
+            Some(&AnnotationValue::Integer(2)),
+            tree.get_annotation(a, "count")
+        );
+        assert_eq!(Some(false), trees.read_next_tree().unwrap().is_rooted());
+        assert_eq!(None, trees.read_next_tree().unwrap().is_rooted());
+    }
+
+    #[test]
+    fn missing_translation() {
+        let nexus = "#NEXUS
+        BEGIN TREES;
+        translate
+        0 Tip0,
+        1 Tip1
+        ;
+        TREE tree0 = (0:0.1,2:0.1);
+        END;";
+        let mut trees = NexusImporter::from_reader(nexus.as_bytes());
+        match trees.read_next_tree() {
+            Err(IoError::Parse {
+                position, found, ..
+            }) => {
+                assert_eq!(0, position.tree);
+                assert_eq!(7, position.line);
+                assert_eq!(29, position.column);
+                assert_eq!("2", found);
+            }
+            other => panic!("expected a parse error found {:?}", other),
+        }
+    }
+
+    #[test]
+    fn error_before_first_tree() {
+        let nexus = "#NEXUS
+        BEGIN TAXA;
+        DIMENSIONS TAXA=2;
+        END;
+        BEGIN TREES;
+        TREE tree0 = (Tip0:0.1,Tip1:0.1);
+        END;";
+        let mut trees = NexusImporter::from_reader(nexus.as_bytes());
+        assert!(trees.has_tree());
+        match trees.read_next_tree() {
+            Err(IoError::Parse {
+                position, found, ..
+            }) => {
+                assert_eq!(0, position.tree);
+                assert_eq!(3, position.line);
+                assert_eq!(20, position.column);
+                assert_eq!("TAXA", found);
+            }
+            other => panic!("expected a parse error found {:?}", other),
+        }
+    }
+
+    #[test]
+    fn duplicate_taxon() {
+        let nexus = "#NEXUS
+        BEGIN TAXA;
+        DIMENSIONS NTAX=2;
+        TAXLABELS Tip0 Tip0;
+        END;";
+        let mut trees = NexusImporter::from_reader(nexus.as_bytes());
+        assert!(matches!(
+            trees.prep_for_trees(),
+            Err(IoError::DuplicateTaxon(_))
+        ));
+    }
+
+    #[test]
+    fn deep_ladder() {
+        let depth = 100_000;
+        let nexus = format!(
+            "#NEXUS\nBEGIN TREES;\nTREE deep = {}\nEND;",
+            test_utils::deep_ladder(depth)
+        );
+        let mut trees = NexusImporter::from_reader(nexus.as_bytes());
+        let tree = trees.read_next_tree().unwrap();
+        assert_eq!(depth + 1, tree.get_external_node_count());
+        let t0 = tree.get_taxon_node("t0").unwrap();
+        assert_eq!(depth, tree.ancestors(t0).count());
+    }
 }
@@ -0,0 +1,458 @@
+use crate::io::error::IoError;
+use crate::io::parser::annotation_parser::AnnotationParser;
+use crate::io::parser::tree_importer::TreeImporter;
+use crate::tree::mutable_tree::{MutableTree, TreeIndex};
+use crate::tree::AnnotationValue;
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use std::collections::HashMap;
+use std::io::{BufReader, Read};
+
+type Result<T> = std::result::Result<T, IoError>;
+
+/// Reads the `<phylogeny>` elements of a PhyloXML document one at a time.
+///
+/// Clade names become taxa on tips and labels on internal nodes. Tips without a name fall
+/// back to their taxonomy's scientific name or code. `<confidence>` values are stored as
+/// continuous annotations keyed by their type and `<property>` values as annotations keyed
+/// by their ref with the namespace prefix removed. Properties and confidences on the
+/// phylogeny itself are stored as tree annotations.
+pub struct PhyloXmlImporter<R> {
+    reader: Reader<BufReader<R>>,
+    buf: Vec<u8>,
+    /// attributes of a `<phylogeny>` start tag found by has_tree but not yet parsed
+    next_phylogeny: Option<HashMap<String, String>>,
+    /// an error hit while looking for the next tree. Returned by the next read.
+    pending_error: Option<IoError>,
+    tree_index: usize,
+}
+
+/// An element whose end tag has not been read yet
+struct Element {
+    name: String,
+    attributes: HashMap<String, String>,
+    text: String,
+}
+
+#[derive(Default)]
+struct PendingClade {
+    name: Option<String>,
+    taxonomy_name: Option<String>,
+    length: Option<f64>,
+    annotations: Vec<(String, AnnotationValue)>,
+    children: Vec<TreeIndex>,
+}
+
+impl<R: Read> PhyloXmlImporter<R> {
+    pub fn from_reader(reader: R) -> Self {
+        let mut reader = Reader::from_reader(BufReader::new(reader));
+        reader.trim_text(true);
+        PhyloXmlImporter {
+            reader,
+            buf: vec![],
+            next_phylogeny: None,
+            pending_error: None,
+            tree_index: 0,
+        }
+    }
+
+    fn error<S: Into<String>>(&self, message: S) -> IoError {
+        IoError::Format(format!(
+            "{} in tree {} (byte {})",
+            message.into(),
+            self.tree_index,
+            self.reader.buffer_position()
+        ))
+    }
+
+    /// Move through the document until the start of the next phylogeny.
+    fn seek_phylogeny(&mut self) -> Result<bool> {
+        loop {
+            self.buf.clear();
+            match self.reader.read_event_into(&mut self.buf) {
+                Ok(Event::Start(e)) if e.local_name().as_ref() == b"phylogeny" => {
+                    let attributes = read_attributes(&e)?;
+                    self.next_phylogeny = Some(attributes);
+                    return Ok(true);
+                }
+                Ok(Event::Eof) => return Ok(false),
+                Ok(_) => {}
+                Err(e) => return Err(self.error(e.to_string())),
+            }
+        }
+    }
+
+    fn parse_tree(&mut self, attributes: HashMap<String, String>) -> Result<MutableTree> {
+        let mut tree = MutableTree::new();
+        match attributes.get("rooted").map(String::as_str) {
+            Some("true") => tree.set_rooted(true),
+            Some("false") => tree.set_rooted(false),
+            _ => {}
+        }
+
+        let mut elements: Vec<Element> = vec![];
+        let mut clades: Vec<PendingClade> = vec![];
+        let mut root = None;
+        loop {
+            self.buf.clear();
+            let event = self
+                .reader
+                .read_event_into(&mut self.buf)
+                .map_err(|e| e.to_string());
+            // empty elements are handled as a start tag immediately followed by an end tag
+            let (start, end) = match event {
+                Ok(Event::Start(e)) => (Some(read_element(&e)?), false),
+                Ok(Event::Empty(e)) => (Some(read_element(&e)?), true),
+                Ok(Event::End(_)) => (None, true),
+                Ok(Event::Text(e)) => {
+                    let text = e.unescape().map_err(|e| e.to_string());
+                    match (text, elements.last_mut()) {
+                        (Ok(text), Some(element)) => element.text.push_str(&text),
+                        (Ok(_), None) => {}
+                        (Err(message), _) => return Err(self.error(message)),
+                    }
+                    (None, false)
+                }
+                Ok(Event::CData(e)) => {
+                    if let Some(element) = elements.last_mut() {
+                        element.text.push_str(&String::from_utf8_lossy(&e));
+                    }
+                    (None, false)
+                }
+                Ok(Event::Eof) => return Err(IoError::Eof),
+                Ok(_) => (None, false),
+                Err(message) => return Err(self.error(message)),
+            };
+
+            if let Some(element) = start {
+                if element.name == "clade" {
+                    let mut clade = PendingClade::default();
+                    if let Some(length) = element.attributes.get("branch_length") {
+                        clade.length = Some(self.parse_number(length, "a branch length")?);
+                    }
+                    clades.push(clade);
+                }
+                elements.push(element);
+            }
+            if !end {
+                continue;
+            }
+
+            let element = match elements.pop() {
+                Some(element) => element,
+                // the end of the phylogeny
+                None => break,
+            };
+            let parent = elements.last().map(|e| e.name.as_str());
+            match (element.name.as_str(), parent) {
+                ("clade", _) => {
+                    let clade = clades.pop().expect("clade stack out of sync with elements");
+                    let node = make_node(&mut tree, clade, parent.is_none());
+                    match clades.last_mut() {
+                        Some(parent_clade) if parent == Some("clade") => {
+                            parent_clade.children.push(node)
+                        }
+                        _ => {
+                            if root.is_some() {
+                                return Err(self.error("more than one root clade"));
+                            }
+                            root = Some(node);
+                        }
+                    }
+                }
+                ("name", Some("clade")) => {
+                    clades.last_mut().unwrap().name = Some(element.text);
+                }
+                ("name", None) => tree.set_id(element.text),
+                ("branch_length", Some("clade")) => {
+                    let length = self.parse_number(&element.text, "a branch length")?;
+                    clades.last_mut().unwrap().length = Some(length);
+                }
+                ("scientific_name", Some("taxonomy")) | ("code", Some("taxonomy")) => {
+                    let grandparent = elements.iter().rev().nth(1).map(|e| e.name.as_str());
+                    if grandparent == Some("clade") {
+                        let clade = clades.last_mut().unwrap();
+                        // prefer the scientific name
+                        if clade.taxonomy_name.is_none() || element.name == "scientific_name" {
+                            clade.taxonomy_name = Some(element.text);
+                        }
+                    }
+                }
+                ("confidence", Some("clade")) | ("confidence", None) => {
+                    let key = element
+                        .attributes
+                        .get("type")
+                        .cloned()
+                        .unwrap_or_else(|| "confidence".to_string());
+                    let value = self.parse_number(&element.text, "a confidence value")?;
+                    let annotation = (key, AnnotationValue::Continuous(value));
+                    match parent {
+                        Some(_) => clades.last_mut().unwrap().annotations.push(annotation),
+                        None => tree.annotate_tree(annotation.0, annotation.1),
+                    }
+                }
+                ("property", Some("clade")) | ("property", None) => {
+                    let key = match element.attributes.get("ref") {
+                        Some(reference) => property_key(reference),
+                        None => return Err(self.error("property without a ref attribute")),
+                    };
+                    let value = property_value(
+                        &key,
+                        element.attributes.get("datatype").map(String::as_str),
+                        element.text,
+                    );
+                    match parent {
+                        Some(_) => clades.last_mut().unwrap().annotations.push((key, value)),
+                        None => tree.annotate_tree(key, value),
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        if let Some(root) = root {
+            tree.set_root(Some(root));
+        }
+        tree.branchlengths_known = true;
+        Ok(tree)
+    }
+
+    fn parse_number(&self, text: &str, expected: &str) -> Result<f64> {
+        text.trim()
+            .parse::<f64>()
+            .map_err(|_| self.error(format!("expected {} but found '{}'", expected, text)))
+    }
+}
+
+fn make_node(tree: &mut MutableTree, clade: PendingClade, is_root: bool) -> TreeIndex {
+    let node = if clade.children.is_empty() {
+        let taxon = clade.name.or(clade.taxonomy_name).unwrap_or_default();
+        tree.make_external_node(taxon.as_str(), None)
+            .expect("Failed to make tip")
+    } else {
+        let node = tree.make_internal_node(clade.children);
+        if let Some(name) = clade.name {
+            tree.label_node(node, name);
+        }
+        node
+    };
+    // missing branch lengths are 0 as in newick files
+    match clade.length {
+        Some(length) => tree.set_length(node, length),
+        None if !is_root => tree.set_length(node, 0.0),
+        None => {}
+    }
+    for (key, value) in clade.annotations.into_iter() {
+        tree.annotate_node(node, key, value);
+    }
+    node
+}
+
+/// `ref` attributes are namespaced ("fertree:location"). Only the name is kept.
+fn property_key(reference: &str) -> String {
+    match reference.split_once(':') {
+        Some((_, key)) => key.to_string(),
+        None => reference.to_string(),
+    }
+}
+
+fn property_value(key: &str, datatype: Option<&str>, text: String) -> AnnotationValue {
+    let datatype = datatype.map(|d| d.trim_start_matches("xsd:"));
+    match datatype {
+        Some("boolean") => AnnotationValue::Boolean(text.trim() == "true" || text.trim() == "1"),
+        Some("double") | Some("float") | Some("decimal") => match text.trim().parse::<f64>() {
+            Ok(value) => AnnotationValue::Continuous(value),
+            Err(_) => AnnotationValue::Discrete(text),
+        },
+        Some("integer") | Some("int") | Some("long") | Some("short") | Some("byte")
+        | Some("nonNegativeInteger") | Some("positiveInteger") | Some("nonPositiveInteger")
+        | Some("negativeInteger") | Some("unsignedLong") | Some("unsignedInt")
+        | Some("unsignedShort") | Some("unsignedByte") => match text.trim().parse::<i64>() {
+            Ok(value) => AnnotationValue::Integer(value),
+            Err(_) => AnnotationValue::Discrete(text),
+        },
+        // sets of values are written in the same syntax as BEAST annotations
+        _ if text.starts_with('{') => AnnotationParser::parse_annotation_value(key, &text)
+            .unwrap_or(AnnotationValue::Discrete(text)),
+        _ => AnnotationValue::Discrete(text),
+    }
+}
+
+fn read_element(start: &BytesStart) -> Result<Element> {
+    Ok(Element {
+        name: String::from_utf8_lossy(start.local_name().as_ref()).to_string(),
+        attributes: read_attributes(start)?,
+        text: String::new(),
+    })
+}
+
+fn read_attributes(start: &BytesStart) -> Result<HashMap<String, String>> {
+    let mut attributes = HashMap::new();
+    for attribute in start.attributes() {
+        let attribute = attribute.map_err(|e| IoError::Format(e.to_string()))?;
+        let value = attribute
+            .unescape_value()
+            .map_err(|e| IoError::Format(e.to_string()))?;
+        attributes.insert(
+            String::from_utf8_lossy(attribute.key.local_name().as_ref()).to_string(),
+            value.to_string(),
+        );
+    }
+    Ok(attributes)
+}
+
+impl<R: Read> Iterator for PhyloXmlImporter<R> {
+    type Item = MutableTree;
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.has_tree() {
+            let tree = self.read_next_tree();
+            match tree {
+                Ok(t) => Some(t),
+                Err(e) => {
+                    warn!("{}", e);
+                    None
+                }
+            }
+        } else {
+            None
+        }
+    }
+}
+
+impl<R: Read> TreeImporter<R> for PhyloXmlImporter<R> {
+    fn has_tree(&mut self) -> bool {
+        if self.next_phylogeny.is_some() || self.pending_error.is_some() {
+            return true;
+        }
+        match self.seek_phylogeny() {
+            Ok(found) => found,
+            Err(e) => {
+                self.pending_error = Some(e);
+                true
+            }
+        }
+    }
+
+    fn read_next_tree(&mut self) -> Result<MutableTree> {
+        if let Some(e) = self.pending_error.take() {
+            return Err(e);
+        }
+        if self.next_phylogeny.is_none() && !self.seek_phylogeny()? {
+            return Err(IoError::Eof);
+        }
+        let attributes = self.next_phylogeny.take().unwrap();
+        let tree = match self.parse_tree(attributes) {
+            Err(IoError::Eof) => Err(self.error("unexpected end of input")),
+            result => result,
+        };
+        self.tree_index += 1;
+        tree
+    }
+
+    fn skip_tree(&mut self) {
+        if self.next_phylogeny.is_none() && !matches!(self.seek_phylogeny(), Ok(true)) {
+            return;
+        }
+        self.next_phylogeny = None;
+        let mut depth = 0;
+        loop {
+            self.buf.clear();
+            match self.reader.read_event_into(&mut self.buf) {
+                Ok(Event::Start(_)) => depth += 1,
+                Ok(Event::End(_)) if depth == 0 => break,
+                Ok(Event::End(_)) => depth -= 1,
+                Ok(Event::Eof) | Err(_) => break,
+                Ok(_) => {}
+            }
+        }
+        self.tree_index += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PHYLOXML: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<phyloxml xmlns="http://www.phyloxml.org">
+  <phylogeny rooted="true">
+    <name>example</name>
+    <clade>
+      <clade branch_length="0.06">
+        <name>AB</name>
+        <confidence type="bootstrap">89</confidence>
+        <clade>
+          <name>A</name>
+          <branch_length>0.102</branch_length>
+          <property ref="fertree:location" datatype="xsd:string" applies_to="clade">UK</property>
+          <property ref="fertree:rate" datatype="xsd:double" applies_to="clade">0.5</property>
+        </clade>
+        <clade branch_length="0.23">
+          <taxonomy><scientific_name>B b</scientific_name></taxonomy>
+        </clade>
+      </clade>
+      <clade branch_length="0.4"><name>C</name></clade>
+    </clade>
+    <property ref="fertree:lnP" datatype="xsd:double" applies_to="phylogeny">-10.5</property>
+  </phylogeny>
+  <phylogeny rooted="false">
+    <clade><clade><name>A</name></clade><clade><name>B</name></clade></clade>
+  </phylogeny>
+</phyloxml>"#;
+
+    #[test]
+    fn parse() {
+        let mut trees = PhyloXmlImporter::from_reader(PHYLOXML.as_bytes());
+        assert!(trees.has_tree());
+        let tree = trees.read_next_tree().unwrap();
+        assert_eq!(Some("example"), tree.get_id());
+        assert_eq!(3, tree.get_external_node_count());
+        assert_eq!(Some(true), tree.is_rooted());
+        assert_eq!(
+            Some(&AnnotationValue::Continuous(-10.5)),
+            tree.tree_annotation.get("lnP")
+        );
+
+        let a = tree.get_taxon_node("A").unwrap();
+        assert_eq!(Some(0.102), tree.get_length(a));
+        assert_eq!(
+            Some(&AnnotationValue::Discrete("UK".to_string())),
+            tree.get_annotation(a, "location")
+        );
+        assert_eq!(
+            Some(&AnnotationValue::Continuous(0.5)),
+            tree.get_annotation(a, "rate")
+        );
+        assert!(tree.get_taxon_node("B b").is_some());
+
+        let ab = tree.get_label_node("AB").unwrap();
+        assert_eq!(Some(0.06), tree.get_length(ab));
+        assert_eq!(
+            Some(&AnnotationValue::Continuous(89.0)),
+            tree.get_annotation(ab, "bootstrap")
+        );
+        assert_eq!(Some(ab), tree.get_parent(a));
+
+        assert!(trees.has_tree());
+        let tree = trees.read_next_tree().unwrap();
+        assert_eq!(2, tree.get_external_node_count());
+        assert_eq!(Some(false), tree.is_rooted());
+        assert!(!trees.has_tree());
+    }
+
+    #[test]
+    fn skip() {
+        let mut trees = PhyloXmlImporter::from_reader(PHYLOXML.as_bytes());
+        trees.skip_tree();
+        let tree = trees.read_next_tree().unwrap();
+        assert_eq!(2, tree.get_external_node_count());
+    }
+
+    #[test]
+    fn bad_branch_length() {
+        let xml = "<phyloxml><phylogeny><clade><clade branch_length=\"x\"><name>A</name></clade></clade></phylogeny></phyloxml>";
+        let mut trees = PhyloXmlImporter::from_reader(xml.as_bytes());
+        assert!(trees.has_tree());
+        assert!(trees.read_next_tree().is_err());
+    }
+}
@@ -0,0 +1,160 @@
+use crate::io::error::IoError;
+use crate::io::parser::tree_importer::TreeImporter;
+use crate::tree::mutable_tree::MutableTree;
+use std::marker::PhantomData;
+use std::str::FromStr;
+
+type Result<T> = std::result::Result<T, IoError>;
+
+/// How many trees to drop from the start of a posterior sample.
+///
+/// Whole numbers are a count of trees. Numbers with a decimal point are a fraction of the
+/// trees in the input, so `0.1` drops the first 10%.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Burnin {
+    Trees(usize),
+    Fraction(f64),
+}
+
+impl Burnin {
+    /// The number of trees to drop from an input of `total` trees.
+    pub fn trees(&self, total: usize) -> usize {
+        match self {
+            Burnin::Trees(n) => *n,
+            Burnin::Fraction(f) => (total as f64 * f).floor() as usize,
+        }
+    }
+}
+
+impl Default for Burnin {
+    fn default() -> Self {
+        Burnin::Trees(0)
+    }
+}
+
+impl FromStr for Burnin {
+    type Err = String;
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        if let Ok(n) = s.parse::<usize>() {
+            return Ok(Burnin::Trees(n));
+        }
+        match s.parse::<f64>() {
+            Ok(f) if (0.0..1.0).contains(&f) => Ok(Burnin::Fraction(f)),
+            _ => Err(format!(
+                "burnin must be a number of trees or a fraction between 0 and 1, not {}",
+                s
+            )),
+        }
+    }
+}
+
+/// Drops a burn-in and thins the trees of another importer.
+///
+/// Dropped trees are passed over with [skip_tree](TreeImporter::skip_tree) so they are
+/// never fully parsed. After the burn-in the first tree is kept and then every `thin`th
+/// tree after it.
+pub struct SampledImporter<R, T: TreeImporter<R>> {
+    importer: T,
+    to_skip: usize,
+    thin: usize,
+    position: usize,
+    reader: PhantomData<R>,
+}
+
+impl<R, T: TreeImporter<R>> SampledImporter<R, T> {
+    pub fn new(importer: T, burnin: usize, thin: usize) -> Self {
+        SampledImporter {
+            importer,
+            to_skip: burnin,
+            thin: thin.max(1),
+            position: 0,
+            reader: PhantomData,
+        }
+    }
+
+    /// The index in the underlying input of the last tree read, counting skipped trees.
+    pub fn last_index(&self) -> Option<usize> {
+        self.position.checked_sub(1)
+    }
+
+    fn skip_pending(&mut self) {
+        while self.to_skip > 0 && self.importer.has_tree() {
+            self.importer.skip_tree();
+            self.to_skip -= 1;
+            self.position += 1;
+        }
+    }
+}
+
+impl<R, T: TreeImporter<R>> Iterator for SampledImporter<R, T> {
+    type Item = MutableTree;
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.has_tree() {
+            let tree = self.read_next_tree();
+            match tree {
+                Ok(t) => Some(t),
+                Err(e) => {
+                    warn!("{}", e);
+                    None
+                }
+            }
+        } else {
+            None
+        }
+    }
+}
+
+impl<R, T: TreeImporter<R>> TreeImporter<R> for SampledImporter<R, T> {
+    fn has_tree(&mut self) -> bool {
+        self.skip_pending();
+        self.importer.has_tree()
+    }
+
+    fn read_next_tree(&mut self) -> Result<MutableTree> {
+        self.skip_pending();
+        let tree = self.importer.read_next_tree();
+        self.position += 1;
+        self.to_skip = self.thin - 1;
+        tree
+    }
+
+    fn skip_tree(&mut self) {
+        self.skip_pending();
+        if self.importer.has_tree() {
+            self.importer.skip_tree();
+            self.position += 1;
+            self.to_skip = self.thin - 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::parser::newick_importer::NewickImporter;
+    use std::io::BufReader;
+
+    fn tip_counts(input: &str, burnin: usize, thin: usize) -> Vec<usize> {
+        let importer = NewickImporter::from_reader(BufReader::new(input.as_bytes()));
+        SampledImporter::new(importer, burnin, thin)
+            .map(|tree| tree.get_external_node_count())
+            .collect()
+    }
+
+    #[test]
+    fn burnin_and_thin() {
+        let input = "(A,B);(A,B,C);(A,B,C,D);(A,B,C,D,E);(A,B,C,D,E,F);";
+        assert_eq!(vec![2, 3, 4, 5, 6], tip_counts(input, 0, 1));
+        assert_eq!(vec![4, 5, 6], tip_counts(input, 2, 1));
+        assert_eq!(vec![3, 5], tip_counts(input, 1, 2));
+        assert_eq!(Vec::<usize>::new(), tip_counts(input, 10, 1));
+    }
+
+    #[test]
+    fn parse_burnin() {
+        assert_eq!(Ok(Burnin::Trees(100)), "100".parse());
+        assert_eq!(Ok(Burnin::Fraction(0.1)), "0.1".parse());
+        assert!("1.5".parse::<Burnin>().is_err());
+        assert_eq!(2, Burnin::Fraction(0.25).trees(10));
+    }
+}
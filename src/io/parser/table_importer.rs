@@ -0,0 +1,401 @@
+use crate::io::error::IoError;
+use crate::io::parser::annotation_parser::AnnotationParser;
+use crate::io::parser::tree_importer::TreeImporter;
+use crate::tree::mutable_tree::{MutableTree, TreeIndex};
+use crate::tree::AnnotationValue;
+use csv::StringRecord;
+use std::collections::HashMap;
+use std::io::Read;
+
+type Result<T> = std::result::Result<T, IoError>;
+
+/// Reads trees from a tab separated edge list with a header row.
+///
+/// Each row is a node. A `node` (or `child`) and a `parent` column are required and the
+/// root is the one node with an empty parent. Optional columns are `tree`, which groups
+/// consecutive rows into trees and becomes the tree id, `taxon`, `label`, `length` and
+/// `height`. Any other column is read as an annotation. Empty cells are missing values.
+///
+/// Tips without a taxon are named by their node id. Without a label column internal nodes
+/// are labelled by their node id as well. Without lengths the branch lengths are
+/// calculated from heights, or set to 0 if there are neither.
+pub struct TableImporter<R> {
+    reader: csv::Reader<R>,
+    columns: Option<Columns>,
+    next_record: Option<StringRecord>,
+    tree_index: usize,
+}
+
+struct Columns {
+    tree: Option<usize>,
+    node: usize,
+    parent: usize,
+    taxon: Option<usize>,
+    label: Option<usize>,
+    length: Option<usize>,
+    height: Option<usize>,
+    annotations: Vec<(usize, String)>,
+}
+
+impl Columns {
+    fn from_header(header: &StringRecord) -> Result<Self> {
+        let find = |name: &str| header.iter().position(|h| h == name);
+        let node = find("node")
+            .or_else(|| find("child"))
+            .ok_or_else(|| IoError::Format("table without a node or child column".to_string()))?;
+        let parent =
+            find("parent").ok_or_else(|| IoError::Format("table without a parent column".to_string()))?;
+        let known = ["tree", "node", "child", "parent", "taxon", "label", "length", "height"];
+        Ok(Columns {
+            tree: find("tree"),
+            node,
+            parent,
+            taxon: find("taxon"),
+            label: find("label"),
+            length: find("length"),
+            height: find("height"),
+            annotations: header
+                .iter()
+                .enumerate()
+                .filter(|(_, h)| !known.contains(h))
+                .map(|(i, h)| (i, h.to_string()))
+                .collect(),
+        })
+    }
+}
+
+/// The id of a tree and its rows keyed by node id
+type TreeRows = (Option<String>, Vec<(String, Row)>);
+
+struct Row {
+    parent: Option<String>,
+    taxon: Option<String>,
+    label: Option<String>,
+    length: Option<f64>,
+    height: Option<f64>,
+    annotations: Vec<(String, AnnotationValue)>,
+}
+
+impl<R: Read> TableImporter<R> {
+    pub fn from_reader(reader: R) -> Self {
+        TableImporter {
+            reader: csv::ReaderBuilder::new()
+                .delimiter(b'\t')
+                .has_headers(false)
+                .flexible(true)
+                .from_reader(reader),
+            columns: None,
+            next_record: None,
+            tree_index: 0,
+        }
+    }
+
+    fn error<S: Into<String>>(&self, message: S) -> IoError {
+        IoError::Format(format!("{} in tree {}", message.into(), self.tree_index))
+    }
+
+    fn read_record(&mut self) -> Result<Option<StringRecord>> {
+        if let Some(record) = self.next_record.take() {
+            return Ok(Some(record));
+        }
+        let mut record = StringRecord::new();
+        match self.reader.read_record(&mut record) {
+            Ok(true) => Ok(Some(record)),
+            Ok(false) => Ok(None),
+            Err(e) => Err(IoError::Io(e.to_string())),
+        }
+    }
+
+    fn read_header(&mut self) -> Result<()> {
+        if self.columns.is_none() {
+            match self.read_record()? {
+                Some(header) => self.columns = Some(Columns::from_header(&header)?),
+                None => return Err(IoError::Eof),
+            }
+        }
+        Ok(())
+    }
+
+    /// Read all rows belonging to the next tree. Rows are kept in the order they were read.
+    fn read_rows(&mut self) -> Result<TreeRows> {
+        self.read_header()?;
+        let mut tree_id = None;
+        let mut rows = vec![];
+        while let Some(record) = self.read_record()? {
+            let columns = self.columns.as_ref().unwrap();
+            let id = columns.tree.and_then(|i| record.get(i)).map(String::from);
+            if !rows.is_empty() && id != tree_id {
+                self.next_record = Some(record);
+                break;
+            }
+            tree_id = id;
+            let node = cell(&record, Some(columns.node))
+                .ok_or_else(|| self.error("row without a node id"))?;
+            let row = Row {
+                parent: cell(&record, Some(columns.parent)),
+                taxon: cell(&record, columns.taxon),
+                label: cell(&record, columns.label),
+                length: self.number(&record, columns.length)?,
+                height: self.number(&record, columns.height)?,
+                annotations: columns
+                    .annotations
+                    .iter()
+                    .filter_map(|(i, key)| {
+                        cell(&record, Some(*i)).map(|value| (key.clone(), annotation_value(key, value)))
+                    })
+                    .collect(),
+            };
+            rows.push((node, row));
+        }
+        Ok((tree_id, rows))
+    }
+
+    fn number(&self, record: &StringRecord, column: Option<usize>) -> Result<Option<f64>> {
+        match cell(record, column) {
+            Some(value) => value
+                .parse::<f64>()
+                .map(Some)
+                .map_err(|_| self.error(format!("expected a number but found '{}'", value))),
+            None => Ok(None),
+        }
+    }
+
+    fn parse_tree(&mut self) -> Result<MutableTree> {
+        let (tree_id, rows) = self.read_rows()?;
+        if rows.is_empty() {
+            return Err(IoError::Eof);
+        }
+        let columns = self.columns.as_ref().unwrap();
+        let has_labels = columns.label.is_some();
+        let has_lengths = columns.length.is_some();
+
+        let mut row_index: HashMap<&str, usize> = HashMap::new();
+        for (i, (node, _)) in rows.iter().enumerate() {
+            if row_index.insert(node.as_str(), i).is_some() {
+                return Err(self.error(format!("node {} appears more than once", node)));
+            }
+        }
+        let mut children: Vec<Vec<usize>> = vec![vec![]; rows.len()];
+        let mut root = None;
+        for (i, (node, row)) in rows.iter().enumerate() {
+            match &row.parent {
+                Some(parent) => match row_index.get(parent.as_str()) {
+                    Some(p) => children[*p].push(i),
+                    None => {
+                        return Err(self.error(format!(
+                            "parent {} of node {} is not in the table",
+                            parent, node
+                        )))
+                    }
+                },
+                None if root.is_none() => root = Some(i),
+                None => return Err(self.error("more than one node without a parent")),
+            }
+        }
+        let root = root.ok_or_else(|| self.error("no root (a node without a parent)"))?;
+
+        // build the tree from the tips up without recursion
+        let mut tree = MutableTree::new();
+        let mut tree_nodes: Vec<Option<TreeIndex>> = vec![None; rows.len()];
+        let mut stack = vec![(root, false)];
+        let mut visited = 0;
+        while let Some((i, children_done)) = stack.pop() {
+            if !children_done && !children[i].is_empty() {
+                stack.push((i, true));
+                stack.extend(children[i].iter().rev().map(|c| (*c, false)));
+                continue;
+            }
+            visited += 1;
+            let (id, row) = &rows[i];
+            let node = if children[i].is_empty() {
+                let taxon = row.taxon.as_deref().unwrap_or(id);
+                tree.make_external_node(taxon, None)
+                    .expect("Failed to make tip")
+            } else {
+                let child_nodes = children[i]
+                    .iter()
+                    .map(|c| tree_nodes[*c].expect("children are built first"))
+                    .collect();
+                let node = tree.make_internal_node(child_nodes);
+                let label = if has_labels { row.label.clone() } else { Some(id.clone()) };
+                if let Some(label) = label {
+                    tree.label_node(node, label);
+                }
+                node
+            };
+            tree_nodes[i] = Some(node);
+        }
+        if visited != rows.len() {
+            return Err(self.error("some nodes are not connected to the root"));
+        }
+        let root_node = tree_nodes[root].unwrap();
+        tree.set_root(Some(root_node));
+
+        let all_heights = rows.iter().all(|(_, row)| row.height.is_some());
+        for (i, (_, row)) in rows.iter().enumerate() {
+            let node = tree_nodes[i].unwrap();
+            for (key, value) in row.annotations.iter() {
+                tree.annotate_node(node, key.clone(), value.clone());
+            }
+            if all_heights {
+                tree.set_height(node, row.height.unwrap());
+            }
+        }
+        if has_lengths || !all_heights {
+            for (i, (_, row)) in rows.iter().enumerate() {
+                let node = tree_nodes[i].unwrap();
+                match row.length {
+                    Some(length) => tree.set_length(node, length),
+                    None if node != root_node => tree.set_length(node, 0.0),
+                    None => {}
+                }
+            }
+            tree.heights_known = all_heights;
+            tree.branchlengths_known = true;
+        } else {
+            tree.heights_known = true;
+            tree.calculate_branchlengths();
+        }
+        if let Some(id) = tree_id {
+            tree.set_id(id);
+        }
+        Ok(tree)
+    }
+}
+
+fn cell(record: &StringRecord, column: Option<usize>) -> Option<String> {
+    column
+        .and_then(|i| record.get(i))
+        .filter(|value| !value.is_empty())
+        .map(String::from)
+}
+
+/// Values are parsed as they would be in a BEAST comment and are otherwise discrete.
+fn annotation_value(key: &str, value: String) -> AnnotationValue {
+    AnnotationParser::parse_annotation_value(key, &value)
+        .unwrap_or(AnnotationValue::Discrete(value))
+}
+
+impl<R: Read> Iterator for TableImporter<R> {
+    type Item = MutableTree;
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.has_tree() {
+            let tree = self.read_next_tree();
+            match tree {
+                Ok(t) => Some(t),
+                Err(e) => {
+                    warn!("{}", e);
+                    None
+                }
+            }
+        } else {
+            None
+        }
+    }
+}
+
+impl<R: Read> TreeImporter<R> for TableImporter<R> {
+    fn has_tree(&mut self) -> bool {
+        if self.next_record.is_some() {
+            return true;
+        }
+        let header_read = self.columns.is_some();
+        match self.read_record() {
+            Ok(Some(record)) => {
+                if !header_read {
+                    match Columns::from_header(&record) {
+                        Ok(columns) => self.columns = Some(columns),
+                        // let read_next_tree report the bad header
+                        Err(_) => {
+                            self.next_record = Some(record);
+                            return true;
+                        }
+                    }
+                    return self.has_tree();
+                }
+                self.next_record = Some(record);
+                true
+            }
+            Ok(None) => false,
+            Err(_) => true,
+        }
+    }
+
+    fn read_next_tree(&mut self) -> Result<MutableTree> {
+        let tree = self.parse_tree();
+        self.tree_index += 1;
+        tree
+    }
+
+    fn skip_tree(&mut self) {
+        let _ = self.read_rows();
+        self.tree_index += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::parser::newick_importer::NewickImporter;
+    use crate::io::writer::table_writer::TableWriter;
+    use std::io::BufReader;
+
+    #[test]
+    fn edge_list() {
+        let table = "parent\tchild\tlength\n\troot\t\nroot\tAB\t1\nAB\tA\t1\nAB\tB\t2\nroot\tC\t2\n";
+        let mut trees = TableImporter::from_reader(table.as_bytes());
+        assert!(trees.has_tree());
+        let tree = trees.read_next_tree().unwrap();
+        assert!(!trees.has_tree());
+        assert_eq!("((A:1,B:2)AB:1,C:2)root;", tree.to_string());
+    }
+
+    #[test]
+    fn round_trip() {
+        let first = "((A[&location=\"UK\",rate=0.5]:1,B:2)label:1,C:2);";
+        let second = "((A:1,C:2):1,B:2);";
+        let mut out = vec![];
+        {
+            let mut writer = TableWriter::new(&mut out);
+            for s in [first, second].iter() {
+                let tree = NewickImporter::read_tree(BufReader::new(s.as_bytes())).unwrap();
+                writer.write_tree(&tree).unwrap();
+            }
+        }
+        let mut trees = TableImporter::from_reader(out.as_slice());
+        let tree = trees.read_next_tree().unwrap();
+        let a = tree.get_taxon_node("A").unwrap();
+        assert_eq!(Some(1.0), tree.get_length(a));
+        assert_eq!(
+            Some(&AnnotationValue::Discrete("UK".to_string())),
+            tree.get_annotation(a, "location")
+        );
+        assert_eq!(
+            Some(&AnnotationValue::Continuous(0.5)),
+            tree.get_annotation(a, "rate")
+        );
+        assert_eq!(Some(tree.get_parent(a).unwrap()), tree.get_label_node("label"));
+        let tree = trees.read_next_tree().unwrap();
+        assert_eq!(Some("1"), tree.get_id());
+        assert_eq!(format!("[&id=\"1\"] {}", second), tree.to_string());
+        assert!(!trees.has_tree());
+    }
+
+    #[test]
+    fn heights() {
+        let table = "node\tparent\theight\nr\t\t3\nA\tr\t0\nB\tr\t1\n";
+        let tree = TableImporter::from_reader(table.as_bytes())
+            .read_next_tree()
+            .unwrap();
+        let b = tree.get_taxon_node("B").unwrap();
+        assert_eq!(Some(2.0), tree.get_length(b));
+    }
+
+    #[test]
+    fn missing_parent() {
+        let table = "node\tparent\nr\t\nA\tr\nB\tx\n";
+        assert!(TableImporter::from_reader(table.as_bytes())
+            .read_next_tree()
+            .is_err());
+    }
+}
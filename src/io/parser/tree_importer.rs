@@ -1,8 +1,13 @@
 use crate::io::error::IoError;
+use crate::io::parser::chained_importer::SourceTracker;
 use crate::tree::mutable_tree::MutableTree;
 
 pub trait TreeImporter<R>: Iterator {
     fn has_tree(&mut self) -> bool;
     fn read_next_tree(&mut self) -> Result<MutableTree, IoError>;
     fn skip_tree(&mut self);
+    /// Where the importer records the file the last tree read came from, if it reads files
+    fn source_tracker(&self) -> Option<SourceTracker> {
+        None
+    }
 }
@@ -1 +1,353 @@
+//! JSON representation of a [MutableTree].
+//!
+//! A tree is a flat table of nodes that reference their parent and children by their
+//! index in `nodes`. The fields are stable and new fields will only ever be added.
+//!
+//! ```json
+//! {
+//!   "id": "STATE_0",
+//!   "root": 4,
+//!   "heights_known": false,
+//!   "branchlengths_known": true,
+//!   "annotations": {"lnP": -10.5},
+//!   "nodes": [
+//!     {"taxon": "A", "label": null, "parent": 2, "children": [], "length": 1.0,
+//!      "height": null, "annotations": {"location": "UK"}},
+//!     ...
+//!   ]
+//! }
+//! ```
+//!
+//! `annotations` on the tree and on nodes map keys to plain JSON values: strings for
+//! discrete traits, numbers for continuous traits, booleans, arrays for sets,
+//! `{"lower", "upper"}` objects for intervals and `{"time", "source", "destination"}`
+//! objects for markov jumps. Values of one annotation with different types are converted
+//! to a common type as in [MutableTree::annotate_node].
+//! Nodes without children are tips. Children are listed in order and each child's
+//! `parent` must be the node that lists it. Every node must be reachable from `root`.
+//! Tips with an attached sequence also have a `sequence` string.
+//! `rooted` is only present if the input said whether the tree is rooted.
+use crate::tree::mutable_tree::{MutableTree, MutableTreeNode, NodeKind, TreeIndex};
+use crate::tree::AnnotationValue;
+use serde::de::Error;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::HashMap;
 
+#[derive(Serialize)]
+struct SerializedTree<'a> {
+    id: &'a Option<String>,
+    root: Option<TreeIndex>,
+    heights_known: bool,
+    branchlengths_known: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rooted: Option<bool>,
+    annotations: &'a HashMap<String, AnnotationValue>,
+    nodes: Vec<SerializedNode<'a>>,
+}
+
+#[derive(Serialize)]
+struct SerializedNode<'a> {
+    taxon: &'a Option<String>,
+    label: &'a Option<String>,
+    parent: Option<TreeIndex>,
+    children: Vec<TreeIndex>,
+    length: Option<f64>,
+    height: Option<f64>,
+    annotations: &'a HashMap<String, AnnotationValue>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sequence: &'a Option<String>,
+}
+
+#[derive(Deserialize)]
+struct DeserializedTree {
+    #[serde(default)]
+    id: Option<String>,
+    root: Option<TreeIndex>,
+    #[serde(default)]
+    heights_known: bool,
+    #[serde(default)]
+    branchlengths_known: bool,
+    #[serde(default)]
+    rooted: Option<bool>,
+    #[serde(default)]
+    annotations: HashMap<String, AnnotationValue>,
+    nodes: Vec<DeserializedNode>,
+}
+
+#[derive(Deserialize)]
+struct DeserializedNode {
+    #[serde(default)]
+    taxon: Option<String>,
+    #[serde(default)]
+    label: Option<String>,
+    #[serde(default)]
+    parent: Option<TreeIndex>,
+    #[serde(default)]
+    children: Vec<TreeIndex>,
+    #[serde(default)]
+    length: Option<f64>,
+    #[serde(default)]
+    height: Option<f64>,
+    #[serde(default)]
+    annotations: HashMap<String, AnnotationValue>,
+    #[serde(default)]
+    sequence: Option<String>,
+}
+
+impl Serialize for MutableTree {
+    /// Only nodes that are still in the tree are written, numbered in arena order.
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut position = vec![None; self.nodes.len()];
+        let mut count = 0;
+        for (index, node) in self.nodes.iter().enumerate() {
+            if node.kind != NodeKind::Unlisted {
+                position[index] = Some(count);
+                count += 1;
+            }
+        }
+        let moved = |index: Option<TreeIndex>| index.and_then(|i| position[i]);
+        let nodes = self
+            .nodes
+            .iter()
+            .filter(|node| node.kind != NodeKind::Unlisted)
+            .map(|node| SerializedNode {
+                taxon: &node.taxon,
+                label: &node.label,
+                parent: moved(node.parent),
+                children: node.children.iter().filter_map(|c| position[*c]).collect(),
+                length: node.length,
+                height: node.height,
+                annotations: &node.annotations,
+                sequence: &node.sequence,
+            })
+            .collect();
+        SerializedTree {
+            id: &self.id,
+            root: moved(self.root),
+            heights_known: self.heights_known,
+            branchlengths_known: self.branchlengths_known,
+            rooted: self.rooted,
+            annotations: &self.tree_annotation,
+            nodes,
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for MutableTree {
+    /// The node lists, taxon, label and annotation type maps are rebuilt from the nodes.
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let data = DeserializedTree::deserialize(deserializer)?;
+        let node_count = data.nodes.len();
+        if data.root.map_or(node_count > 0, |root| root >= node_count) {
+            return Err(D::Error::custom("root is not a node in the tree"));
+        }
+        for (index, node) in data.nodes.iter().enumerate() {
+            for child in node.children.iter() {
+                if data.nodes.get(*child).and_then(|c| c.parent) != Some(index) {
+                    return Err(D::Error::custom(format!(
+                        "child {} of node {} does not have it as parent",
+                        child, index
+                    )));
+                }
+            }
+            if let Some(parent) = node.parent {
+                if !data.nodes.get(parent).is_some_and(|p| p.children.contains(&index)) {
+                    return Err(D::Error::custom(format!(
+                        "node {} is not a child of its parent",
+                        index
+                    )));
+                }
+            }
+        }
+        // every node has one parent, so the nodes reachable from the root form a tree
+        let mut reached = 0;
+        let mut seen = vec![false; node_count];
+        let mut stack: Vec<TreeIndex> = data.root.into_iter().collect();
+        while let Some(node) = stack.pop() {
+            if seen[node] {
+                return Err(D::Error::custom(format!("node {} is listed twice", node)));
+            }
+            seen[node] = true;
+            reached += 1;
+            stack.extend(data.nodes[node].children.iter());
+        }
+        if reached != node_count {
+            return Err(D::Error::custom("not all nodes are reachable from the root"));
+        }
+
+        let mut tree = MutableTree::new();
+        for (index, node) in data.nodes.iter().enumerate() {
+            let mut new_node = MutableTreeNode::new(node.taxon.clone(), index);
+            new_node.label = node.label.clone();
+            new_node.parent = node.parent;
+            new_node.length = node.length;
+            new_node.height = node.height;
+            new_node.sequence = node.sequence.clone();
+            if node.children.is_empty() {
+                new_node.kind = NodeKind::External;
+                tree.external_nodes.push(index);
+                if let Some(taxon) = &node.taxon {
+                    tree.taxon_node_map.insert(taxon.clone(), index);
+                    tree.label_node_map.insert(taxon.clone(), index);
+                }
+            } else {
+                new_node.kind = NodeKind::Internal;
+                tree.internal_nodes.push(index);
+                if let Some(label) = &node.label {
+                    tree.label_node_map.insert(label.clone(), index);
+                }
+            }
+            tree.nodes.push(new_node);
+        }
+        for (index, node) in data.nodes.into_iter().enumerate() {
+            for child in node.children.iter() {
+                tree.add_child(index, *child);
+            }
+            for (key, value) in node.annotations {
+                tree.annotate_node(index, key, value);
+            }
+        }
+        tree.id = data.id;
+        tree.root = data.root;
+        tree.heights_known = data.heights_known;
+        tree.branchlengths_known = data.branchlengths_known;
+        tree.rooted = data.rooted;
+        tree.tree_annotation = data.annotations;
+        Ok(tree)
+    }
+}
+
+/// Write the tree as a single line of JSON
+pub fn write_json(tree: &MutableTree) -> String {
+    serde_json::to_string(tree).expect("trees can always be written as JSON")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::parser::newick_importer::NewickImporter;
+    use std::io::BufReader;
+
+    #[test]
+    fn round_trip() {
+        let s = "((A[&location=\"UK\",rate=0.5,height_95%_HPD={1.0,2.5}]:1,B:1)label[&resolved=true]:1,'C d':2);";
+        let mut tree = NewickImporter::read_tree(BufReader::new(s.as_bytes())).unwrap();
+        tree.set_id("STATE_0".to_string());
+        tree.annotate_tree("lnP".to_string(), AnnotationValue::Continuous(-10.5));
+
+        let json = write_json(&tree);
+        let read: MutableTree = serde_json::from_str(&json).unwrap();
+        assert_eq!(Some("STATE_0"), read.get_id());
+        assert_eq!(tree.get_node_count(), read.get_node_count());
+        for node in tree.preorder_iter() {
+            assert_eq!(tree.get_parent(node), read.get_parent(node));
+            assert_eq!(tree.get_length(node), read.get_length(node));
+            assert_eq!(tree.get_children(node), read.get_children(node));
+            assert_eq!(tree.is_external(node), read.is_external(node));
+            assert_eq!(
+                tree.get_node(node).unwrap().annotations,
+                read.get_node(node).unwrap().annotations
+            );
+        }
+        assert_eq!(tree.external_nodes, read.external_nodes);
+        assert_eq!(tree.get_taxon_node("C d"), read.get_taxon_node("C d"));
+        assert_eq!(tree.get_label_node("label"), read.get_label_node("label"));
+        assert_eq!(
+            Some(&AnnotationValue::Continuous(-10.5)),
+            read.tree_annotation.get("lnP")
+        );
+        assert_eq!(
+            std::mem::discriminant(tree.get_annotation_type("rate").unwrap()),
+            std::mem::discriminant(read.get_annotation_type("rate").unwrap())
+        );
+    }
+
+    #[test]
+    fn plain_annotation_values() {
+        let mut tree = NewickImporter::read_tree(BufReader::new(
+            "(A[&location=\"UK\",rate=0.5]:1,B:1);".as_bytes(),
+        ))
+        .unwrap();
+        let a = tree.get_taxon_node("A").unwrap();
+        tree.annotate_node(a, "resolved".to_string(), AnnotationValue::Boolean(true));
+        let json: serde_json::Value = serde_json::from_str(&write_json(&tree)).unwrap();
+        let a = &json["nodes"][0]["annotations"];
+        assert_eq!("UK", a["location"]);
+        assert_eq!(0.5, a["rate"]);
+        assert_eq!(true, a["resolved"]);
+        assert_eq!(2, json["root"]);
+    }
+
+    #[test]
+    fn parent_and_children() {
+        let mut tree =
+            NewickImporter::read_tree(BufReader::new("((A:1,B:1):1,(C:1,D:1):1);".as_bytes()))
+                .unwrap();
+        let c = tree.get_taxon_node("C").unwrap();
+        tree.delete_node(c).unwrap();
+        let json: serde_json::Value = serde_json::from_str(&write_json(&tree)).unwrap();
+        let nodes = json["nodes"].as_array().unwrap();
+        assert_eq!(6, nodes.len());
+        assert!(nodes[0].get("first_child").is_none());
+        assert!(nodes[0].get("number").is_none());
+        assert!(json.get("external_nodes").is_none());
+        assert_eq!(5, json["root"]);
+        assert_eq!(serde_json::json!([2, 4]), nodes[5]["children"]);
+        assert_eq!(4, nodes[3]["parent"]);
+
+        let read: MutableTree = serde_json::from_value(json).unwrap();
+        assert_eq!(tree.to_string(), read.to_string());
+    }
+
+    #[test]
+    fn intervals_and_sets() {
+        let s = "(A[&p.set={0.3,0.7},h_95%_HPD={0.5,1}]:1,B[&p.set={0.1,0.2,0.7},h_95%_HPD={0.5,1.5}]:1);";
+        let tree = NewickImporter::read_tree(BufReader::new(s.as_bytes())).unwrap();
+        let json = write_json(&tree);
+        let read: MutableTree = serde_json::from_str(&json).unwrap();
+        for taxon in ["A", "B"].iter() {
+            let node = tree.get_taxon_node(taxon).unwrap();
+            for key in ["p.set", "h_95%_HPD"].iter() {
+                assert_eq!(tree.get_annotation(node, key), read.get_annotation(node, key));
+            }
+        }
+        let a = read.get_taxon_node("A").unwrap();
+        assert_eq!(
+            Some(&AnnotationValue::Interval(0.5, 1.0)),
+            read.get_annotation(a, "h_95%_HPD")
+        );
+    }
+
+    #[test]
+    fn mixed_types_are_widened() {
+        let json = r#"{"root": 2, "nodes": [
+            {"parent": 2, "taxon": "A", "annotations": {"x": 1, "hpd": {"lower": 0, "upper": 1}}},
+            {"parent": 2, "taxon": "B", "annotations": {"x": 0.5, "hpd": [1, 2, 3]}},
+            {"children": [0, 1]}]}"#;
+        let tree: MutableTree = serde_json::from_str(json).unwrap();
+        assert_eq!(Some(&AnnotationValue::Continuous(1.0)), tree.get_annotation(0, "x"));
+        assert_eq!(
+            Some(&AnnotationValue::Set(vec![
+                AnnotationValue::Continuous(0.0),
+                AnnotationValue::Continuous(1.0)
+            ])),
+            tree.get_annotation(0, "hpd")
+        );
+    }
+
+    #[test]
+    fn bad_index() {
+        let json = r#"{"root": 3, "nodes": []}"#;
+        assert!(serde_json::from_str::<MutableTree>(json).is_err());
+    }
+
+    #[test]
+    fn bad_links() {
+        let not_parent = r#"{"root": 0, "nodes": [{"children": [1]}, {"parent": null}]}"#;
+        assert!(serde_json::from_str::<MutableTree>(not_parent).is_err());
+        let cycle = r#"{"root": 0, "nodes": [{"children": []},
+            {"parent": 2, "children": [2]}, {"parent": 1, "children": [1]}]}"#;
+        assert!(serde_json::from_str::<MutableTree>(cycle).is_err());
+    }
+}
@@ -0,0 +1,270 @@
+use crate::tree::mutable_tree::{MutableTree, TreeIndex};
+use crate::tree::AnnotationValue;
+use serde_json::{json, Map, Value};
+use std::collections::BTreeMap;
+use std::io::{Error, ErrorKind, Result, Write};
+
+/// Options for converting a tree to Nextstrain's Auspice v2 JSON.
+#[derive(Debug, Clone)]
+pub struct AuspiceFormat {
+    /// title shown in auspice. Defaults to the tree id.
+    pub title: Option<String>,
+    /// write node heights as `num_date`. The heights should be decimal dates as set by
+    /// `MutableTree::calc_relative_node_heights` with the date of the most recent tip.
+    pub num_date: bool,
+    /// annotation holding TreeTime style comma separated mutations (A123G,S:D614G)
+    pub mutations_key: String,
+}
+
+impl Default for AuspiceFormat {
+    fn default() -> Self {
+        AuspiceFormat {
+            title: None,
+            num_date: false,
+            mutations_key: "mutations".to_string(),
+        }
+    }
+}
+
+/// Write a tree as a single line Auspice v2 JSON document.
+///
+/// Every node gets its divergence from the root as `div`. Discrete, continuous and
+/// boolean annotations become node attributes with a matching coloring in the meta data.
+/// Mutations are split by gene with nucleotide mutations (no gene prefix) under `nuc`.
+/// Internal nodes without a label are named NODE_<index>. The nested nodes are written
+/// as they are visited rather than built up as a JSON value.
+pub fn write_auspice<W: Write + ?Sized>(
+    handle: &mut W,
+    tree: &MutableTree,
+    format: &AuspiceFormat,
+) -> Result<()> {
+    if format.num_date && !tree.heights_known {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "tried to write dates for a tree without heights known! calculate them first!",
+        ));
+    }
+    let root = tree.get_root().expect("tree has no root");
+    // the colorings come before the tree in the document so are found first
+    let mut colorings: BTreeMap<&str, &'static str> = BTreeMap::new();
+    for node in tree.preorder_iter() {
+        for (key, value) in tree.get_node(node).unwrap().annotations.iter() {
+            if *key != format.mutations_key {
+                if let Some((_, kind)) = attribute(value) {
+                    colorings.entry(key).or_insert(kind);
+                }
+            }
+        }
+    }
+    let mut colorings = colorings
+        .into_iter()
+        .map(|(key, kind)| json!({"key": key, "title": key, "type": kind}))
+        .collect::<Vec<Value>>();
+    if format.num_date {
+        colorings.insert(
+            0,
+            json!({"key": "num_date", "title": "Sampling date", "type": "continuous"}),
+        );
+    }
+    let mut meta = Map::new();
+    if let Some(title) = format.title.as_deref().or_else(|| tree.get_id()) {
+        meta.insert("title".to_string(), json!(title));
+    }
+    meta.insert("panels".to_string(), json!(["tree"]));
+    meta.insert("colorings".to_string(), Value::Array(colorings));
+
+    write!(handle, "{{\"version\":\"v2\",\"meta\":")?;
+    serde_json::to_writer(&mut *handle, &meta)?;
+    write!(handle, ",\"tree\":")?;
+    write_nodes(handle, tree, root, format)?;
+    write!(handle, "}}")
+}
+
+enum Step {
+    /// Write a node given its parent's divergence
+    Node(TreeIndex, f64),
+    Comma,
+    /// Close the children of a node and the node itself
+    Close,
+}
+
+fn write_nodes<W: Write + ?Sized>(
+    handle: &mut W,
+    tree: &MutableTree,
+    root: TreeIndex,
+    format: &AuspiceFormat,
+) -> Result<()> {
+    let mut stack = vec![Step::Node(root, 0.0)];
+    while let Some(step) = stack.pop() {
+        let (node, parent_div) = match step {
+            Step::Node(node, parent_div) => (node, parent_div),
+            Step::Comma => {
+                write!(handle, ",")?;
+                continue;
+            }
+            Step::Close => {
+                write!(handle, "]}}")?;
+                continue;
+            }
+        };
+        let name = if tree.is_external(node) {
+            tree.get_taxon(node).map(String::from)
+        } else {
+            tree.get_label(node).map(String::from)
+        }
+        .unwrap_or_else(|| format!("NODE_{:07}", node));
+
+        let div = match tree.get_parent(node) {
+            Some(_) => parent_div + tree.get_length(node).unwrap_or(0.0),
+            None => 0.0,
+        };
+        let mut node_attrs = Map::new();
+        node_attrs.insert("div".to_string(), json!(div));
+        if format.num_date {
+            let date = tree.get_height(node).expect("node should have a height");
+            node_attrs.insert("num_date".to_string(), json!({ "value": date }));
+        }
+
+        let mut branch_attrs = Map::new();
+        let annotations = &tree.get_node(node).unwrap().annotations;
+        let mut keys = annotations.keys().collect::<Vec<&String>>();
+        keys.sort();
+        for key in keys {
+            let value = &annotations[key];
+            if *key == format.mutations_key {
+                if let AnnotationValue::Discrete(mutations) = value {
+                    branch_attrs.insert("mutations".to_string(), write_mutations(mutations));
+                }
+                continue;
+            }
+            if let Some((value, _)) = attribute(value) {
+                node_attrs.insert(key.clone(), json!({ "value": value }));
+            }
+        }
+
+        write!(handle, "{{\"name\":")?;
+        serde_json::to_writer(&mut *handle, &name)?;
+        write!(handle, ",\"node_attrs\":")?;
+        serde_json::to_writer(&mut *handle, &node_attrs)?;
+        if !branch_attrs.is_empty() {
+            write!(handle, ",\"branch_attrs\":")?;
+            serde_json::to_writer(&mut *handle, &branch_attrs)?;
+        }
+        let children = tree.get_children(node);
+        if children.is_empty() {
+            write!(handle, "}}")?;
+        } else {
+            write!(handle, ",\"children\":[")?;
+            stack.push(Step::Close);
+            for (i, child) in children.into_iter().enumerate().rev() {
+                stack.push(Step::Node(child, div));
+                if i > 0 {
+                    stack.push(Step::Comma);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The auspice value and coloring type of an annotation. Intervals, sets and jumps have
+/// no auspice equivalent.
+fn attribute(value: &AnnotationValue) -> Option<(Value, &'static str)> {
+    match value {
+        AnnotationValue::Discrete(s) => Some((json!(s), "categorical")),
+        AnnotationValue::Integer(i) => Some((json!(i), "continuous")),
+        AnnotationValue::Continuous(c) => Some((json!(c), "continuous")),
+        AnnotationValue::Boolean(b) => Some((json!(b), "boolean")),
+        _ => None,
+    }
+}
+
+fn write_mutations(mutations: &str) -> Value {
+    let mut genes: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+    for mutation in mutations.split(',').map(str::trim).filter(|m| !m.is_empty()) {
+        let (gene, mutation) = mutation.split_once(':').unwrap_or(("nuc", mutation));
+        genes.entry(gene).or_default().push(mutation);
+    }
+    json!(genes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::parser::newick_importer::NewickImporter;
+    use crate::tree::test_utils;
+    use std::io::BufReader;
+
+    fn auspice_json(tree: &MutableTree, format: &AuspiceFormat) -> Value {
+        let mut out = vec![];
+        write_auspice(&mut out, tree, format).unwrap();
+        serde_json::from_slice(&out).unwrap()
+    }
+
+    #[test]
+    fn export() {
+        let s = "((A[&country=\"UK\",mutations=\"A1G,S:D614G\"]:1,B[&country=\"USA\"]:2)[&mutations=\"C7T\"]:1,C[&country=\"UK\"]:2);";
+        let mut tree = NewickImporter::read_tree(BufReader::new(s.as_bytes())).unwrap();
+        tree.calc_relative_node_heights(2021.0);
+        let format = AuspiceFormat {
+            title: Some("test".to_string()),
+            num_date: true,
+            ..Default::default()
+        };
+        let json = auspice_json(&tree, &format);
+        assert_eq!("v2", json["version"]);
+        assert_eq!("test", json["meta"]["title"]);
+        assert_eq!(
+            json!([
+                {"key": "num_date", "title": "Sampling date", "type": "continuous"},
+                {"key": "country", "title": "country", "type": "categorical"}
+            ]),
+            json["meta"]["colorings"]
+        );
+        let root = &json["tree"];
+        assert_eq!(json!(2018.0), root["node_attrs"]["num_date"]["value"]);
+        let ab = &root["children"][0];
+        assert_eq!(json!(["C7T"]), ab["branch_attrs"]["mutations"]["nuc"]);
+        let a = &ab["children"][0];
+        assert_eq!("A", a["name"]);
+        assert_eq!(json!(2.0), a["node_attrs"]["div"]);
+        assert_eq!(json!(2020.0), a["node_attrs"]["num_date"]["value"]);
+        assert_eq!("UK", a["node_attrs"]["country"]["value"]);
+        assert_eq!(
+            json!({"nuc": ["A1G"], "S": ["D614G"]}),
+            a["branch_attrs"]["mutations"]
+        );
+    }
+
+    #[test]
+    fn dates_need_heights() {
+        let tree =
+            NewickImporter::read_tree(BufReader::new("((A:1,B:2):1,C:2);".as_bytes())).unwrap();
+        let format = AuspiceFormat {
+            num_date: true,
+            ..AuspiceFormat::default()
+        };
+        let mut out = vec![];
+        let err = write_auspice(&mut out, &tree, &format).unwrap_err();
+        assert_eq!(ErrorKind::InvalidInput, err.kind());
+    }
+
+    #[test]
+    fn deep_ladder() {
+        let depth = 100_000;
+        let tree = NewickImporter::read_tree(BufReader::new(
+            test_utils::deep_ladder(depth).as_bytes(),
+        ))
+        .unwrap();
+        let mut out = vec![];
+        write_auspice(&mut out, &tree, &AuspiceFormat::default()).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(2 * depth + 1, out.matches("\"name\":").count());
+        assert_eq!(depth, out.matches("\"children\":[").count());
+        assert!(out.starts_with("{\"version\":\"v2\","));
+        assert!(out.contains(
+            "[{\"name\":\"t0\",\"node_attrs\":{\"div\":100000.0}},{\"name\":\"t1\",\"node_attrs\":{\"div\":100000.0}}]"
+        ));
+        assert!(out.ends_with("{\"name\":\"t100000\",\"node_attrs\":{\"div\":1.0}}]}}"));
+    }
+}
@@ -0,0 +1,28 @@
+use std::io::{Result, Write};
+
+/// Write sequences as FASTA with each sequence on a single line.
+pub fn write_fasta<'a, W: Write + ?Sized, I: IntoIterator<Item = (&'a str, &'a str)>>(
+    writer: &mut W,
+    sequences: I,
+) -> Result<()> {
+    for (taxon, sequence) in sequences {
+        writeln!(writer, ">{}", taxon)?;
+        writeln!(writer, "{}", sequence)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::parser::alignment_importer::read_fasta;
+
+    #[test]
+    fn round_trip() {
+        let fasta = ">A\nACGT\n>B c\nAC-T\n";
+        let alignment = read_fasta(fasta.as_bytes()).unwrap();
+        let mut out = vec![];
+        write_fasta(&mut out, alignment.iter()).unwrap();
+        assert_eq!(fasta, String::from_utf8(out).unwrap());
+    }
+}
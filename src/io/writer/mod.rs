@@ -1 +1,7 @@
+pub mod auspice_writer;
+pub mod fasta_writer;
 pub mod newick_writer;
+pub mod nexus_writer;
+pub mod phyloxml_writer;
+pub mod source_column;
+pub mod table_writer;
@@ -1,7 +1,87 @@
 use crate::tree::mutable_tree::{MutableTree, TreeIndex};
 use crate::tree::AnnotationValue;
+use std::collections::{HashMap, HashSet};
 use std::fmt;
 
+/// Key used for the tree id in the comment before a newick tree. Newick has no other place
+/// to keep it.
+pub const TREE_ID_KEY: &str = "id";
+
+/// How node annotations are written in newick comments.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AnnotationDialect {
+    /// BEAST style `[&key=value,...]` before the branch length
+    Beast,
+    /// New Hampshire eXtended `[&&NHX:key=value:...]` after the branch length.
+    /// NHX has no quoting so ':' and ']' in values are replaced with '_'.
+    Nhx,
+}
+
+/// Options controlling what is included in newick output. The default writes everything
+/// fertree knows about the tree.
+#[derive(Debug, Clone)]
+pub struct NewickFormat {
+    /// write node annotations in BEAST style comments
+    pub annotations: bool,
+    /// comment syntax used for annotations
+    pub dialect: AnnotationDialect,
+    /// annotation keys that are never written
+    pub excluded_annotations: HashSet<String>,
+    /// write labels on internal nodes
+    pub internal_labels: bool,
+    /// write branch lengths. Without them the output is a cladogram.
+    pub branch_lengths: bool,
+    /// number of decimal places for branch lengths
+    pub precision: Option<usize>,
+    /// number of significant digits for branch lengths. Ignored if precision is set.
+    pub significant_digits: Option<usize>,
+    /// write the tree id and tree annotations in a comment before the tree, followed by
+    /// `[&R]` or `[&U]` if the rooting is known
+    pub tree_annotations: bool,
+}
+
+impl Default for NewickFormat {
+    fn default() -> Self {
+        NewickFormat {
+            annotations: true,
+            dialect: AnnotationDialect::Beast,
+            excluded_annotations: HashSet::new(),
+            internal_labels: true,
+            branch_lengths: true,
+            precision: None,
+            significant_digits: None,
+            tree_annotations: true,
+        }
+    }
+}
+
+impl NewickFormat {
+    /// Just the topology and the taxa.
+    pub fn topology() -> Self {
+        NewickFormat {
+            annotations: false,
+            internal_labels: false,
+            branch_lengths: false,
+            tree_annotations: false,
+            ..Default::default()
+        }
+    }
+
+    fn format_length(&self, l: f64) -> String {
+        if let Some(precision) = self.precision {
+            format!("{:.*}", precision, l)
+        } else if let Some(digits) = self.significant_digits {
+            // round trip through scientific notation to round to the significant digits
+            let rounded = format!("{:.*e}", digits.max(1) - 1, l);
+            rounded.parse::<f64>().unwrap_or(l).to_string()
+        } else if l < 1e-4 {
+            format!("{:e}", l)
+        } else {
+            l.to_string()
+        }
+    }
+}
+
 impl fmt::Display for MutableTree {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let s = write_newick(self);
@@ -10,11 +90,20 @@ impl fmt::Display for MutableTree {
 }
 
 pub fn write_newick(tree: &MutableTree) -> String {
+    write_formatted_newick(tree, &NewickFormat::default())
+}
+
+pub fn write_formatted_newick(tree: &MutableTree, format: &NewickFormat) -> String {
     //TODO check if branchlengths known and throw error if not known. Need io erro
-    if !tree.branchlengths_known {
+    if format.branch_lengths && !tree.branchlengths_known {
         panic!("tried to write  a tree without branchlengths known! calculate them first!")
     }
-    let mut s = write_node(tree, tree.get_root().unwrap());
+    let mut s = if format.tree_annotations {
+        write_tree_comments(tree)
+    } else {
+        String::new()
+    };
+    s.push_str(&write_node(tree, tree.get_root().unwrap(), None, format));
     s.push(';');
     s
 }
@@ -24,57 +113,141 @@ pub fn write_newick_subtree(tree: &MutableTree,node:TreeIndex) -> String {
     if !tree.branchlengths_known {
         panic!("tried to write  a tree without branchlengths known! calculate them first!")
     }
-    let mut s = write_node(tree, node);
+    let mut s = write_node(tree, node, None, &NewickFormat::default());
     s.push(';');
     s
 }
-//TODO write without annotation
 
-fn write_node(tree: &MutableTree, node_ref: TreeIndex) -> String {
+/// Write the tree with taxon names replaced by their key in the translation map, as is done
+/// in the TREES block of nexus files. Taxa missing from the map are written as is.
+pub fn write_translated_newick(tree: &MutableTree, translation: &HashMap<String, String>) -> String {
+    if !tree.branchlengths_known {
+        panic!("tried to write  a tree without branchlengths known! calculate them first!")
+    }
+    let mut s = write_node(
+        tree,
+        tree.get_root().unwrap(),
+        Some(translation),
+        &NewickFormat::default(),
+    );
+    s.push(';');
+    s
+}
+
+/// The comments written before a tree. Tree annotations come first with the id, then the
+/// rooting, as in BEAST's `tree STATE_0 [&lnP=-10.5] = [&R] (...)`.
+fn write_tree_comments(tree: &MutableTree) -> String {
+    let mut entries = vec![];
+    if let Some(id) = tree.get_id() {
+        let id = AnnotationValue::Discrete(id.to_string());
+        entries.push(write_annotation(TREE_ID_KEY, Some(&id)));
+    }
+    let mut keys = tree.tree_annotation.keys().collect::<Vec<&String>>();
+    keys.sort();
+    for key in keys {
+        entries.push(write_annotation(key, tree.tree_annotation.get(key)));
+    }
+    let mut s = String::new();
+    if !entries.is_empty() {
+        s.push_str(&format!("[&{}] ", entries.join(",")));
+    }
+    match tree.is_rooted() {
+        Some(true) => s.push_str("[&R] "),
+        Some(false) => s.push_str("[&U] "),
+        None => {}
+    }
+    s
+}
+
+/// Quote a name if it can not be written as is. Names are quoted with single quotes and
+/// any single quotes in them are doubled, so `it's` is written `'it''s'`.
+pub(crate) fn quote_name(name: &str) -> String {
+    let special = |c: char| c.is_whitespace() || "()[]{},:;='\"".contains(c);
+    if name.is_empty() || name.contains(special) {
+        format!("'{}'", name.replace('\'', "''"))
+    } else {
+        name.to_string()
+    }
+}
+
+enum Step {
+    Open(TreeIndex),
+    Comma,
+    Close(TreeIndex),
+}
+
+/// Write the subtree below `node_ref`. An internal node queues its children, the commas
+/// between them and its own ')' in the order they are written.
+fn write_node(
+    tree: &MutableTree,
+    node_ref: TreeIndex,
+    translation: Option<&HashMap<String, String>>,
+    format: &NewickFormat,
+) -> String {
     let mut s = String::new();
-    if tree.is_external(node_ref) {
-        if let Some(taxon_string) = tree.get_taxon(node_ref) {
-            let quoted = taxon_string.contains(char::is_whitespace);
-            if quoted {
-                s.push('\'')
+    let mut stack = vec![Step::Open(node_ref)];
+    while let Some(step) = stack.pop() {
+        match step {
+            Step::Open(node) if tree.is_external(node) => {
+                if let Some(taxon_string) = tree.get_taxon(node) {
+                    match translation.and_then(|map| map.get(taxon_string)) {
+                        Some(key) => s.push_str(key),
+                        None => s.push_str(quote_name(taxon_string).as_str()),
+                    }
+                }
+                write_node_suffix(tree, node, format, &mut s);
+            }
+            Step::Open(node) => {
+                s.push('(');
+                stack.push(Step::Close(node));
+                for (i, child) in tree.get_children(node).into_iter().enumerate().rev() {
+                    stack.push(Step::Open(child));
+                    if i > 0 {
+                        stack.push(Step::Comma);
+                    }
+                }
             }
-            s.push_str(taxon_string);
-            if quoted {
-                s.push('\'')
+            Step::Comma => s.push(','),
+            Step::Close(node) => {
+                s.push(')');
+                write_node_suffix(tree, node, format, &mut s);
             }
         }
-    } else {
-        s.push('(');
-        let children_string = tree
-            .get_children(node_ref)
-            .iter()
-            .map(|child| write_node(tree, *child))
-            .collect::<Vec<String>>()
-            .join(",");
-        s.push_str(&children_string);
-        s.push(')');
     }
-    s.push_str(write_annotations(tree, node_ref).as_str());
-    if let Some(label) = tree.get_node_label(node_ref) {
-        s.push_str(label);
+    s
+}
+
+/// The annotations, label and length written after a node's name or children
+fn write_node_suffix(tree: &MutableTree, node_ref: TreeIndex, format: &NewickFormat, s: &mut String) {
+    if format.annotations && format.dialect == AnnotationDialect::Beast {
+        s.push_str(write_annotations(tree, node_ref, &format.excluded_annotations).as_str());
+    }
+    if format.internal_labels {
+        if let Some(label) = tree.get_node_label(node_ref) {
+            s.push_str(&quote_name(label));
+        }
     }
-    if let Some(l) = tree.get_length(node_ref) {
-        s.push(':');
-        let length = if l < 1e-4 {
-            format!("{:e}", l)
-        } else {
-            l.to_string()
-        };
-        s.push_str(length.as_str());
+    if format.branch_lengths {
+        if let Some(l) = tree.get_length(node_ref) {
+            s.push(':');
+            s.push_str(format.format_length(l).as_str());
+        }
+    }
+    if format.annotations && format.dialect == AnnotationDialect::Nhx {
+        s.push_str(write_nhx_annotations(tree, node_ref, &format.excluded_annotations).as_str());
     }
-    s
 }
 
-fn write_annotations(tree: &MutableTree, node_ref: TreeIndex) -> String {
+fn write_annotations(
+    tree: &MutableTree,
+    node_ref: TreeIndex,
+    excluded: &HashSet<String>,
+) -> String {
     let mut s = String::new();
     let keys = tree.get_annotation_keys();
     if keys.len() > 0 {
         let annotation_string = keys
+            .filter(|k| !excluded.contains(*k))
             .filter(|k| tree.get_annotation(node_ref, k).is_some())
             .map(|k| write_annotation(k, tree.get_annotation(node_ref, k)))
             .collect::<Vec<String>>()
@@ -88,25 +261,74 @@ fn write_annotations(tree: &MutableTree, node_ref: TreeIndex) -> String {
     s
 }
 
+fn write_nhx_annotations(
+    tree: &MutableTree,
+    node_ref: TreeIndex,
+    excluded: &HashSet<String>,
+) -> String {
+    let fields = tree
+        .get_annotation_keys()
+        .filter(|k| !excluded.contains(*k))
+        .filter_map(|k| tree.get_annotation(node_ref, k).map(|v| (k, v)))
+        .map(|(k, v)| format!(":{}={}", k, v.to_string().replace([':', ']'], "_")))
+        .collect::<String>();
+    if fields.is_empty() {
+        fields
+    } else {
+        format!("[&&NHX{}]", fields)
+    }
+}
+
 pub fn write_annotation(key: &str, value: Option<&AnnotationValue>) -> String {
     if let Some(annotation) = value {
-        let value_string = match annotation {
-            AnnotationValue::Discrete(_) => {
-                "\"".to_string() + annotation.to_string().as_str() + "\""
-            }
-            _ => annotation.to_string(),
-        };
-        format!("{}={}", key, value_string)
+        format!("{}={}", quote_name(key), write_annotation_value(annotation))
     } else {
         "".to_string()
     }
 }
 
+/// Write a value so it is read back as the same type. Discrete values are always double
+/// quoted with any double quotes doubled, and whole continuous values keep a decimal point
+/// so they are not read as integers.
+fn write_annotation_value(value: &AnnotationValue) -> String {
+    match value {
+        AnnotationValue::Discrete(s) => format!("\"{}\"", s.replace('"', "\"\"")),
+        AnnotationValue::Continuous(c) => {
+            let s = c.to_string();
+            if c.is_finite() && !s.contains(['.', 'e']) {
+                s + ".0"
+            } else {
+                s
+            }
+        }
+        AnnotationValue::MarkovJump(jump) => format!(
+            "{{{},{},{}}}",
+            write_annotation_value(&AnnotationValue::Continuous(jump.time)),
+            write_annotation_value(&AnnotationValue::Discrete(jump.source.clone())),
+            write_annotation_value(&AnnotationValue::Discrete(jump.destination.clone()))
+        ),
+        AnnotationValue::Set(values) => format!(
+            "{{{}}}",
+            values
+                .iter()
+                .map(write_annotation_value)
+                .collect::<Vec<String>>()
+                .join(",")
+        ),
+        _ => value.to_string(),
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    use super::{write_formatted_newick, write_newick, AnnotationDialect, NewickFormat};
     use crate::io::parser::newick_importer::NewickImporter;
     use crate::tree::fixed_tree::FixedNode;
     use crate::tree::mutable_tree::MutableTree;
+    use crate::tree::AnnotationValue;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+    use std::collections::HashMap;
     use std::io::BufReader;
 
     #[test]
@@ -165,4 +387,273 @@ mod tests {
 
         assert_eq!(tree.to_string(), exp)
     }
+
+    #[test]
+    fn drop_annotations_and_labels() {
+        let s = "((A[&location=\"UK\"]:0.3,B:0.05)label[&location=\"UK\"]:0.9,C:0.1);";
+        let tree =
+            NewickImporter::read_tree(BufReader::new(s.as_bytes())).expect("error in parsing");
+        let mut format = NewickFormat {
+            internal_labels: false,
+            ..Default::default()
+        };
+        format.excluded_annotations.insert("location".to_string());
+        assert_eq!(
+            "((A:0.3,B:0.05):0.9,C:0.1);",
+            write_formatted_newick(&tree, &format)
+        );
+    }
+
+    #[test]
+    fn topology() {
+        let s = "((A[&location=\"UK\"]:0.3,B:0.05)label:0.9,C:0.1);";
+        let tree =
+            NewickImporter::read_tree(BufReader::new(s.as_bytes())).expect("error in parsing");
+        assert_eq!(
+            "((A,B),C);",
+            write_formatted_newick(&tree, &NewickFormat::topology())
+        );
+    }
+
+    #[test]
+    fn precision() {
+        let s = "((A:0.123456,B:0.00001234):1.5,C:12345.678);";
+        let tree =
+            NewickImporter::read_tree(BufReader::new(s.as_bytes())).expect("error in parsing");
+        let format = NewickFormat {
+            precision: Some(2),
+            ..Default::default()
+        };
+        assert_eq!(
+            "((A:0.12,B:0.00):1.50,C:12345.68);",
+            write_formatted_newick(&tree, &format)
+        );
+        let format = NewickFormat {
+            significant_digits: Some(3),
+            ..Default::default()
+        };
+        assert_eq!(
+            "((A:0.123,B:0.0000123):1.5,C:12300);",
+            write_formatted_newick(&tree, &format)
+        );
+    }
+
+    #[test]
+    fn nhx() {
+        let s = "((A[&&NHX:S=human]:0.3,B:0.05)[&&NHX:D=Y]:0.9,C:0.1);";
+        let tree =
+            NewickImporter::read_tree(BufReader::new(s.as_bytes())).expect("error in parsing");
+        assert_eq!(
+            "((A[&S=\"human\"]:0.3,B:0.05)[&D=\"Y\"]:0.9,C:0.1);",
+            tree.to_string()
+        );
+        let format = NewickFormat {
+            dialect: AnnotationDialect::Nhx,
+            ..Default::default()
+        };
+        assert_eq!(
+            "((A:0.3[&&NHX:S=human],B:0.05):0.9[&&NHX:D=Y],C:0.1);",
+            write_formatted_newick(&tree, &format)
+        );
+    }
+
+    #[test]
+    fn intervals_and_integers() {
+        let s = "((A[&count=3,height_95%_HPD={0.5,1.25}]:0.3,B[&count=2,height_95%_HPD={0,0.1}]:0.05):0.9,C:0.1);";
+        let tree =
+            NewickImporter::read_tree(BufReader::new(s.as_bytes())).expect("error in parsing");
+        let written = tree.to_string();
+        let read =
+            NewickImporter::read_tree(BufReader::new(written.as_bytes())).expect("error in parsing");
+        let a = read.get_taxon_node("A").unwrap();
+        assert_eq!(
+            Some((0.5, 1.25)),
+            read.get_annotation(a, "height_95%_HPD")
+                .and_then(|hpd| hpd.as_interval())
+        );
+        assert_eq!(
+            Some(&crate::tree::AnnotationValue::Integer(3)),
+            read.get_annotation(a, "count")
+        );
+    }
+
+    #[test]
+    fn tree_annotations() {
+        let nexus = "#NEXUS
+        BEGIN TREES;
+        TREE STATE_10 [&lnP=-10.5,posterior=-3] = [&U] ((A:1,B:1):1,C:2);
+        END;";
+        let tree = crate::io::parser::nexus_importer::NexusImporter::from_reader(nexus.as_bytes())
+            .next()
+            .unwrap();
+        let written = tree.to_string();
+        assert_eq!(
+            "[&id=\"STATE_10\",lnP=-10.5,posterior=-3] [&U] ((A:1,B:1):1,C:2);",
+            written
+        );
+        let read =
+            NewickImporter::read_tree(BufReader::new(written.as_bytes())).expect("error in parsing");
+        assert_eq!(Some("STATE_10"), read.get_id());
+        assert_eq!(Some(false), read.is_rooted());
+        assert_eq!(
+            Some(&crate::tree::AnnotationValue::Continuous(-10.5)),
+            read.tree_annotation.get("lnP")
+        );
+        // the comments are not taken as annotations on the first tip
+        let a = read.get_taxon_node("A").unwrap();
+        assert!(read.get_annotation(a, "U").is_none());
+        let format = NewickFormat {
+            tree_annotations: false,
+            ..Default::default()
+        };
+        assert_eq!("((A:1,B:1):1,C:2);", write_formatted_newick(&read, &format));
+    }
+
+    #[test]
+    fn quoted_names() {
+        let s = "(('it''s (a) [b]':1,\"x,y\":2)'l ; 1':1,C:1);";
+        let tree =
+            NewickImporter::read_tree(BufReader::new(s.as_bytes())).expect("error in parsing");
+        assert!(tree.get_taxon_node("it's (a) [b]").is_some());
+        assert!(tree.get_taxon_node("x,y").is_some());
+        assert!(tree.get_label_node("l ; 1").is_some());
+        assert_eq!(
+            "(('it''s (a) [b]':1,'x,y':2)'l ; 1':1,C:1);",
+            tree.to_string()
+        );
+    }
+
+    #[test]
+    fn quoted_annotation_values() {
+        let s = "(A[&'a key'=\"say \"\"hi\"\" [1]\",flag,ok=false]:1,B:1);";
+        let tree =
+            NewickImporter::read_tree(BufReader::new(s.as_bytes())).expect("error in parsing");
+        let a = tree.get_taxon_node("A").unwrap();
+        assert_eq!(
+            Some(&AnnotationValue::Discrete("say \"hi\" [1]".to_string())),
+            tree.get_annotation(a, "a key")
+        );
+        assert_eq!(
+            Some(&AnnotationValue::Boolean(true)),
+            tree.get_annotation(a, "flag")
+        );
+        assert_eq!(
+            Some(&AnnotationValue::Boolean(false)),
+            tree.get_annotation(a, "ok")
+        );
+    }
+
+    const NAME_CHARS: &[char] = &[
+        'a', 'B', '1', '_', '.', '-', ' ', '\'', '"', '(', ')', '[', ']', '{', '}', ',', ':', ';',
+        '=', '&', 'é',
+    ];
+
+    fn random_name(rng: &mut StdRng) -> String {
+        (0..rng.gen_range(1..6))
+            .map(|_| NAME_CHARS[rng.gen_range(0..NAME_CHARS.len())])
+            .collect()
+    }
+
+    // a key keeps one type as mixed types are converted to a common one
+    fn random_value(rng: &mut StdRng, kind: usize) -> AnnotationValue {
+        match kind {
+            0 => AnnotationValue::Discrete(random_name(rng)),
+            1 => AnnotationValue::Integer(rng.gen_range(-1000..1000)),
+            2 => AnnotationValue::Continuous(rng.gen_range(-100.0..100.0)),
+            3 => AnnotationValue::Boolean(rng.gen()),
+            4 => AnnotationValue::Interval(rng.gen_range(-1.0..0.0), rng.gen_range(0.0..1.0)),
+            5 => AnnotationValue::Set(
+                (0..rng.gen_range(1..5))
+                    .map(|_| match rng.gen_bool(0.5) {
+                        true => AnnotationValue::Integer(rng.gen_range(-1000..1000)),
+                        false => AnnotationValue::Continuous(rng.gen_range(-100.0..100.0)),
+                    })
+                    .collect(),
+            ),
+            _ => AnnotationValue::Set(
+                (0..rng.gen_range(1..4))
+                    .map(|_| AnnotationValue::Discrete(random_name(rng)))
+                    .collect(),
+            ),
+        }
+    }
+
+    fn random_node(
+        rng: &mut StdRng,
+        keys: &[(String, usize)],
+        tips: &mut usize,
+        depth: usize,
+    ) -> FixedNode {
+        let mut node = FixedNode::new();
+        node.length = Some(rng.gen_range(0.0..2.0));
+        let mut annotations = HashMap::new();
+        for (key, kind) in keys {
+            if rng.gen_bool(0.3) {
+                annotations.insert(key.clone(), random_value(rng, *kind));
+            }
+        }
+        if !annotations.is_empty() {
+            node.annotations = Some(annotations);
+        }
+        if depth == 0 || rng.gen_bool(0.3) {
+            // the counter keeps taxa unique
+            node.taxon = Some(format!("{}{}", random_name(rng), tips));
+            *tips += 1;
+        } else {
+            if rng.gen_bool(0.5) {
+                node.label = Some(random_name(rng));
+            }
+            node.children = (0..rng.gen_range(2..4))
+                .map(|_| random_node(rng, keys, tips, depth - 1))
+                .collect();
+        }
+        node
+    }
+
+    #[test]
+    fn random_round_trip() {
+        let mut rng = StdRng::seed_from_u64(19);
+        for _ in 0..200 {
+            let keys = (0..7)
+                .map(|kind| {
+                    // pairs of numbers are only intervals under interval keys
+                    let suffix = if kind == 4 { "_HPD" } else { "" };
+                    (format!("{}{}{}", random_name(&mut rng), kind, suffix), kind)
+                })
+                .collect::<Vec<(String, usize)>>();
+            let mut tips = 0;
+            let mut root = random_node(&mut rng, &keys, &mut tips, 4);
+            root.length = None;
+            if root.children.is_empty() {
+                continue;
+            }
+            let tree = MutableTree::from_fixed_node(root);
+            let written = write_newick(&tree);
+            let read = NewickImporter::read_tree(BufReader::new(written.as_bytes()))
+                .unwrap_or_else(|e| panic!("could not read {}: {}", written, e));
+
+            let nodes = tree.preorder_iter().collect::<Vec<_>>();
+            let read_nodes = read.preorder_iter().collect::<Vec<_>>();
+            assert_eq!(nodes.len(), read_nodes.len(), "{}", written);
+            for (node, read_node) in nodes.into_iter().zip(read_nodes) {
+                assert_eq!(tree.get_taxon(node), read.get_taxon(read_node), "{}", written);
+                assert_eq!(
+                    tree.get_node_label(node),
+                    read.get_node_label(read_node),
+                    "{}",
+                    written
+                );
+                assert_eq!(tree.get_length(node), read.get_length(read_node), "{}", written);
+                for (key, _) in &keys {
+                    assert_eq!(
+                        tree.get_annotation(node, key),
+                        read.get_annotation(read_node, key),
+                        "{} in {}",
+                        key,
+                        written
+                    );
+                }
+            }
+        }
+    }
 }
@@ -0,0 +1,234 @@
+use crate::io::writer::newick_writer::{quote_name, write_annotation, write_translated_newick};
+use crate::tree::mutable_tree::MutableTree;
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind, Result, Write};
+
+/// Writes trees to a nexus file one at a time so large posterior sets can be converted
+/// without holding them all in memory.
+///
+/// The TAXA block and TRANSLATE table are built from the taxa of the first tree written.
+/// All subsequent trees must be on the same set of taxa.
+pub struct NexusWriter<W: Write> {
+    writer: W,
+    taxa: Vec<String>,
+    translation: HashMap<String, String>,
+    trees_written: usize,
+    closed: bool,
+}
+
+impl<W: Write> NexusWriter<W> {
+    pub fn new(writer: W) -> Self {
+        NexusWriter {
+            writer,
+            taxa: vec![],
+            translation: HashMap::new(),
+            trees_written: 0,
+            closed: false,
+        }
+    }
+
+    pub fn write_tree(&mut self, tree: &MutableTree) -> Result<()> {
+        if self.trees_written == 0 {
+            self.write_header(tree)?;
+        } else {
+            self.check_taxa(tree)?;
+        }
+        let id = match tree.get_id() {
+            Some(id) => id.to_string(),
+            None => format!("tree_{}", self.trees_written),
+        };
+        writeln!(
+            self.writer,
+            "tree {}{} = {} {}",
+            quote_name(&id),
+            write_tree_annotations(tree),
+            rooted_comment(tree),
+            write_translated_newick(tree, &self.translation)
+        )?;
+        self.trees_written += 1;
+        Ok(())
+    }
+
+    /// End the TREES block. If no trees were written an empty file is left.
+    pub fn finish(&mut self) -> Result<()> {
+        if !self.closed && self.trees_written > 0 {
+            writeln!(self.writer, "End;")?;
+        }
+        self.closed = true;
+        self.writer.flush()
+    }
+
+    fn write_header(&mut self, tree: &MutableTree) -> Result<()> {
+        self.taxa = tree
+            .external_nodes
+            .iter()
+            .filter_map(|node| tree.get_taxon(*node))
+            .map(String::from)
+            .collect();
+        self.translation = self
+            .taxa
+            .iter()
+            .enumerate()
+            .map(|(i, taxon)| (taxon.clone(), (i + 1).to_string()))
+            .collect();
+
+        writeln!(self.writer, "#NEXUS")?;
+        writeln!(self.writer)?;
+        writeln!(self.writer, "Begin taxa;")?;
+        writeln!(self.writer, "\tDimensions ntax={};", self.taxa.len())?;
+        writeln!(self.writer, "\tTaxlabels")?;
+        for taxon in self.taxa.iter() {
+            writeln!(self.writer, "\t\t{}", quote_name(taxon))?;
+        }
+        writeln!(self.writer, "\t\t;")?;
+        writeln!(self.writer, "End;")?;
+        writeln!(self.writer)?;
+        writeln!(self.writer, "Begin trees;")?;
+        writeln!(self.writer, "\tTranslate")?;
+        let translate_table = self
+            .taxa
+            .iter()
+            .map(|taxon| format!("\t\t{} {}", self.translation[taxon], quote_name(taxon)))
+            .collect::<Vec<String>>()
+            .join(",\n");
+        writeln!(self.writer, "{}", translate_table)?;
+        writeln!(self.writer, ";")?;
+        Ok(())
+    }
+
+    fn check_taxa(&self, tree: &MutableTree) -> Result<()> {
+        for node in tree.external_nodes.iter() {
+            if let Some(taxon) = tree.get_taxon(*node) {
+                if !self.translation.contains_key(taxon) {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        format!(
+                            "taxon {} was not in the first tree written to the nexus file",
+                            taxon
+                        ),
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> Drop for NexusWriter<W> {
+    fn drop(&mut self) {
+        if !self.closed {
+            let _ = self.finish();
+        }
+    }
+}
+
+fn rooted_comment(tree: &MutableTree) -> &'static str {
+    if tree.is_rooted() == Some(false) {
+        "[&U]"
+    } else {
+        "[&R]"
+    }
+}
+
+fn write_tree_annotations(tree: &MutableTree) -> String {
+    let mut keys = tree.tree_annotation.keys().collect::<Vec<&String>>();
+    if keys.is_empty() {
+        return "".to_string();
+    }
+    keys.sort();
+    let annotation_string = keys
+        .into_iter()
+        .map(|k| write_annotation(k, tree.tree_annotation.get(k)))
+        .collect::<Vec<String>>()
+        .join(",");
+    format!(" [&{}]", annotation_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::parser::newick_importer::NewickImporter;
+    use crate::io::parser::nexus_importer::NexusImporter;
+    use crate::tree::AnnotationValue;
+    use std::io::BufReader;
+
+    #[test]
+    fn header_and_translation() {
+        let tree = NewickImporter::read_tree(BufReader::new(
+            "((A:1,B:1)[&location=\"UK\"]:1,C:2);".as_bytes(),
+        ))
+        .unwrap();
+        let mut out = vec![];
+        {
+            let mut writer = NexusWriter::new(&mut out);
+            writer.write_tree(&tree).unwrap();
+            writer.finish().unwrap();
+        }
+        let exp = "#NEXUS
+
+Begin taxa;
+\tDimensions ntax=3;
+\tTaxlabels
+\t\tA
+\t\tB
+\t\tC
+\t\t;
+End;
+
+Begin trees;
+\tTranslate
+\t\t1 A,
+\t\t2 B,
+\t\t3 C
+;
+tree tree_0 = [&R] ((1:1,2:1)[&location=\"UK\"]:1,3:2);
+End;
+";
+        assert_eq!(exp, String::from_utf8(out).unwrap());
+    }
+
+    #[test]
+    fn round_trip() {
+        let nexus = "#NEXUS
+        BEGIN TAXA;
+        DIMENSIONS NTAX=3;
+        TAXLABELS A B 'C d';
+        END;
+        BEGIN TREES;
+        TREE STATE_0 [&lnP=-10.5] = [&R] ((A[&location=\"UK\"]:1,B:1):1,'C d':2);
+        TREE STATE_1 [&lnP=-9.5] = [&R] ((A:1,'C d':1):1,B:2);
+        END;";
+        let trees = NexusImporter::from_reader(nexus.as_bytes());
+        let mut out = vec![];
+        {
+            let mut writer = NexusWriter::new(&mut out);
+            for tree in trees {
+                writer.write_tree(&tree).unwrap();
+            }
+        }
+        let written = String::from_utf8(out).unwrap();
+        let trees = NexusImporter::from_reader(written.as_bytes()).collect::<Vec<MutableTree>>();
+        assert_eq!(2, trees.len());
+        assert_eq!(Some("STATE_1"), trees[1].get_id());
+        assert_eq!(
+            Some(&AnnotationValue::Continuous(-9.5)),
+            trees[1].tree_annotation.get("lnP")
+        );
+        let a = trees[0].get_taxon_node("A").unwrap();
+        assert_eq!(
+            Some(&AnnotationValue::Discrete("UK".to_string())),
+            trees[0].get_annotation(a, "location")
+        );
+        assert!(trees[0].get_taxon_node("C d").is_some());
+    }
+
+    #[test]
+    fn new_taxa_error() {
+        let first = NewickImporter::read_tree(BufReader::new("(A:1,B:1);".as_bytes())).unwrap();
+        let second = NewickImporter::read_tree(BufReader::new("(A:1,C:1);".as_bytes())).unwrap();
+        let mut out = vec![];
+        let mut writer = NexusWriter::new(&mut out);
+        writer.write_tree(&first).unwrap();
+        assert!(writer.write_tree(&second).is_err());
+    }
+}
@@ -0,0 +1,266 @@
+use crate::tree::mutable_tree::{MutableTree, TreeIndex};
+use crate::tree::AnnotationValue;
+use quick_xml::escape::escape;
+use std::collections::HashMap;
+use std::io::{Result, Write};
+
+/// Annotation keys written as `<confidence>` elements rather than properties.
+pub const CONFIDENCE_KEYS: [&str; 5] = [
+    "confidence",
+    "bootstrap",
+    "posterior",
+    "probability",
+    "support",
+];
+
+/// Nesting levels after which clades are not indented further
+const MAX_INDENT: usize = 32;
+
+/// Writes trees to a PhyloXML document, one `<phylogeny>` per tree.
+///
+/// Internal node labels and taxa are written as clade names. Continuous annotations with
+/// a key in [CONFIDENCE_KEYS] are written as `<confidence>` elements and all others as
+/// `<property>` elements with a "fertree:" ref so they can be read back by
+/// [PhyloXmlImporter](crate::io::parser::phyloxml_importer::PhyloXmlImporter).
+pub struct PhyloXmlWriter<W: Write> {
+    writer: W,
+    trees_written: usize,
+    closed: bool,
+}
+
+impl<W: Write> PhyloXmlWriter<W> {
+    pub fn new(writer: W) -> Self {
+        PhyloXmlWriter {
+            writer,
+            trees_written: 0,
+            closed: false,
+        }
+    }
+
+    pub fn write_tree(&mut self, tree: &MutableTree) -> Result<()> {
+        if self.trees_written == 0 {
+            writeln!(self.writer, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>")?;
+            writeln!(
+                self.writer,
+                "<phyloxml xmlns:xsi=\"http://www.w3.org/2001/XMLSchema-instance\" xsi:schemaLocation=\"http://www.phyloxml.org http://www.phyloxml.org/1.10/phyloxml.xsd\" xmlns=\"http://www.phyloxml.org\">"
+            )?;
+        }
+        let rooted = tree.is_rooted() != Some(false);
+        writeln!(self.writer, "  <phylogeny rooted=\"{}\">", rooted)?;
+        if let Some(id) = tree.get_id() {
+            writeln!(self.writer, "    <name>{}</name>", escape(id))?;
+        }
+        if let Some(root) = tree.get_root() {
+            self.write_clade(tree, root, 2)?;
+        }
+        self.write_tree_annotations(tree)?;
+        writeln!(self.writer, "  </phylogeny>")?;
+        self.trees_written += 1;
+        Ok(())
+    }
+
+    /// Close the document. If no trees were written an empty file is left.
+    pub fn finish(&mut self) -> Result<()> {
+        if !self.closed && self.trees_written > 0 {
+            writeln!(self.writer, "</phyloxml>")?;
+        }
+        self.closed = true;
+        self.writer.flush()
+    }
+
+    /// Write the clade below `root` and all those within it. Each clade is visited once
+    /// to open it and once to close it, and the indentation stops growing after
+    /// [MAX_INDENT] levels so the output stays linear in the tree size.
+    fn write_clade(&mut self, tree: &MutableTree, root: TreeIndex, depth: usize) -> Result<()> {
+        // the node, its depth and whether its clade is being closed
+        let mut stack = vec![(root, depth, false)];
+        while let Some((node, depth, close)) = stack.pop() {
+            let indent = "  ".repeat(depth.min(MAX_INDENT));
+            if close {
+                writeln!(self.writer, "{}</clade>", indent)?;
+                continue;
+            }
+            writeln!(self.writer, "{}<clade>", indent)?;
+            let name = if tree.is_external(node) {
+                tree.get_taxon(node)
+            } else {
+                tree.get_label(node)
+            };
+            if let Some(name) = name {
+                writeln!(self.writer, "{}  <name>{}</name>", indent, escape(name))?;
+            }
+            if let Some(length) = tree.get_length(node) {
+                writeln!(
+                    self.writer,
+                    "{}  <branch_length>{}</branch_length>",
+                    indent, length
+                )?;
+            }
+            let annotations = sorted_annotations(&tree.get_node(node).unwrap().annotations);
+            for (key, value) in annotations.iter() {
+                if let (true, AnnotationValue::Continuous(c)) = (is_confidence(key), value) {
+                    writeln!(
+                        self.writer,
+                        "{}  <confidence type=\"{}\">{}</confidence>",
+                        indent,
+                        escape(key.as_str()),
+                        c
+                    )?;
+                }
+            }
+            for (key, value) in annotations.iter() {
+                if !(is_confidence(key) && matches!(value, AnnotationValue::Continuous(_))) {
+                    write_property(&mut self.writer, &indent, key, value, "clade")?;
+                }
+            }
+            stack.push((node, depth, true));
+            stack.extend(
+                tree.get_children(node)
+                    .into_iter()
+                    .rev()
+                    .map(|child| (child, depth + 1, false)),
+            );
+        }
+        Ok(())
+    }
+
+    fn write_tree_annotations(&mut self, tree: &MutableTree) -> Result<()> {
+        for (key, value) in sorted_annotations(&tree.tree_annotation) {
+            write_property(&mut self.writer, "  ", key, value, "phylogeny")?;
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> Drop for PhyloXmlWriter<W> {
+    fn drop(&mut self) {
+        if !self.closed {
+            let _ = self.finish();
+        }
+    }
+}
+
+fn is_confidence(key: &str) -> bool {
+    CONFIDENCE_KEYS.contains(&key)
+}
+
+fn sorted_annotations(
+    annotations: &HashMap<String, AnnotationValue>,
+) -> Vec<(&String, &AnnotationValue)> {
+    let mut annotations = annotations.iter().collect::<Vec<(&String, &AnnotationValue)>>();
+    annotations.sort_by(|a, b| a.0.cmp(b.0));
+    annotations
+}
+
+fn write_property<W: Write>(
+    writer: &mut W,
+    indent: &str,
+    key: &str,
+    value: &AnnotationValue,
+    applies_to: &str,
+) -> Result<()> {
+    let datatype = match value {
+        AnnotationValue::Integer(_) => "xsd:integer",
+        AnnotationValue::Continuous(_) => "xsd:double",
+        AnnotationValue::Boolean(_) => "xsd:boolean",
+        _ => "xsd:string",
+    };
+    writeln!(
+        writer,
+        "{}  <property ref=\"fertree:{}\" datatype=\"{}\" applies_to=\"{}\">{}</property>",
+        indent,
+        escape(key),
+        datatype,
+        applies_to,
+        escape(value.to_string().as_str())
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::parser::newick_importer::NewickImporter;
+    use crate::io::parser::phyloxml_importer::PhyloXmlImporter;
+    use crate::tree::test_utils;
+    use std::io::BufReader;
+
+    #[test]
+    fn write() {
+        let tree = NewickImporter::read_tree(BufReader::new(
+            "((A:1,B<1>:1)[&posterior=0.9]:1,C:2);".as_bytes(),
+        ))
+        .unwrap();
+        let mut out = vec![];
+        {
+            let mut writer = PhyloXmlWriter::new(&mut out);
+            writer.write_tree(&tree).unwrap();
+        }
+        let written = String::from_utf8(out).unwrap();
+        assert!(written.contains("<name>B&lt;1&gt;</name>"));
+        assert!(written.contains("<confidence type=\"posterior\">0.9</confidence>"));
+        assert!(written.ends_with("</phyloxml>\n"));
+    }
+
+    #[test]
+    fn round_trip() {
+        let s = "((A[&location=\"UK\",rate=0.5,height_95%_HPD={1.0,2.5}]:1,B:1)label[&posterior=0.9,resolved=true]:1,'C d':2);";
+        let mut tree = NewickImporter::read_tree(BufReader::new(s.as_bytes())).unwrap();
+        tree.set_id("STATE_0".to_string());
+        tree.annotate_tree("lnP".to_string(), AnnotationValue::Continuous(-10.5));
+        let mut out = vec![];
+        {
+            let mut writer = PhyloXmlWriter::new(&mut out);
+            writer.write_tree(&tree).unwrap();
+            writer.write_tree(&tree).unwrap();
+        }
+        let trees = PhyloXmlImporter::from_reader(out.as_slice()).collect::<Vec<MutableTree>>();
+        assert_eq!(2, trees.len());
+        let read = &trees[1];
+        assert_eq!(Some("STATE_0"), read.get_id());
+        assert_eq!(
+            Some(&AnnotationValue::Continuous(-10.5)),
+            read.tree_annotation.get("lnP")
+        );
+        for node in tree.preorder_iter() {
+            let read_node = match tree.get_taxon(node) {
+                Some(taxon) => read.get_taxon_node(taxon),
+                None => read.get_label_node(tree.get_label(node).unwrap_or("")),
+            };
+            if let Some(read_node) = read_node {
+                assert_eq!(
+                    tree.get_node(node).unwrap().annotations,
+                    read.get_node(read_node).unwrap().annotations
+                );
+                assert_eq!(tree.get_length(node), read.get_length(read_node));
+            }
+        }
+        let a = read.get_taxon_node("A").unwrap();
+        assert_eq!(
+            Some(&AnnotationValue::Discrete("UK".to_string())),
+            read.get_annotation(a, "location")
+        );
+        assert!(read.get_taxon_node("C d").is_some());
+        assert!(read.get_label_node("label").is_some());
+    }
+
+    #[test]
+    fn deep_ladder() {
+        let depth = 100_000;
+        let tree = NewickImporter::read_tree(BufReader::new(
+            test_utils::deep_ladder(depth).as_bytes(),
+        ))
+        .unwrap();
+        let mut out = vec![];
+        {
+            let mut writer = PhyloXmlWriter::new(&mut out);
+            writer.write_tree(&tree).unwrap();
+        }
+        let indent = "  ".repeat(MAX_INDENT);
+        assert!(String::from_utf8_lossy(&out).contains(&format!("\n{}<clade>", indent)));
+        assert!(!String::from_utf8_lossy(&out).contains(&format!("\n{}  <clade>", indent)));
+        let read = PhyloXmlImporter::from_reader(out.as_slice()).next().unwrap();
+        assert_eq!(depth + 1, read.get_external_node_count());
+        let t0 = read.get_taxon_node("t0").unwrap();
+        assert_eq!(depth, read.ancestors(t0).count());
+    }
+}
@@ -0,0 +1,101 @@
+use crate::io::parser::chained_importer::SourceTracker;
+use std::io::{Result, Write};
+
+/// Adds a column naming the input file to tab separated output.
+///
+/// If the output has a header its first line gets a `file` column. Every other line is
+/// prefixed with the file in `source` when the line is started, so the writer of the
+/// rows must keep it pointing at the file of the tree the rows are for.
+pub struct SourceColumnWriter<W: Write> {
+    writer: W,
+    source: SourceTracker,
+    at_line_start: bool,
+    header_written: bool,
+}
+
+impl<W: Write> SourceColumnWriter<W> {
+    pub fn new(writer: W, source: SourceTracker, has_header: bool) -> Self {
+        SourceColumnWriter {
+            writer,
+            source,
+            at_line_start: true,
+            header_written: !has_header,
+        }
+    }
+
+    fn write_prefix(&mut self) -> Result<()> {
+        if self.header_written {
+            let source = self
+                .source
+                .lock()
+                .unwrap()
+                .as_ref()
+                .map(|path| path.display().to_string())
+                .unwrap_or_default();
+            write!(self.writer, "{}\t", source)
+        } else {
+            write!(self.writer, "file\t")
+        }
+    }
+}
+
+impl<W: Write> Write for SourceColumnWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        for line in buf.split_inclusive(|b| *b == b'\n') {
+            if self.at_line_start {
+                self.write_prefix()?;
+            }
+            self.writer.write_all(line)?;
+            self.at_line_start = line.ends_with(b"\n");
+            if self.at_line_start {
+                self.header_written = true;
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn prefix_lines() {
+        let source: SourceTracker = Arc::new(Mutex::new(None));
+        let mut out = vec![];
+        {
+            let mut writer = SourceColumnWriter::new(&mut out, Arc::clone(&source), true);
+            writeln!(writer, "tree\ttips").unwrap();
+            *source.lock().unwrap() = Some(PathBuf::from("run1.trees"));
+            write!(writer, "0\t").unwrap();
+            writeln!(writer, "3").unwrap();
+            *source.lock().unwrap() = Some(PathBuf::from("run2.trees"));
+            writeln!(writer, "0\t4").unwrap();
+        }
+        assert_eq!(
+            "file\ttree\ttips\nrun1.trees\t0\t3\nrun2.trees\t0\t4\n",
+            String::from_utf8(out).unwrap()
+        );
+    }
+
+    #[test]
+    fn no_header() {
+        let source: SourceTracker = Arc::new(Mutex::new(Some(PathBuf::from("run1.trees"))));
+        let mut out = vec![];
+        {
+            let mut writer = SourceColumnWriter::new(&mut out, Arc::clone(&source), false);
+            writeln!(writer, "A").unwrap();
+            writeln!(writer, "B").unwrap();
+        }
+        assert_eq!(
+            "run1.trees\tA\nrun1.trees\tB\n",
+            String::from_utf8(out).unwrap()
+        );
+    }
+}
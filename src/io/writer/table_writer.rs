@@ -0,0 +1,101 @@
+use crate::tree::mutable_tree::MutableTree;
+use std::io::{Result, Write};
+
+/// Columns written before the annotations. [TableImporter](crate::io::parser::table_importer::TableImporter)
+/// reads the same names back.
+pub const TABLE_COLUMNS: [&str; 7] = ["tree", "node", "parent", "taxon", "label", "length", "height"];
+
+/// Writes trees as a tab separated node table with one row per node.
+///
+/// Nodes are identified by their index in the tree and listed in preorder so parents come
+/// before their children. The annotation columns are taken from the first tree written.
+/// The tree column holds the tree id, or its 0 based index if it has no id.
+pub struct TableWriter<W: Write> {
+    writer: W,
+    annotations: Vec<String>,
+    trees_written: usize,
+}
+
+impl<W: Write> TableWriter<W> {
+    pub fn new(writer: W) -> Self {
+        TableWriter {
+            writer,
+            annotations: vec![],
+            trees_written: 0,
+        }
+    }
+
+    /// Node heights are written if they have been calculated.
+    pub fn write_tree(&mut self, tree: &MutableTree) -> Result<()> {
+        if self.trees_written == 0 {
+            self.annotations = tree.annotation_type.keys().cloned().collect();
+            self.annotations.sort();
+            let mut header = TABLE_COLUMNS.join("\t");
+            for key in self.annotations.iter() {
+                header.push('\t');
+                header.push_str(key);
+            }
+            writeln!(self.writer, "{}", header)?;
+        }
+        let id = match tree.get_id() {
+            Some(id) => id.to_string(),
+            None => self.trees_written.to_string(),
+        };
+        for node in tree.preorder_iter() {
+            let mut row = vec![
+                id.clone(),
+                node.to_string(),
+                option_string(tree.get_parent(node)),
+                tree.get_taxon(node).unwrap_or("").to_string(),
+                tree.get_label(node)
+                    .filter(|_| tree.is_internal(node))
+                    .unwrap_or("")
+                    .to_string(),
+                option_string(tree.get_length(node)),
+                option_string(tree.get_height(node).filter(|_| tree.heights_known)),
+            ];
+            for key in self.annotations.iter() {
+                row.push(option_string(tree.get_annotation(node, key)));
+            }
+            writeln!(self.writer, "{}", row.join("\t"))?;
+        }
+        self.trees_written += 1;
+        Ok(())
+    }
+
+    pub fn finish(&mut self) -> Result<()> {
+        self.writer.flush()
+    }
+}
+
+fn option_string<T: ToString>(value: Option<T>) -> String {
+    value.map(|v| v.to_string()).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::parser::newick_importer::NewickImporter;
+    use std::io::BufReader;
+
+    #[test]
+    fn table() {
+        let tree = NewickImporter::read_tree(BufReader::new(
+            "((A[&location=\"UK\"]:1,B:2)label:1,C:2);".as_bytes(),
+        ))
+        .unwrap();
+        let mut out = vec![];
+        {
+            let mut writer = TableWriter::new(&mut out);
+            writer.write_tree(&tree).unwrap();
+        }
+        let exp = "tree\tnode\tparent\ttaxon\tlabel\tlength\theight\tlocation
+0\t4\t\t\t\t\t\t
+0\t2\t4\t\tlabel\t1\t\t
+0\t0\t2\tA\t\t1\t\tUK
+0\t1\t2\tB\t\t2\t\t
+0\t3\t4\tC\t\t2\t\t
+";
+        assert_eq!(exp, String::from_utf8(out).unwrap());
+    }
+}
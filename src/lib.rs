@@ -4,5 +4,6 @@ extern crate pest_derive;
 #[macro_use]
 extern crate log;
 
+pub mod alignment;
 pub mod io;
 pub mod tree;
@@ -2,12 +2,14 @@ mod commands;
 
 // use commands::{split, transmission_lineage};
 // use commands::{annotate, clades, extract, resolve, stats};
-use rebl::io::parser::nexus_importer::NexusImporter;
+use rebl::io::compression;
+use rebl::io::parser::auto_importer::{AutoImporter, TreeFormat};
+use rebl::io::parser::chained_importer::ChainedImporter;
+use rebl::io::parser::sampled_importer::{Burnin, SampledImporter};
 use rebl::io::parser::tree_importer::TreeImporter;
-use rebl::io::parser::{newick_importer, nexus_importer};
+use rebl::io::writer::source_column::SourceColumnWriter;
 use std::error::Error;
-use std::fs::File;
-use std::io::StdinLock;
+use std::io::{BufWriter, Read, Write};
 use std::{io, path};
 use structopt::StructOpt;
 
@@ -142,6 +144,19 @@ enum Fertree {
         )]
         lag: Option<f64>,
     },
+    /// Restrict an alignment to the tips of the first tree and order it as in the tree.
+    ///
+    /// Taxa found in only the tree or only the alignment are reported.
+    MatchAlignment {
+        #[structopt(long, parse(from_os_str), help = "FASTA or nexus alignment")]
+        alignment: path::PathBuf,
+        #[structopt(
+            long,
+            parse(from_os_str),
+            help = "file to write the trees pruned to the taxa in the alignment"
+        )]
+        pruned_trees: Option<path::PathBuf>,
+    },
     /// Commands to modify branch lengths
     Brlen {
         #[structopt(subcommand)]
@@ -156,18 +171,68 @@ enum Fertree {
 
 #[derive(Debug, StructOpt)]
 pub struct Common {
-    #[structopt(short, long, global = true, help = "tree is in nexus format")]
+    #[structopt(
+        short,
+        long,
+        global = true,
+        help = "tree is in nexus format. By default the format is detected from the input"
+    )]
     nexus: bool,
+    #[structopt(
+        long,
+        global = true,
+        conflicts_with = "nexus",
+        help = "input format (newick, nexus, phyloxml, json or table) if it can't be detected"
+    )]
+    input_format: Option<TreeFormat>,
     #[structopt(
         short,
         long,
         parse(from_os_str),
-        help = "input tree file",
+        number_of_values = 1,
+        help = "input tree file. Can be given more than once and may be a glob such as 'runs/*.trees'",
         global = true
     )]
-    infile: Option<path::PathBuf>,
-    // #[structopt(short, long, parse(from_os_str), help = "output tree file", global = true)]
-    // outfile: Option<path::PathBuf>,
+    infile: Vec<path::PathBuf>,
+    #[structopt(
+        long,
+        global = true,
+        help = "prefix tree ids with the name of the file they came from"
+    )]
+    prefix_ids: bool,
+    #[structopt(
+        long,
+        global = true,
+        help = "add a first column naming the input file to tabular output"
+    )]
+    source_column: bool,
+    #[structopt(
+        long,
+        global = true,
+        help = "trees to drop from the start of each input. A whole number is a count and a decimal a fraction, e.g. 0.1"
+    )]
+    burnin: Option<Burnin>,
+    #[structopt(
+        long,
+        global = true,
+        default_value = "1",
+        help = "keep every nth tree after the burnin"
+    )]
+    thin: usize,
+    #[structopt(
+        long,
+        global = true,
+        default_value = "1",
+        help = "threads used to process trees in brlen, prune, resolve, clades and transmission-lineages. 0 uses one per core"
+    )]
+    threads: usize,
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help = "output file. Compressed if the name ends in .gz or .zst",
+        global = true
+    )]
+    outfile: Option<path::PathBuf>,
     // //TODO implement this log file option
     // #[structopt(short, long, parse(from_os_str), help = "logfile", global = true)]
     // logfile: Option<path::PathBuf>,
@@ -179,32 +244,71 @@ fn main() {
     let args = Cli::from_args();
     debug!("{:?}", args);
     let start = std::time::Instant::now();
-    let stdin = io::stdin();
-    let result = match args.common.infile {
-        Some(path) => {
-            if args.common.nexus {
-                let importer: NexusImporter<File> = nexus_importer::NexusImporter::from_reader(
-                    File::open(path).expect("issue with path "),
-                );
-                run_commands(importer, args.cmd)
-            } else {
-                let importer = newick_importer::NewickImporter::from_reader(
-                    File::open(path).expect("issue with path "),
-                );
-                run_commands(importer, args.cmd)
+    let mut outfile = args
+        .common
+        .outfile
+        .map(|path| compression::create_writer(&path).expect("issue with output path "));
+    let mut stdout = BufWriter::new(io::stdout().lock());
+    let mut output: &mut dyn Write = match outfile.as_mut() {
+        Some(file) => file,
+        None => &mut stdout,
+    };
+    let format = if args.common.nexus {
+        Some(TreeFormat::Nexus)
+    } else {
+        args.common.input_format
+    };
+    let burnin = args.common.burnin.unwrap_or_default();
+    let thin = args.common.thin;
+    let result = if thin == 0 {
+        Err("--thin must be at least 1".into())
+    } else if args.common.infile.is_empty() {
+        let input: Box<dyn Read> = Box::new(io::stdin().lock());
+        let importer = match format {
+            Some(format) => AutoImporter::with_format(input, format),
+            None => AutoImporter::from_reader(input),
+        };
+        match (importer, burnin) {
+            (Ok(_), Burnin::Fraction(_)) => {
+                Err("a fractional --burnin needs an input file so the trees can be counted".into())
+            }
+            (Ok(importer), Burnin::Trees(n)) => {
+                let importer = SampledImporter::new(importer, n, thin);
+                run_commands(importer, &mut output, args.cmd, args.common.threads)
             }
+            (Err(e), _) => Err(e.into()),
         }
-        None => {
-            if args.common.nexus {
-                let importer: NexusImporter<StdinLock> =
-                    nexus_importer::NexusImporter::from_reader(stdin.lock());
-                run_commands(importer, args.cmd)
-            } else {
-                let importer = newick_importer::NewickImporter::from_reader(stdin.lock());
-                run_commands(importer, args.cmd)
+    } else {
+        match expand_paths(&args.common.infile) {
+            Ok(paths) => {
+                let mut importer = ChainedImporter::from_paths(paths)
+                    .prefix_ids(args.common.prefix_ids)
+                    .sample(burnin, thin);
+                if let Some(format) = format {
+                    importer = importer.with_format(format);
+                }
+                match (args.common.source_column, table_header(&args.cmd)) {
+                    (true, Some(has_header)) => {
+                        let source = importer.source_tracker().expect("files are tracked");
+                        let mut output = SourceColumnWriter::new(&mut output, source, has_header);
+                        run_commands(importer, &mut output, args.cmd, args.common.threads)
+                    }
+                    (true, None) => {
+                        warn!("--source-column is ignored as the command does not write a table");
+                        run_commands(importer, &mut output, args.cmd, args.common.threads)
+                    }
+                    (false, _) => run_commands(importer, &mut output, args.cmd, args.common.threads),
+                }
             }
+            Err(e) => Err(e),
         }
     };
+    let result = result.and_then(|_| output.flush().map_err(|e| e.into()));
+    // finish any compressed stream before exiting
+    let result = match outfile {
+        Some(file) => result.and_then(|_| file.finish().map_err(|e| e.into())),
+        None => result,
+    };
 
     info!("{} seconds elapsed", start.elapsed().as_secs());
     match result {
@@ -218,24 +322,67 @@ fn main() {
     }
 }
 
+/// Expand any glob patterns in the input paths. Patterns are expanded in sorted order and
+/// must match at least one file.
+fn expand_paths(paths: &[path::PathBuf]) -> Result<Vec<path::PathBuf>, Box<dyn Error>> {
+    let mut expanded = vec![];
+    for path in paths {
+        let pattern = path.to_string_lossy();
+        if !pattern.contains(['*', '?', '[']) {
+            expanded.push(path.clone());
+            continue;
+        }
+        let mut matches = glob::glob(&pattern)?.collect::<Result<Vec<_>, _>>()?;
+        if matches.is_empty() {
+            return Err(format!("no files match {}", pattern).into());
+        }
+        matches.sort();
+        expanded.append(&mut matches);
+    }
+    Ok(expanded)
+}
+
+/// Whether a command writes tab separated rows, and if so whether they start with a header.
+/// Commands that write trees give None.
+fn table_header(cmd: &Fertree) -> Option<bool> {
+    match cmd {
+        Fertree::Stats { .. } | Fertree::TransmissionLineages { .. } => Some(true),
+        Fertree::Extract { cmd } => match cmd {
+            commands::extract::SubCommands::Taxa => Some(false),
+            commands::extract::SubCommands::Tree { .. } => None,
+            _ => Some(true),
+        },
+        Fertree::Split {
+            explore, min_size, ..
+        } if *explore && min_size.is_some() => Some(true),
+        _ => None,
+    }
+}
+
 fn run_commands<R: std::io::Read, T: TreeImporter<R>>(
     tree_importer: T,
+    handle: &mut dyn Write,
     cmd: Fertree,
+    threads: usize,
 ) -> Result<(), Box<dyn Error>> {
     match cmd {
-        Fertree::Format { cmd } => commands::format::run(tree_importer, cmd),
-        Fertree::Stats { cmd } => commands::stats::run(tree_importer, cmd),
-        Fertree::Annotate { traits } => commands::annotate::run(tree_importer, traits),
-        Fertree::Extract { cmd } => commands::extract::run(tree_importer, cmd),
-        Fertree::Clades { cmd } => commands::clades::run(tree_importer, cmd),
+        Fertree::Format { cmd } => commands::format::run(tree_importer, handle, cmd),
+        Fertree::Stats { cmd } => commands::stats::run(tree_importer, handle, cmd),
+        Fertree::Annotate { traits } => commands::annotate::run(tree_importer, handle, traits),
+        Fertree::Extract { cmd } => commands::extract::run(tree_importer, handle, cmd),
+        Fertree::Clades { cmd } => commands::clades::run(tree_importer, handle, cmd, threads),
         Fertree::Split {
             min_size,
             explore,
             relaxed,
-        } => commands::split::run(tree_importer, min_size, explore, !relaxed),
-        Fertree::Resolve { cmd } => commands::resolve::run(tree_importer, cmd),
-        Fertree::Brlen { cmd } => commands::branchlengths::run(tree_importer, cmd),
-        Fertree::Prune { cmd } => commands::prune::run(tree_importer, cmd),
+        } => commands::split::run(tree_importer, handle, min_size, explore, !relaxed),
+        Fertree::Resolve { cmd } => commands::resolve::run(tree_importer, handle, cmd, threads),
+        Fertree::MatchAlignment {
+            alignment,
+            pruned_trees,
+        } => commands::alignment::run(tree_importer, handle, alignment, pruned_trees),
+        Fertree::Brlen { cmd } => commands::branchlengths::run(tree_importer, handle, cmd, threads),
+        Fertree::Prune { cmd } => commands::prune::run(tree_importer, handle, cmd, threads),
         Fertree::TransmissionLineages {
             key,
             ignore_taxa,
@@ -246,6 +393,7 @@ fn run_commands<R: std::io::Read, T: TreeImporter<R>>(
             lag,
         } => commands::transmission_lineage::run(
             tree_importer,
+            handle,
             ignore_taxa,
             key,
             to,
@@ -253,7 +401,8 @@ fn run_commands<R: std::io::Read, T: TreeImporter<R>>(
             origin,
             cutoff,
             lag,
+            threads,
         ),
-        Fertree::TransmissionChain {  }=> commands::transmission_chain::run(tree_importer)
+        Fertree::TransmissionChain {  }=> commands::transmission_chain::run(tree_importer, handle)
     }
 }
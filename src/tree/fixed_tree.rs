@@ -1,5 +1,6 @@
-use super::AnnotationValue;
+use super::{AnnotationValue, Tree};
 use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
 
 #[derive(Debug)]
 pub struct FixedNode {
@@ -27,8 +28,8 @@ impl FixedNode {
         }
     }
 
-    pub fn iter(&self) -> PreorderIter {
-        PreorderIter::new(&self)
+    pub fn iter(&self) -> PreorderIter<'_> {
+        PreorderIter::new(self)
     }
 }
 
@@ -49,8 +50,69 @@ impl<'a> Iterator for PreorderIter<'a> {
             for child in node.children.iter() {
                 self.stack.push(child);
             }
-            return Some(&node);
+            return Some(node);
         };
         None
     }
 }
+
+/// A handle to a node in a [FixedNode] tree. Handles are equal if they point to the same
+/// node.
+#[derive(Debug, Clone, Copy)]
+pub struct NodeRef<'a>(pub &'a FixedNode);
+
+impl PartialEq for NodeRef<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self.0, other.0)
+    }
+}
+
+impl Eq for NodeRef<'_> {}
+
+impl Hash for NodeRef<'_> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        std::ptr::hash(self.0, state)
+    }
+}
+
+/// The node is the root of the tree. A FixedNode does not know its parent so
+/// [get_parent](Tree::get_parent) searches the tree from the root, and heights are worked
+/// out from the branch lengths on each call.
+impl Tree for FixedNode {
+    type Node<'a> = NodeRef<'a>;
+
+    fn get_root(&self) -> Option<NodeRef<'_>> {
+        Some(NodeRef(self))
+    }
+    fn get_children<'a>(&'a self, node: NodeRef<'a>) -> Vec<NodeRef<'a>> {
+        node.0.children.iter().map(NodeRef).collect()
+    }
+    fn get_parent<'a>(&'a self, node: NodeRef<'a>) -> Option<NodeRef<'a>> {
+        self.iter()
+            .find(|parent| parent.children.iter().any(|child| std::ptr::eq(child, node.0)))
+            .map(NodeRef)
+    }
+    fn get_taxon<'a>(&'a self, node: NodeRef<'a>) -> Option<&'a str> {
+        node.0.taxon.as_deref()
+    }
+    fn get_label<'a>(&'a self, node: NodeRef<'a>) -> Option<&'a str> {
+        node.0.label.as_deref()
+    }
+    fn get_length(&self, node: NodeRef<'_>) -> Option<f64> {
+        node.0.length
+    }
+    fn get_annotation<'a>(&'a self, node: NodeRef<'a>, key: &str) -> Option<&'a AnnotationValue> {
+        node.0.annotations.as_ref().and_then(|annotations| annotations.get(key))
+    }
+    fn get_annotations<'a>(&'a self, node: NodeRef<'a>) -> Vec<(&'a str, &'a AnnotationValue)> {
+        let mut annotations = node
+            .0
+            .annotations
+            .iter()
+            .flatten()
+            .map(|(key, value)| (key.as_str(), value))
+            .collect::<Vec<(&str, &AnnotationValue)>>();
+        annotations.sort_by(|a, b| a.0.cmp(b.0));
+        annotations
+    }
+}
@@ -1,11 +1,13 @@
-use serde::Deserialize;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::fmt;
+use std::hash::Hash;
 
-//TODO unify the trees with traits so they can be used interchangeably where applicable.
 pub mod fixed_tree;
 pub mod mutable_tree;
+#[cfg(test)]
+pub(crate) mod test_utils;
 
-#[derive(Debug, Clone, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct MarkovJump {
     pub(crate) time: f64,
     pub(crate) source: String,
@@ -18,21 +20,118 @@ impl fmt::Display for MarkovJump {
     }
 }
 
-#[derive(Debug, Clone, Deserialize, PartialEq)]
+/// Values are (de)serialized without a tag so they appear as plain JSON strings, numbers,
+/// booleans and arrays. Intervals are objects with a lower and upper bound, so they are
+/// not confused with sets of two numbers, and markov jumps are objects with a time,
+/// source and destination.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(untagged)]
 pub enum AnnotationValue {
     Discrete(String),
+    Integer(i64),
     Continuous(f64),
     Boolean(bool),
+    /// A lower and upper bound such as a 95% HPD. Written as {lower,upper}
+    #[serde(
+        serialize_with = "serialize_interval",
+        deserialize_with = "deserialize_interval"
+    )]
+    Interval(f64, f64),
     MarkovJump(MarkovJump),
     Set(Vec<AnnotationValue>),
 }
 
+#[derive(Serialize, Deserialize)]
+struct Bounds {
+    lower: f64,
+    upper: f64,
+}
+
+fn serialize_interval<S: Serializer>(
+    lower: &f64,
+    upper: &f64,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    Bounds {
+        lower: *lower,
+        upper: *upper,
+    }
+    .serialize(serializer)
+}
+
+fn deserialize_interval<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<(f64, f64), D::Error> {
+    let bounds = Bounds::deserialize(deserializer)?;
+    Ok((bounds.lower, bounds.upper))
+}
+
+impl AnnotationValue {
+    /// The value of an integer or continuous annotation
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            AnnotationValue::Integer(i) => Some(*i as f64),
+            AnnotationValue::Continuous(c) => Some(*c),
+            _ => None,
+        }
+    }
+
+    /// The bounds of an interval annotation
+    pub fn as_interval(&self) -> Option<(f64, f64)> {
+        match self {
+            AnnotationValue::Interval(lower, upper) => Some((*lower, *upper)),
+            _ => None,
+        }
+    }
+
+    /// A type that can hold values of both self's and other's type. Integers widen to
+    /// continuous values and intervals to sets. Anything else can only be held as text.
+    pub(crate) fn common_type(&self, other: &AnnotationValue) -> AnnotationValue {
+        match (self, other) {
+            (AnnotationValue::Integer(_), AnnotationValue::Integer(_)) => self.clone(),
+            (AnnotationValue::Integer(_), AnnotationValue::Continuous(_))
+            | (AnnotationValue::Continuous(_), AnnotationValue::Integer(_))
+            | (AnnotationValue::Continuous(_), AnnotationValue::Continuous(_)) => {
+                AnnotationValue::Continuous(0.0)
+            }
+            (AnnotationValue::Interval(..), AnnotationValue::Set(_))
+            | (AnnotationValue::Set(_), AnnotationValue::Interval(..)) => {
+                AnnotationValue::Set(vec![])
+            }
+            _ if std::mem::discriminant(self) == std::mem::discriminant(other) => self.clone(),
+            _ => AnnotationValue::Discrete("".to_string()),
+        }
+    }
+
+    /// Convert the value to the type of `annotation_type` as found by [common_type](Self::common_type).
+    pub(crate) fn convert_to(self, annotation_type: &AnnotationValue) -> AnnotationValue {
+        match (self, annotation_type) {
+            (AnnotationValue::Integer(i), AnnotationValue::Continuous(_)) => {
+                AnnotationValue::Continuous(i as f64)
+            }
+            (AnnotationValue::Interval(lower, upper), AnnotationValue::Set(_)) => {
+                AnnotationValue::Set(vec![
+                    AnnotationValue::Continuous(lower),
+                    AnnotationValue::Continuous(upper),
+                ])
+            }
+            (AnnotationValue::Discrete(s), AnnotationValue::Discrete(_)) => {
+                AnnotationValue::Discrete(s)
+            }
+            (value, AnnotationValue::Discrete(_)) => AnnotationValue::Discrete(value.to_string()),
+            (value, _) => value,
+        }
+    }
+}
+
 impl fmt::Display for AnnotationValue {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             AnnotationValue::Discrete(string) => write!(f, "{}", string),
-            AnnotationValue::Continuous(f64) => write!(f, "{}", f64.to_string()),
-            AnnotationValue::Boolean(b) => write!(f, "{}", b.to_string()),
+            AnnotationValue::Integer(i) => write!(f, "{}", i),
+            AnnotationValue::Continuous(f64) => write!(f, "{}", f64),
+            AnnotationValue::Interval(lower, upper) => write!(f, "{{{},{}}}", lower, upper),
+            AnnotationValue::Boolean(b) => write!(f, "{}", b),
             AnnotationValue::MarkovJump(v) => {
                 write!(f, "{{ {} }}", v)
             }
@@ -47,3 +146,238 @@ impl fmt::Display for AnnotationValue {
         }
     }
 }
+
+/// Read-only access to a rooted tree. [MutableTree](mutable_tree::MutableTree) and
+/// [FixedNode](fixed_tree::FixedNode) both implement it so analyses can be written once
+/// and used with either, or with another tree representation.
+///
+/// Nodes are referred to by handles that are only meaningful to the tree that gave them
+/// out. Only the first eight methods need to be implemented. Traversals and heights are
+/// worked out from them, so a representation that stores them can do better by
+/// overriding the defaults.
+///
+/// Analyses such as the transmission lineage search call [get_parent](Tree::get_parent)
+/// and [get_height](Tree::get_height) for every node they visit, so a representation
+/// used with them should answer both in constant time. The default `get_height` works
+/// out every height on each call, and [FixedNode](fixed_tree::FixedNode) searches the
+/// tree for a parent. Loops over every node of any tree should get all the heights at
+/// once with [get_heights](Tree::get_heights) instead.
+///
+/// A height is a node's position on the tree's time scale, increasing towards the root.
+/// Trees that store heights give them on their own scale, which may have any origin:
+/// [MutableTree](mutable_tree::MutableTree) heights set with
+/// [calc_relative_node_heights](mutable_tree::MutableTree::calc_relative_node_heights)
+/// are decimal dates. Otherwise heights are the distance back from the tip furthest from
+/// the root.
+pub trait Tree {
+    /// A cheap handle to a node. Handles are equal and hash the same if they refer to the
+    /// same node.
+    type Node<'a>: Copy + Eq + Hash + fmt::Debug
+    where
+        Self: 'a;
+
+    fn get_root(&self) -> Option<Self::Node<'_>>;
+    /// Children in the order they were added
+    fn get_children<'a>(&'a self, node: Self::Node<'a>) -> Vec<Self::Node<'a>>;
+    fn get_parent<'a>(&'a self, node: Self::Node<'a>) -> Option<Self::Node<'a>>;
+    fn get_taxon<'a>(&'a self, node: Self::Node<'a>) -> Option<&'a str>;
+    fn get_label<'a>(&'a self, node: Self::Node<'a>) -> Option<&'a str>;
+    fn get_length(&self, node: Self::Node<'_>) -> Option<f64>;
+    fn get_annotation<'a>(
+        &'a self,
+        node: Self::Node<'a>,
+        key: &str,
+    ) -> Option<&'a AnnotationValue>;
+    /// The annotations on a node sorted by key
+    fn get_annotations<'a>(&'a self, node: Self::Node<'a>) -> Vec<(&'a str, &'a AnnotationValue)>;
+
+    fn is_external<'a>(&'a self, node: Self::Node<'a>) -> bool {
+        self.get_children(node).is_empty()
+    }
+    fn is_internal<'a>(&'a self, node: Self::Node<'a>) -> bool {
+        !self.is_external(node)
+    }
+    fn get_num_children<'a>(&'a self, node: Self::Node<'a>) -> usize {
+        self.get_children(node).len()
+    }
+
+    /// Nodes with each parent before its children and children in order
+    fn preorder(&self) -> Vec<Self::Node<'_>> {
+        let mut nodes = vec![];
+        let mut stack = self.get_root().into_iter().collect::<Vec<Self::Node<'_>>>();
+        while let Some(node) = stack.pop() {
+            nodes.push(node);
+            stack.extend(self.get_children(node).into_iter().rev());
+        }
+        nodes
+    }
+    /// Nodes with each parent after its children and children in order
+    fn postorder(&self) -> Vec<Self::Node<'_>> {
+        let mut nodes = vec![];
+        let mut stack = self.get_root().into_iter().collect::<Vec<Self::Node<'_>>>();
+        while let Some(node) = stack.pop() {
+            nodes.push(node);
+            stack.extend(self.get_children(node));
+        }
+        nodes.reverse();
+        nodes
+    }
+    /// Every node in the order the tree keeps them, which is preorder unless the tree
+    /// has an order of its own
+    fn get_nodes(&self) -> Vec<Self::Node<'_>> {
+        self.preorder()
+    }
+    /// The tips in the order the tree keeps them, which is preorder unless the tree has
+    /// an order of its own
+    fn get_external_nodes(&self) -> Vec<Self::Node<'_>> {
+        self.preorder()
+            .into_iter()
+            .filter(|node| self.is_external(*node))
+            .collect()
+    }
+    fn get_node_count(&self) -> usize {
+        self.get_nodes().len()
+    }
+    fn get_external_node_count(&self) -> usize {
+        self.get_external_nodes().len()
+    }
+
+    /// Every node in preorder with its height. Unless the tree stores heights they are
+    /// the distance back from the tip furthest from the root, with missing branch lengths
+    /// counting as 0.
+    fn get_heights(&self) -> Vec<(Self::Node<'_>, f64)> {
+        heights_from_lengths(self)
+    }
+    /// The height of a node as given by [get_heights](Tree::get_heights). Override this
+    /// if heights are stored as the default works out every height.
+    fn get_height<'a>(&'a self, node: Self::Node<'a>) -> Option<f64> {
+        self.get_heights()
+            .into_iter()
+            .find(|(other, _)| *other == node)
+            .map(|(_, height)| height)
+    }
+}
+
+pub(crate) fn heights_from_lengths<T: Tree + ?Sized>(tree: &T) -> Vec<(T::Node<'_>, f64)> {
+    let mut depths = vec![];
+    let mut stack = tree
+        .get_root()
+        .map(|root| (root, 0.0))
+        .into_iter()
+        .collect::<Vec<(T::Node<'_>, f64)>>();
+    while let Some((node, depth)) = stack.pop() {
+        depths.push((node, depth));
+        for child in tree.get_children(node).into_iter().rev() {
+            stack.push((child, depth + tree.get_length(child).unwrap_or(0.0)));
+        }
+    }
+    let root_to_tip = depths
+        .iter()
+        .filter(|(node, _)| tree.is_external(*node))
+        .fold(0.0, |max: f64, (_, depth)| max.max(*depth));
+    depths
+        .into_iter()
+        .map(|(node, depth)| (node, root_to_tip - depth))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::fixed_tree::FixedNode;
+    use super::mutable_tree::MutableTree;
+    use super::{AnnotationValue, Tree};
+
+    fn node(taxon: Option<&str>, length: f64, children: Vec<FixedNode>) -> FixedNode {
+        let mut node = FixedNode::new();
+        node.taxon = taxon.map(String::from);
+        node.length = Some(length);
+        node.children = children;
+        node
+    }
+
+    // ((A:1,B:2)[&location=UK]:1,C:1);
+    fn fixed_tree() -> FixedNode {
+        let mut ab = node(
+            None,
+            1.0,
+            vec![node(Some("A"), 1.0, vec![]), node(Some("B"), 2.0, vec![])],
+        );
+        ab.label = Some("ab".to_string());
+        ab.annotations = Some(
+            vec![(
+                "location".to_string(),
+                AnnotationValue::Discrete("UK".to_string()),
+            )]
+            .into_iter()
+            .collect(),
+        );
+        let mut root = node(None, 0.0, vec![ab, node(Some("C"), 1.0, vec![])]);
+        root.length = None;
+        root
+    }
+
+    // taxon, label, height, number of children and whether it is the root
+    type NodeSummary = (Option<String>, Option<String>, f64, usize, bool);
+
+    fn summary<T: Tree>(tree: &T) -> Vec<NodeSummary> {
+        tree.preorder()
+            .into_iter()
+            .map(|node| {
+                (
+                    tree.get_taxon(node).map(String::from),
+                    tree.get_label(node).map(String::from),
+                    tree.get_height(node).unwrap(),
+                    tree.get_num_children(node),
+                    tree.get_parent(node).is_none(),
+                )
+            })
+            .collect()
+    }
+
+    fn taxa<'a, T: Tree>(tree: &'a T, nodes: Vec<T::Node<'a>>) -> Vec<String> {
+        nodes
+            .into_iter()
+            .filter_map(|node| tree.get_taxon(node).map(String::from))
+            .collect()
+    }
+
+    #[test]
+    fn fixed_and_mutable_trees_agree() {
+        let fixed = fixed_tree();
+        let mutable = MutableTree::from_fixed_node(fixed_tree());
+        assert_eq!(summary(&fixed), summary(&mutable));
+        assert_eq!(
+            vec![
+                (None, None, 3.0, 2, true),
+                (None, Some("ab".to_string()), 2.0, 2, false),
+                (Some("A".to_string()), None, 1.0, 0, false),
+                (Some("B".to_string()), None, 0.0, 0, false),
+                (Some("C".to_string()), None, 2.0, 0, false),
+            ],
+            summary(&fixed)
+        );
+        assert_eq!(vec!["A", "B", "C"], taxa(&fixed, fixed.postorder()));
+        assert_eq!(5, fixed.get_node_count());
+        assert_eq!(3, fixed.get_external_node_count());
+
+        let ab = fixed.get_children(fixed.get_root().unwrap())[0];
+        assert_eq!(
+            vec![("location", &AnnotationValue::Discrete("UK".to_string()))],
+            fixed.get_annotations(ab)
+        );
+        let ab = mutable.get_label_node("ab").unwrap();
+        assert_eq!(
+            vec![("location", &AnnotationValue::Discrete("UK".to_string()))],
+            Tree::get_annotations(&mutable, ab)
+        );
+    }
+
+    #[test]
+    fn stored_heights_are_used() {
+        let mut tree = MutableTree::from_fixed_node(fixed_tree());
+        tree.heights_known = false;
+        tree.calc_relative_node_heights(2020.0);
+        let c = tree.get_taxon_node("C").unwrap();
+        assert_eq!(Some(2018.0), Tree::get_height(&tree, c));
+    }
+}
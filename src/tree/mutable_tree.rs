@@ -1,8 +1,9 @@
 use super::fixed_tree::FixedNode;
-use super::AnnotationValue;
+use crate::alignment::Alignment;
+use super::{heights_from_lengths, AnnotationValue, Tree};
 use core::f64;
 use std::collections::hash_map::Keys;
-use std::collections::{HashMap, HashSet};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::option::Option;
 
 pub type TreeIndex = usize;
@@ -23,6 +24,22 @@ pub struct MutableTreeNode {
     pub height: Option<f64>,
     pub annotations: HashMap<String, AnnotationValue>,
     pub number: usize,
+    /// sequence from an alignment. See [MutableTree::attach_sequences]
+    pub sequence: Option<String>,
+    /// Which of the tree's node lists the node is in. Kept by the tree so kind checks
+    /// do not search the lists.
+    pub(crate) kind: NodeKind,
+    /// The children in order. Kept in step with the sibling links by the tree so child
+    /// access does not walk them.
+    pub(crate) children: Vec<TreeIndex>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub(crate) enum NodeKind {
+    #[default]
+    Unlisted,
+    External,
+    Internal,
 }
 
 impl MutableTreeNode {
@@ -38,6 +55,9 @@ impl MutableTreeNode {
             height: None,
             annotations: HashMap::new(),
             number,
+            sequence: None,
+            kind: NodeKind::Unlisted,
+            children: vec![],
         }
     }
 }
@@ -55,6 +75,8 @@ pub struct MutableTree {
     pub branchlengths_known: bool,
     pub id: Option<String>,
     pub tree_annotation: HashMap<String, AnnotationValue>,
+    /// Whether the input marked the tree as rooted (`[&R]`) or unrooted (`[&U]`)
+    pub rooted: Option<bool>,
 }
 
 impl Default for MutableTree {
@@ -77,51 +99,61 @@ impl MutableTree {
             branchlengths_known: false,
             id: None,
             tree_annotation: HashMap::new(),
+            rooted: None,
         }
     }
     pub fn from_fixed_node(root: FixedNode) -> Self {
         let mut tree = MutableTree::new();
         tree.branchlengths_known = true;
-        tree.fixed_node_helper(root, None);
+        tree.fixed_node_helper(root);
         tree.set_root(Some(0));
         tree.calc_node_heights();
         tree.branchlengths_known = true;
         tree
     }
 
-    fn fixed_node_helper(&mut self, node: FixedNode, parent: Option<TreeIndex>) {
-        let index = self.nodes.len();
-        let new_node = MutableTreeNode::new(node.taxon.clone(), index);
-        self.nodes.push(new_node);
+    /// Add the nodes in preorder, each popped with the index of the parent it joins.
+    fn fixed_node_helper(&mut self, root: FixedNode) {
+        let mut stack = vec![(root, None)];
+        while let Some((node, parent)) = stack.pop() {
+            let index = self.nodes.len();
+            let new_node = MutableTreeNode::new(node.taxon.clone(), index);
+            self.nodes.push(new_node);
 
-        if let Some(length) = node.length {
-            self.set_length(index, length);
-        }
-        if let Some(label) = node.label {
-            self.set_label(index, label);
-        }
+            if let Some(length) = node.length {
+                self.set_length(index, length);
+            }
+            if let Some(label) = node.label {
+                self.set_label(index, label);
+            }
 
-        if let Some(annotation_map) = node.annotations {
-            for (key, value) in annotation_map.into_iter() {
-                self.annotate_node(index, key, value);
+            if let Some(annotation_map) = node.annotations {
+                for (key, value) in annotation_map.into_iter() {
+                    self.annotate_node(index, key, value);
+                }
             }
-        }
 
-        if !node.children.is_empty() {
-            self.internal_nodes.push(index);
-        } else {
-            self.external_nodes.push(index);
-            if let Some(taxon) = node.taxon {
-                self.label_node_map.insert(taxon.clone(), index);
-                self.taxon_node_map.insert(taxon, index);
+            if !node.children.is_empty() {
+                self.internal_nodes.push(index);
+                self.nodes[index].kind = NodeKind::Internal;
+            } else {
+                self.external_nodes.push(index);
+                self.nodes[index].kind = NodeKind::External;
+                if let Some(taxon) = node.taxon {
+                    self.label_node_map.insert(taxon.clone(), index);
+                    self.taxon_node_map.insert(taxon, index);
+                }
             }
-        }
-        if let Some(p) = parent {
-            self.add_child(p, index);
-            self.set_parent(p, index);
-        }
-        for child in node.children.into_iter() {
-            self.fixed_node_helper(child, Some(index));
+            if let Some(p) = parent {
+                self.add_child(p, index);
+                self.set_parent(p, index);
+            }
+            stack.extend(
+                node.children
+                    .into_iter()
+                    .rev()
+                    .map(|child| (child, Some(index))),
+            );
         }
     }
     //TODO refactor to return error for node heights
@@ -132,7 +164,9 @@ impl MutableTree {
             .expect("every tree should have a root at least nominally");
         tree.calc_node_heights();
         me.tree_helper(root,tree, taxa,false);
-        // me.collapse_degree2_nodes(me.get_root().expect("Tree is not rooted"));
+        me.id = tree.id.clone();
+        me.tree_annotation = tree.tree_annotation.clone();
+        me.rooted = tree.rooted;
         me.heights_known = true;
         me.calculate_branchlengths();
         me
@@ -145,6 +179,9 @@ impl MutableTree {
             .expect("every tree should have a root at least nominally");
         tree.calc_node_heights();
         me.tree_helper(root,tree, taxa,true);
+        me.id = tree.id.clone();
+        me.tree_annotation = tree.tree_annotation.clone();
+        me.rooted = tree.rooted;
         me.heights_known = true;
         me.calculate_branchlengths();
         me
@@ -165,73 +202,242 @@ impl MutableTree {
             self.annotate_node(my_node, key.clone(), value.clone());
         }
     }
+    /// Copy the part of the subtree below `node` that leads to the taxa, children before
+    /// parents. Returns the copy of `node`, or of the one node below it that is kept.
     fn tree_helper(&mut self, node:TreeIndex,tree: &MutableTree, taxa: &HashSet<String>,keep_degree_2:bool)->Option<TreeIndex>{
-        if tree.is_external(node) {
-            if let Some(taxon) = tree.get_taxon(node) {
-                if taxa.contains(taxon) {
-
-                    let new_node = self.make_external_node(&taxon, None).unwrap();
+        // what each visited node became in this tree
+        let mut copies: Vec<Option<TreeIndex>> = vec![None; tree.arena_len()];
+        for their_node in PostOrderIterator::new(Some(node), tree) {
+            copies[their_node] = if tree.is_external(their_node) {
+                match tree.get_taxon(their_node) {
+                    Some(taxon) if taxa.contains(taxon) => {
+                        let new_node = self.make_external_node(taxon, None).unwrap();
+                        self.set_height(
+                            new_node,
+                            tree.get_height(their_node).expect("nodes should be known"),
+                        );
+                        self.copy_annotations(tree, their_node, new_node);
+                        Some(new_node)
+                    }
+                    _ => None,
+                }
+            } else {
+                let child_nodes = tree
+                    .get_children(their_node)
+                    .iter()
+                    .filter_map(|child| copies[*child])
+                    .collect::<Vec<TreeIndex>>();
+                let included_children = child_nodes.len();
+                if included_children>1 || (included_children==1 && keep_degree_2){ // only include bifurcating nodes at least
+                    let new_node = self.make_internal_node(child_nodes);
                     self.set_height(
                         new_node,
-                        tree.get_height(node).expect("nodes should be known"),
+                        tree.get_height(their_node).expect("nodes should be known"),
                     );
-                    self.copy_annotations(tree, node, new_node);
-                    return Some(new_node);
+                    self.copy_annotations(tree, their_node, new_node);
+                    self.set_root(Some(new_node));
+                    Some(new_node)
+                } else {
+                    child_nodes.first().copied()
+                }
+            };
+        }
+        copies[node]
+    }
+
+    /// Collapse every node with a single child, the root included, so each branch joins
+    /// two nodes that are tips or have at least two children.
+    pub fn collapse_degree2_nodes(&mut self) {
+        let degree2 = self
+            .postorder_iter()
+            .filter(|node| self.get_num_children(*node) == 1)
+            .collect::<Vec<TreeIndex>>();
+        for node in degree2 {
+            self.collapse_node(node)
+                .expect("nodes with one child can be collapsed");
+        }
+    }
+
+    /// Delete a node without children: it is removed from its parent, the node lists and
+    /// the taxon and label lookups. The node stays in the arena so other indices are
+    /// unchanged until [compact](MutableTree::compact) is called.
+    pub fn delete_node(&mut self, node: TreeIndex) -> Result<(), String> {
+        if self.get_num_children(node) > 0 {
+            return Err(format!(
+                "node {} has children. Delete its subtree or collapse it instead",
+                node
+            ));
+        }
+        self.detach(node);
+        self.unlist(&[node]);
+        Ok(())
+    }
+
+    /// Delete a node and everything below it as [delete_node](MutableTree::delete_node)
+    /// does. Returns the number of nodes deleted.
+    pub fn delete_subtree(&mut self, node: TreeIndex) -> usize {
+        let nodes = self.subtree_iter(node).collect::<Vec<TreeIndex>>();
+        self.detach(node);
+        self.unlist(&nodes);
+        nodes.len()
+    }
+
+    /// Delete an internal node and put its children in its place among its parent's
+    /// children. The node's branch length is added to each child's so heights are kept.
+    /// A root with one child is replaced by that child, which takes the root's branch
+    /// length. Fails for tips and for roots with more than one child.
+    pub fn collapse_node(&mut self, node: TreeIndex) -> Result<(), String> {
+        let children = std::mem::take(&mut self.get_unwrapped_node_mut(node).children);
+        if children.is_empty() {
+            return Err(format!("node {} has no children to collapse into", node));
+        }
+        let length = self.get_length(node);
+        match self.get_parent(node) {
+            Some(parent) => {
+                for child in children.iter() {
+                    let child_node = self.get_unwrapped_node_mut(*child);
+                    if let Some(length) = length {
+                        child_node.length = Some(child_node.length.unwrap_or(0.0) + length);
+                    }
                 }
+                let siblings = &mut self.get_unwrapped_node_mut(parent).children;
+                let position = siblings
+                    .iter()
+                    .position(|sibling| *sibling == node)
+                    .expect("nodes are children of their parent");
+                siblings.splice(position..=position, children);
+                self.link_children(parent);
             }
-        }else{
-            let mut included_children =0;
-            let mut child_nodes = vec![];
-            for child in tree.get_children(node){
-
-               if let Some(child_node) =  self.tree_helper(child,tree,taxa,keep_degree_2){
-                    child_nodes.push(child_node);
-                    included_children +=1;
-               }
+            None if children.len() == 1 => {
+                let child_node = self.get_unwrapped_node_mut(children[0]);
+                child_node.parent = None;
+                child_node.next_sibling = None;
+                child_node.previous_sibling = None;
+                child_node.length = length;
+                if self.root == Some(node) {
+                    self.root = Some(children[0]);
+                }
             }
-            if included_children>1 || (included_children==1 && keep_degree_2){ // only include bifurcating nodes at least
-                let new_node = self.make_internal_node(child_nodes);
-                self.set_height(
-                    new_node,
-                    tree.get_height(node).expect("nodes should be known"),
-                );
-                self.copy_annotations(tree, node, new_node);
-                self.set_root(Some(new_node));
-                return Some(new_node)
-            }else if included_children==1{
-                return Some(child_nodes[0])
+            None => {
+                let count = children.len();
+                self.get_unwrapped_node_mut(node).children = children;
+                return Err(format!(
+                    "node {} has no parent to take its {} children",
+                    node, count
+                ));
             }
         }
-        return None;
-    }
-    
-    fn collapse_degree2_nodes(&mut self, node: TreeIndex) {
-        self.calc_node_heights();
-
-        let mut i=0;
-        while let Some(child) = self.get_child(node,i){
-            self.collapse_degree2_nodes(child);
-            i+=1;
-        }
-        if self.get_num_children(node)==1{
-            if let Some(parent) = self.get_parent(node){
-                self.remove_child(parent,node);
-            }else{
-                //at the root
-                let child= self.get_child(node, 0).unwrap();
-                self.remove_child(node, child);
-                self.set_root(Some(child));
+        let collapsed = self.get_unwrapped_node_mut(node);
+        collapsed.parent = None;
+        collapsed.first_child = None;
+        self.unlist(&[node]);
+        Ok(())
+    }
+
+    /// Remove deleted nodes from the arena. The nodes that are left keep their order and
+    /// every index in the tree, its node lists and lookups is updated to match, so
+    /// indices held from before are no longer valid.
+    pub fn compact(&mut self) {
+        let mut new_index: Vec<Option<TreeIndex>> = vec![None; self.nodes.len()];
+        let mut kept = 0;
+        for (index, node) in self.nodes.iter().enumerate() {
+            if node.kind != NodeKind::Unlisted {
+                new_index[index] = Some(kept);
+                kept += 1;
             }
-            
+        }
+        let remap = |index: Option<TreeIndex>| index.and_then(|i| new_index[i]);
 
+        let nodes = std::mem::take(&mut self.nodes);
+        self.nodes = nodes
+            .into_iter()
+            .filter(|node| node.kind != NodeKind::Unlisted)
+            .enumerate()
+            .map(|(index, mut node)| {
+                node.number = index;
+                node.parent = remap(node.parent);
+                node.first_child = remap(node.first_child);
+                node.next_sibling = remap(node.next_sibling);
+                node.previous_sibling = remap(node.previous_sibling);
+                node.children = node
+                    .children
+                    .iter()
+                    .filter_map(|child| new_index[*child])
+                    .collect();
+                node
+            })
+            .collect();
+        for list in [&mut self.external_nodes, &mut self.internal_nodes] {
+            *list = list.iter().filter_map(|node| new_index[*node]).collect();
         }
+        for map in [&mut self.taxon_node_map, &mut self.label_node_map] {
+            map.retain(|_, node| match new_index[*node] {
+                Some(index) => {
+                    *node = index;
+                    true
+                }
+                None => false,
+            });
+        }
+        self.root = remap(self.root);
+    }
 
+    /// Take a node away from its parent, or from the root if it is the root
+    fn detach(&mut self, node: TreeIndex) {
+        match self.get_parent(node) {
+            Some(parent) => {
+                self.remove_child(parent, node);
+                self.get_unwrapped_node_mut(node).parent = None;
+            }
+            None => {
+                if self.root == Some(node) {
+                    self.root = None;
+                }
+            }
+        }
     }
-    pub fn is_internal(&self,node:TreeIndex)->bool{
-        self.internal_nodes.contains(&node)
+
+    /// Set the sibling links and parents of a node's children from its child list
+    fn link_children(&mut self, parent: TreeIndex) {
+        let children = self.get_children(parent);
+        self.get_unwrapped_node_mut(parent).first_child = children.first().copied();
+        for (i, child) in children.iter().enumerate() {
+            let child_node = self.get_unwrapped_node_mut(*child);
+            child_node.parent = Some(parent);
+            child_node.previous_sibling = i.checked_sub(1).map(|i| children[i]);
+            child_node.next_sibling = children.get(i + 1).copied();
+        }
     }
-    pub fn  is_external(&self,node:TreeIndex)->bool{
-        self.external_nodes.contains(&node)
+
+    /// Drop nodes from the node lists and lookups and clear their links to each other
+    fn unlist(&mut self, nodes: &[TreeIndex]) {
+        for node in nodes.iter() {
+            let deleted = &mut self.nodes[*node];
+            deleted.kind = NodeKind::Unlisted;
+            deleted.parent = None;
+            deleted.first_child = None;
+            deleted.next_sibling = None;
+            deleted.previous_sibling = None;
+            deleted.children.clear();
+            for name in deleted.taxon.iter().chain(deleted.label.iter()) {
+                for map in [&mut self.taxon_node_map, &mut self.label_node_map] {
+                    if map.get(name) == Some(node) {
+                        map.remove(name);
+                    }
+                }
+            }
+        }
+        let all_nodes = &self.nodes;
+        self.external_nodes
+            .retain(|node| all_nodes[*node].kind == NodeKind::External);
+        self.internal_nodes
+            .retain(|node| all_nodes[*node].kind == NodeKind::Internal);
+    }
+    pub fn is_internal(&self, node: TreeIndex) -> bool {
+        self.get_unwrapped_node(node).kind == NodeKind::Internal
+    }
+    pub fn is_external(&self, node: TreeIndex) -> bool {
+        self.get_unwrapped_node(node).kind == NodeKind::External
     }
     pub fn get_height(&self, node: TreeIndex) -> Option<f64> {
         self.get_unwrapped_node(node).height
@@ -260,11 +466,10 @@ impl MutableTree {
                 }
             }
 
-            let mut i = 0;
-            while i < self.nodes.len() {
+            let preorder = self.preorder_iter().collect::<Vec<TreeIndex>>();
+            for i in preorder {
                 let height = rtt - self.get_height(i).unwrap();
                 self.set_height(i, height);
-                i += 1;
             }
         }
     }
@@ -280,19 +485,18 @@ impl MutableTree {
             }
         }
 
-        let mut i = 0;
-        while i < self.nodes.len() {
+        let preorder = self.preorder_iter().collect::<Vec<TreeIndex>>();
+        for i in preorder {
             let height = origin - (rtt - self.get_height(i).unwrap());
             self.set_height(i, height);
-            i += 1;
         }
     }
 
     pub fn calculate_branchlengths(&mut self) {
         if !self.branchlengths_known {
             self.branchlengths_known = true;
-            let mut i = 0;
-            while i < self.nodes.len() {
+            let preorder = self.preorder_iter().collect::<Vec<TreeIndex>>();
+            for i in preorder {
                 if i != self.root.expect("how is this tree not rooted") {
                     let length = self
                         .get_height(
@@ -303,17 +507,16 @@ impl MutableTree {
                         - self.get_height(i).expect("node should have height");
                     self.set_length(i, length);
                 }
-                i += 1;
             }
         }
     }
     fn calc_height_above_root(&mut self) {
-        let preorder = self.preorder_iter();
+        let preorder = self.preorder_iter().collect::<Vec<TreeIndex>>();
         for node_ref in preorder {
             if let Some(p) = self.get_parent(node_ref) {
                 let l = self
                     .get_length(node_ref)
-                    .expect(&*format!("no length on node {}", node_ref));
+                    .unwrap_or_else(|| panic!("no length on node {}", node_ref));
                 let pheight = self.get_height(p).unwrap();
                 self.set_height(node_ref, l + pheight);
             } else {
@@ -337,7 +540,8 @@ impl MutableTree {
         if let Some(taxa) = taxa_set {
             if taxa.contains(taxon) {
                 let index = self.nodes.len();
-                let new_node = MutableTreeNode::new(Some(taxon.to_string()), index);
+                let mut new_node = MutableTreeNode::new(Some(taxon.to_string()), index);
+                new_node.kind = NodeKind::External;
                 self.nodes.push(new_node);
                 self.external_nodes.push(index);
                 self.taxon_node_map.insert(taxon.to_string(), index);
@@ -349,7 +553,8 @@ impl MutableTree {
             }
         } else {
             let index = self.nodes.len();
-            let new_node = MutableTreeNode::new(Some(taxon.to_string()), index);
+            let mut new_node = MutableTreeNode::new(Some(taxon.to_string()), index);
+            new_node.kind = NodeKind::External;
             self.nodes.push(new_node);
             self.external_nodes.push(index);
             self.taxon_node_map.insert(taxon.to_string(), index);
@@ -362,7 +567,8 @@ impl MutableTree {
     /// the order they appear in the input vector;
     pub fn make_internal_node(&mut self, children: Vec<TreeIndex>) -> TreeIndex {
         let index = self.nodes.len();
-        let new_node = MutableTreeNode::new(None, index);
+        let mut new_node = MutableTreeNode::new(None, index);
+        new_node.kind = NodeKind::Internal;
         self.nodes.push(new_node);
         self.internal_nodes.push(index);
         for child in children.into_iter() {
@@ -374,82 +580,100 @@ impl MutableTree {
         }
         index
     }
+    #[allow(dead_code)]
     fn make_root_node(&mut self, children: Vec<TreeIndex>) -> TreeIndex {
         let index = self.make_internal_node(children);
         self.set_root(Some(index));
         index
     }
 
-    pub fn preorder_iter(&self) -> PreOrderIterator {
+    pub fn preorder_iter(&self) -> PreOrderIterator<'_> {
         PreOrderIterator::new(self.root, self)
     }
+    pub fn postorder_iter(&self) -> PostOrderIterator<'_> {
+        PostOrderIterator::new(self.root, self)
+    }
+    pub fn levelorder_iter(&self) -> LevelOrderIterator<'_> {
+        LevelOrderIterator::new(self.root, self)
+    }
+    /// The nodes below and including `node` in preorder
+    pub fn subtree_iter(&self, node: TreeIndex) -> PreOrderIterator<'_> {
+        PreOrderIterator::new(Some(node), self)
+    }
     pub fn set_root(&mut self, root: Option<TreeIndex>) {
         self.root = root
     }
 
     pub fn add_child(&mut self, parent: TreeIndex, child: TreeIndex) {
         let parent_node = self.get_node_mut(parent).expect("Node not in tree");
-        if let Some(first_child) = parent_node.first_child {
-            let mut sibling_node = self.get_node_mut(first_child).expect("Node not in tree");
-            while let Some(sibling) = sibling_node.next_sibling {
-                sibling_node = self
-                    .get_node_mut(sibling)
-                    .expect("expected sibling node to be in the tree");
+        let last_child = parent_node.children.last().copied();
+        parent_node.children.push(child);
+        match last_child {
+            Some(sibling) => {
+                self.get_unwrapped_node_mut(sibling).next_sibling = Some(child);
+                let child_node = self.get_node_mut(child).expect("node not in tree");
+                child_node.previous_sibling = Some(sibling);
             }
-            sibling_node.next_sibling = Some(child);
-            let previous_sib_i = sibling_node.number;
-            let mut child_node = self.get_node_mut(child).expect("node not in tree");
-            child_node.previous_sibling = Some(previous_sib_i);
-        } else {
-            let mut parent_node = self
-                .get_node_mut(parent)
-                .expect("parent to be part of the tree");
-            parent_node.first_child = Some(child);
+            None => parent_node.first_child = Some(child),
         }
     }
 
     pub fn remove_child(&mut self, parent: TreeIndex, child: TreeIndex) -> Option<TreeIndex> {
-        let mut successful_removal = true;
-        let child_node = self.get_unwrapped_node(child);
-        if let Some(previous_slibling_i) = child_node.previous_sibling {
-            if let Some(next_sibling_i) = child_node.next_sibling {
-                let mut prev_sib = self.get_unwrapped_node_mut(previous_slibling_i);
-                prev_sib.next_sibling = Some(next_sibling_i);
-                let mut next_sib = self.get_unwrapped_node_mut(next_sibling_i);
-                next_sib.previous_sibling = Some(previous_slibling_i);
-            } else {
-                let mut prev_sib = self.get_unwrapped_node_mut(previous_slibling_i);
-                prev_sib.next_sibling = None;
-            }
-        } else {
-            let parent_node = self.get_unwrapped_node_mut(parent);
-            if let Some(first_child) = parent_node.first_child {
-                if first_child == child {
-                    let child_node = self.get_unwrapped_node(child);
-                    if let Some(next_sibling_i) = child_node.next_sibling {
-                        let parent_node = self.get_unwrapped_node_mut(parent);
-                        parent_node.first_child = Some(next_sibling_i);
-                        let mut next_sib = self.get_unwrapped_node_mut(next_sibling_i);
-                        next_sib.previous_sibling = None;
-                    } else {
-                        let parent_node = self.get_unwrapped_node_mut(parent);
-                        parent_node.first_child = None;
-                    }
-                } else {
-                    println!("not a child of the parent node! TODO make this a warning");
-                    successful_removal = false;
-                }
+        let parent_node = self.get_unwrapped_node_mut(parent);
+        let position = match parent_node.children.iter().position(|c| *c == child) {
+            Some(position) => position,
+            None => {
+                warn!("node {} is not a child of node {}", child, parent);
+                return None;
             }
+        };
+        parent_node.children.remove(position);
+        let previous = position.checked_sub(1).map(|i| parent_node.children[i]);
+        let next = parent_node.children.get(position).copied();
+        match previous {
+            Some(previous) => self.get_unwrapped_node_mut(previous).next_sibling = next,
+            None => self.get_unwrapped_node_mut(parent).first_child = next,
+        }
+        if let Some(next) = next {
+            self.get_unwrapped_node_mut(next).previous_sibling = previous;
         }
 
-        if successful_removal {
-            let mut_child = self.get_unwrapped_node_mut(child);
-            mut_child.next_sibling = None;
-            mut_child.previous_sibling = None;
-            Some(child)
-        } else {
-            None
+        let mut_child = self.get_unwrapped_node_mut(child);
+        mut_child.next_sibling = None;
+        mut_child.previous_sibling = None;
+        Some(child)
+    }
+
+    /// Fill in the node kinds and child lists from the node lists and sibling links.
+    ///
+    /// The tree's own methods keep these in step. Call this after writing the public
+    /// `nodes`, `external_nodes`, `internal_nodes`, `first_child` or `next_sibling`
+    /// fields directly and before using the tree again. Fails if the sibling links form
+    /// a cycle.
+    pub fn rebuild_node_cache(&mut self) -> Result<(), String> {
+        for node in self.nodes.iter_mut() {
+            node.kind = NodeKind::Unlisted;
+            node.children.clear();
+        }
+        for node in self.external_nodes.iter() {
+            self.nodes[*node].kind = NodeKind::External;
+        }
+        for node in self.internal_nodes.iter() {
+            self.nodes[*node].kind = NodeKind::Internal;
+        }
+        for index in 0..self.nodes.len() {
+            let mut children = vec![];
+            let mut next = self.nodes[index].first_child;
+            while let Some(child) = next {
+                if children.len() >= self.nodes.len() {
+                    return Err(format!("the children of node {} form a cycle", index));
+                }
+                children.push(child);
+                next = self.nodes[child].next_sibling;
+            }
+            self.nodes[index].children = children;
         }
+        Ok(())
     }
 
     pub fn set_parent(&mut self, parent: TreeIndex, child: TreeIndex) {
@@ -463,14 +687,14 @@ impl MutableTree {
         self.nodes.get_mut(index)
     }
     fn get_unwrapped_node(&self, index: TreeIndex) -> &MutableTreeNode {
-        return self
+        self
             .get_node(index)
-            .expect(&*format!("node {} not in tree", index));
+            .unwrap_or_else(|| panic!("node {} not in tree", index))
     }
     fn get_unwrapped_node_mut(&mut self, index: TreeIndex) -> &mut MutableTreeNode {
-        return self
+        self
             .get_node_mut(index)
-            .expect(&*format!("node {} not in tree", index));
+            .unwrap_or_else(|| panic!("node {} not in tree", index))
     }
 
     pub fn get_taxon(&self, node_ref: TreeIndex) -> Option<&str> {
@@ -478,7 +702,14 @@ impl MutableTree {
         node.taxon.as_deref()
     }
 
+    /// The number of nodes in the tree
     pub fn get_node_count(&self) -> usize {
+        self.external_nodes.len() + self.internal_nodes.len()
+    }
+    /// The size of the arena, which counts deleted nodes until
+    /// [compact](MutableTree::compact) is called. Node indices are below this so it is
+    /// the length for vectors indexed by node.
+    pub fn arena_len(&self) -> usize {
         self.nodes.len()
     }
     pub fn get_internal_node_count(&self) -> usize {
@@ -489,35 +720,10 @@ impl MutableTree {
     }
 
     pub fn get_num_children(&self, node_ref: TreeIndex) -> TreeIndex {
-        let mut count = 0;
-        let parent_node = self.get_unwrapped_node(node_ref);
-        if let Some(first_child) = parent_node.first_child {
-            count += 1;
-            let mut sibling_node = self.get_unwrapped_node(first_child);
-            while let Some(sibling) = sibling_node.next_sibling {
-                sibling_node = self.get_unwrapped_node(sibling);
-                count += 1;
-            }
-        }
-        count
+        self.get_unwrapped_node(node_ref).children.len()
     }
     pub fn get_child(&self, node_ref: TreeIndex, index: usize) -> Option<TreeIndex> {
-        let mut count = 0;
-        let parent_node = self.get_unwrapped_node(node_ref);
-        if let Some(first_child) = parent_node.first_child {
-            if index == 0 {
-                return Some(first_child);
-            }
-            let mut sibling_node = self.get_unwrapped_node(first_child);
-            while let Some(sibling) = sibling_node.next_sibling {
-                sibling_node = self.get_unwrapped_node(sibling);
-                count += 1;
-                if count == index {
-                    return Some(sibling);
-                }
-            }
-        }
-        None
+        self.get_unwrapped_node(node_ref).children.get(index).copied()
     }
     pub fn get_external_node(&self, index: usize) -> Option<&MutableTreeNode> {
         self.nodes.get(self.external_nodes[index])
@@ -527,17 +733,7 @@ impl MutableTree {
     }
 
     pub fn get_children(&self, node: TreeIndex) -> Vec<TreeIndex> {
-        let mut kids: Vec<TreeIndex> = vec![];
-        let parent_node = self.get_unwrapped_node(node);
-        if let Some(first_child) = parent_node.first_child {
-            kids.push(first_child);
-            let mut sibling_node = self.get_unwrapped_node(first_child);
-            while let Some(sibling) = sibling_node.next_sibling {
-                kids.push(sibling);
-                sibling_node = self.get_unwrapped_node(sibling);
-            }
-        }
-        kids
+        self.get_unwrapped_node(node).children.clone()
     }
     pub fn get_next_sibling(&self, index: TreeIndex) -> &Option<TreeIndex> {
         let node = self.get_unwrapped_node(index);
@@ -559,30 +755,18 @@ impl MutableTree {
         None
     }
 
-    pub fn get_path_to_root(&self, index:TreeIndex)->Vec<TreeIndex>{
-        let mut path = vec![];
-        let mut node = index;
-
-        while let Some(parent)  = self.get_parent(node){
-            path.push(parent);
-            node = parent;
-        }
-        if let Some(node)= self.get_root(){
-            path.push(node)
-        }else{
-            panic!("The last node in the path to root was not the root!?")
+    /// The parent of `index`, its parent and so on up to the root.
+    pub fn ancestors(&self, index: TreeIndex) -> AncestorIterator<'_> {
+        AncestorIterator {
+            tree: self,
+            node: Some(index),
         }
-        return path;
     }
 
     pub fn get_mrca(&self, nodes:Vec<TreeIndex>)->TreeIndex{
         let mut paths : Vec<HashSet<TreeIndex>>= vec![];
         for node in nodes{
-            let mut path = HashSet::new();
-            for n in self.get_path_to_root(node){
-                path.insert(n);
-           }
-           paths.push(path)
+            paths.push(self.ancestors(node).chain(self.get_root()).collect());
         }
         let mut common_ancestors = paths[0].clone();
         for path in paths.iter().skip(1) {
@@ -623,7 +807,7 @@ impl MutableTree {
         node.length
     }
     pub fn get_annotation(&self, index: TreeIndex, key: &str) -> Option<&AnnotationValue> {
-        return self.get_unwrapped_node(index).annotations.get(key);
+        self.get_unwrapped_node(index).annotations.get(key)
     }
     //TODO public members or getter/setter?
     pub fn get_annotation_keys(&self) -> Keys<'_, String, AnnotationValue> {
@@ -636,48 +820,49 @@ impl MutableTree {
     pub fn get_root(&self) -> Option<TreeIndex> {
         self.root
     }
+    /// Annotate a node. All values of an annotation share a type. If a value's type does not
+    /// match the values already in the tree, all values are converted to a common type:
+    /// integers become continuous, intervals become sets and otherwise values become discrete.
     pub fn annotate_node(&mut self, index: TreeIndex, key: String, value: AnnotationValue) {
-        if let Some(annotation) = self.annotation_type.get(&key) {
-            let value_type = std::mem::discriminant(&value);
-            let annotation_type = std::mem::discriminant(annotation);
-
-            if value_type == annotation_type {
-                let node = self.get_unwrapped_node_mut(index);
-                node.annotations.insert(key, value);
-            } else if let AnnotationValue::Continuous(c) = value {
-                warn!("coercing {} to string for annotation {}", c, key.as_str());
-                self.annotate_node(index, key, AnnotationValue::Discrete(c.to_string()));
-            } else {
-                panic!("tried to annotate node with an missmatched annotation type for {}, found {} expected {}",key.as_str(),&value,&annotation);
+        let value = match self.annotation_type.get(&key) {
+            Some(annotation_type)
+                if std::mem::discriminant(annotation_type) == std::mem::discriminant(&value) =>
+            {
+                value
             }
-        } else {
-            match value {
-                AnnotationValue::Discrete(_) => {
-                    self.annotation_type
-                        .insert(key.clone(), AnnotationValue::Discrete("".to_string()));
-                }
-                AnnotationValue::Continuous(_) => {
-                    self.annotation_type
-                        .insert(key.clone(), AnnotationValue::Continuous(0.0));
-                }
-                AnnotationValue::Boolean(_) => {
-                    self.annotation_type
-                        .insert(key.clone(), AnnotationValue::Boolean(true));
-                }
-                AnnotationValue::Set(_) => {
-                    //TODO check internal types
-                    self.annotation_type.insert(
-                        key.clone(),
-                        AnnotationValue::Set(vec![AnnotationValue::Discrete("0".to_string())]),
+            Some(annotation_type) => {
+                let common_type = annotation_type.common_type(&value);
+                if std::mem::discriminant(&common_type) != std::mem::discriminant(annotation_type)
+                {
+                    warn!(
+                        "converting annotation {} from {:?} to {:?} to fit {}",
+                        key.as_str(),
+                        annotation_type,
+                        common_type,
+                        &value
                     );
+                    self.convert_annotation(&key, &common_type);
                 }
-                AnnotationValue::MarkovJump(_) => {
-                    panic!("Markov jumps must be in sets of annotations not single.");
-                }
+                value.convert_to(&common_type)
+            }
+            None => {
+                self.annotation_type.insert(key.clone(), value.clone());
+                value
+            }
+        };
+        let node = self.get_unwrapped_node_mut(index);
+        node.annotations.insert(key, value);
+    }
+    /// Convert every value of an annotation to a new type
+    fn convert_annotation(&mut self, key: &str, annotation_type: &AnnotationValue) {
+        for node in self.nodes.iter_mut() {
+            if let Some(value) = node.annotations.remove(key) {
+                node.annotations
+                    .insert(key.to_string(), value.convert_to(annotation_type));
             }
-            let node = self.get_unwrapped_node_mut(index);
-            node.annotations.insert(key, value);
         }
+        self.annotation_type
+            .insert(key.to_string(), annotation_type.clone());
     }
     pub fn label_node(&mut self, index: TreeIndex, label: String) {
         let node = self.get_unwrapped_node_mut(index);
@@ -708,53 +893,469 @@ impl MutableTree {
     pub fn get_id(&self) -> Option<&str> {
         self.id.as_deref()
     }
+
+    pub fn set_rooted(&mut self, rooted: bool) {
+        self.rooted = Some(rooted);
+    }
+    /// None if the input did not say whether the tree is rooted
+    pub fn is_rooted(&self) -> Option<bool> {
+        self.rooted
+    }
+
+    /// Give each tip the sequence of its taxon. Tips whose taxon is not in the alignment
+    /// are left without a sequence. Returns the taxa in the alignment that are not in
+    /// the tree.
+    pub fn attach_sequences(&mut self, alignment: &Alignment) -> Vec<String> {
+        let mut missing = vec![];
+        for (taxon, sequence) in alignment.iter() {
+            match self.taxon_node_map.get(taxon) {
+                Some(node) => {
+                    self.nodes[*node].sequence = Some(sequence.to_string());
+                }
+                None => missing.push(taxon.to_string()),
+            }
+        }
+        missing
+    }
+    pub fn get_sequence(&self, node_ref: TreeIndex) -> Option<&str> {
+        self.get_unwrapped_node(node_ref).sequence.as_deref()
+    }
+}
+/// Nodes are their index in the arena. Stored heights are used once they have been
+/// calculated so relative heights are kept.
+impl Tree for MutableTree {
+    type Node<'a> = TreeIndex;
+
+    fn get_root(&self) -> Option<TreeIndex> {
+        self.root
+    }
+    fn get_children(&self, node: TreeIndex) -> Vec<TreeIndex> {
+        MutableTree::get_children(self, node)
+    }
+    fn get_parent(&self, node: TreeIndex) -> Option<TreeIndex> {
+        MutableTree::get_parent(self, node)
+    }
+    fn get_taxon(&self, node: TreeIndex) -> Option<&str> {
+        MutableTree::get_taxon(self, node)
+    }
+    fn get_label(&self, node: TreeIndex) -> Option<&str> {
+        MutableTree::get_label(self, node)
+    }
+    fn get_length(&self, node: TreeIndex) -> Option<f64> {
+        MutableTree::get_length(self, node)
+    }
+    fn get_annotation(&self, node: TreeIndex, key: &str) -> Option<&AnnotationValue> {
+        MutableTree::get_annotation(self, node, key)
+    }
+    fn get_annotations(&self, node: TreeIndex) -> Vec<(&str, &AnnotationValue)> {
+        let mut annotations = self
+            .get_unwrapped_node(node)
+            .annotations
+            .iter()
+            .map(|(key, value)| (key.as_str(), value))
+            .collect::<Vec<(&str, &AnnotationValue)>>();
+        annotations.sort_by(|a, b| a.0.cmp(b.0));
+        annotations
+    }
+    fn is_external(&self, node: TreeIndex) -> bool {
+        MutableTree::is_external(self, node)
+    }
+    fn get_num_children(&self, node: TreeIndex) -> usize {
+        MutableTree::get_num_children(self, node)
+    }
+    fn preorder(&self) -> Vec<TreeIndex> {
+        self.preorder_iter().collect()
+    }
+    fn postorder(&self) -> Vec<TreeIndex> {
+        self.postorder_iter().collect()
+    }
+    fn get_nodes(&self) -> Vec<TreeIndex> {
+        (0..self.nodes.len())
+            .filter(|node| self.nodes[*node].kind != NodeKind::Unlisted)
+            .collect()
+    }
+    fn get_external_nodes(&self) -> Vec<TreeIndex> {
+        self.external_nodes.clone()
+    }
+    fn get_node_count(&self) -> usize {
+        MutableTree::get_node_count(self)
+    }
+    fn get_heights(&self) -> Vec<(TreeIndex, f64)> {
+        if self.heights_known {
+            self.preorder_iter()
+                .filter_map(|node| MutableTree::get_height(self, node).map(|h| (node, h)))
+                .collect()
+        } else {
+            heights_from_lengths(self)
+        }
+    }
+    fn get_height(&self, node: TreeIndex) -> Option<f64> {
+        match MutableTree::get_height(self, node) {
+            Some(height) if self.heights_known => Some(height),
+            _ => self
+                .get_heights()
+                .into_iter()
+                .find(|(other, _)| *other == node)
+                .map(|(_, height)| height),
+        }
+    }
 }
-//TODO I don't like that this is not lazy
 
-pub struct PreOrderIterator {
+/// Visits a node before its children. Only the children still to be visited are held.
+pub struct PreOrderIterator<'a> {
+    tree: &'a MutableTree,
     stack: Vec<TreeIndex>,
 }
 
-impl PreOrderIterator {
-    pub fn new(root: Option<TreeIndex>, tree: &MutableTree) -> Self {
-        let mut local_stack: Vec<TreeIndex>;
-        if let Some(index) = root {
-            local_stack = vec![index];
-        } else {
-            let root = tree.get_root().expect("each tree should have a root");
-            local_stack = vec![root];
+impl<'a> PreOrderIterator<'a> {
+    /// The subtree below `root`, or the whole tree if `root` is None
+    pub fn new(root: Option<TreeIndex>, tree: &'a MutableTree) -> Self {
+        PreOrderIterator {
+            tree,
+            stack: root.or(tree.root).into_iter().collect(),
         }
-        let mut me = PreOrderIterator { stack: vec![] };
+    }
+}
 
-        while let Some(node_index) = local_stack.pop() {
-            if let Some(node) = tree.get_node(node_index) {
-                if let Some(sibling) = node.next_sibling {
-                    local_stack.push(sibling);
-                }
-                if let Some(child) = node.first_child {
-                    local_stack.push(child);
+impl Iterator for PreOrderIterator<'_> {
+    type Item = TreeIndex;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = self.stack.pop()?;
+        self.stack
+            .extend(self.tree.get_unwrapped_node(node).children.iter().rev());
+        Some(node)
+    }
+}
+
+/// Visits a node after its children. Holds the path from the start node to the current
+/// node and the next child to visit at each step.
+pub struct PostOrderIterator<'a> {
+    tree: &'a MutableTree,
+    stack: Vec<(TreeIndex, usize)>,
+}
+
+impl<'a> PostOrderIterator<'a> {
+    /// The subtree below `root`, or the whole tree if `root` is None
+    pub fn new(root: Option<TreeIndex>, tree: &'a MutableTree) -> Self {
+        PostOrderIterator {
+            tree,
+            stack: root.or(tree.root).map(|node| (node, 0)).into_iter().collect(),
+        }
+    }
+}
+
+impl Iterator for PostOrderIterator<'_> {
+    type Item = TreeIndex;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (node, next_child) = self.stack.last_mut()?;
+            match self.tree.get_unwrapped_node(*node).children.get(*next_child) {
+                Some(child) => {
+                    *next_child += 1;
+                    self.stack.push((*child, 0));
                 }
-                me.stack.push(node_index)
+                None => return self.stack.pop().map(|(node, _)| node),
             }
         }
-        me
     }
 }
 
-impl Iterator for PreOrderIterator {
+/// Visits the nodes breadth first, a level at a time from the start node.
+pub struct LevelOrderIterator<'a> {
+    tree: &'a MutableTree,
+    queue: VecDeque<TreeIndex>,
+}
+
+impl<'a> LevelOrderIterator<'a> {
+    /// The subtree below `root`, or the whole tree if `root` is None
+    pub fn new(root: Option<TreeIndex>, tree: &'a MutableTree) -> Self {
+        LevelOrderIterator {
+            tree,
+            queue: root.or(tree.root).into_iter().collect(),
+        }
+    }
+}
+
+impl Iterator for LevelOrderIterator<'_> {
     type Item = TreeIndex;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if !self.stack.is_empty() {
-            Some(self.stack.remove(0))
-        } else {
-            None
-        }
+        let node = self.queue.pop_front()?;
+        self.queue
+            .extend(self.tree.get_unwrapped_node(node).children.iter());
+        Some(node)
     }
 }
 
-impl DoubleEndedIterator for PreOrderIterator {
-    fn next_back(&mut self) -> Option<Self::Item> {
-        self.stack.pop()
+/// The parent of a node, then its parent and so on up to the root.
+pub struct AncestorIterator<'a> {
+    tree: &'a MutableTree,
+    node: Option<TreeIndex>,
+}
+
+impl Iterator for AncestorIterator<'_> {
+    type Item = TreeIndex;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.node = self.tree.get_parent(self.node?);
+        self.node
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::parser::newick_importer::NewickImporter;
+    use crate::tree::test_utils;
+    use std::io::BufReader;
+
+    fn siblings(tree: &MutableTree, parent: TreeIndex) -> Vec<TreeIndex> {
+        let mut children = vec![];
+        let mut next = tree.get_node(parent).unwrap().first_child;
+        while let Some(child) = next {
+            assert_eq!(
+                children.last().copied(),
+                tree.get_node(child).unwrap().previous_sibling
+            );
+            children.push(child);
+            next = tree.get_node(child).unwrap().next_sibling;
+        }
+        children
+    }
+
+    #[test]
+    fn children_follow_sibling_links() {
+        let mut tree = MutableTree::new();
+        let tips: Vec<TreeIndex> = ["A", "B", "C", "D"]
+            .iter()
+            .map(|taxon| tree.make_external_node(taxon, None).unwrap())
+            .collect();
+        let root = tree.make_internal_node(tips.clone());
+        tree.set_root(Some(root));
+        assert_eq!(tips, tree.get_children(root));
+        assert_eq!(4, tree.get_num_children(root));
+        assert_eq!(Some(tips[2]), tree.get_child(root, 2));
+        assert_eq!(None, tree.get_child(root, 4));
+        assert!(tips.iter().all(|tip| tree.is_external(*tip) && !tree.is_internal(*tip)));
+        assert!(tree.is_internal(root) && !tree.is_external(root));
+
+        for removed in [tips[1], tips[0], tips[3]] {
+            assert_eq!(Some(removed), tree.remove_child(root, removed));
+            assert_eq!(siblings(&tree, root), tree.get_children(root));
+            let node = tree.get_node(removed).unwrap();
+            assert_eq!((None, None), (node.previous_sibling, node.next_sibling));
+        }
+        assert_eq!(vec![tips[2]], tree.get_children(root));
+        assert_eq!(None, tree.remove_child(root, tips[0]));
+
+        tree.add_child(root, tips[0]);
+        assert_eq!(vec![tips[2], tips[0]], tree.get_children(root));
+        assert_eq!(siblings(&tree, root), tree.get_children(root));
+    }
+
+    #[test]
+    fn rebuild_cache() {
+        let mut tree = MutableTree::new();
+        let a = tree.make_external_node("A", None).unwrap();
+        let b = tree.make_external_node("B", None).unwrap();
+        let root = tree.make_internal_node(vec![a, b]);
+        for node in tree.nodes.iter_mut() {
+            node.kind = NodeKind::Unlisted;
+            node.children.clear();
+        }
+        tree.rebuild_node_cache().unwrap();
+        assert_eq!(vec![a, b], tree.get_children(root));
+        assert!(tree.is_external(a) && tree.is_internal(root));
+
+        tree.nodes[b].next_sibling = Some(a);
+        assert!(tree.rebuild_node_cache().is_err());
+    }
+
+    fn names(tree: &MutableTree, nodes: impl Iterator<Item = TreeIndex>) -> Vec<&str> {
+        nodes
+            .map(|node| tree.get_taxon(node).or(tree.get_label(node)).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn traversals() {
+        let tree = NewickImporter::read_tree(BufReader::new(
+            "((A,B)ab,(C,(D,E)de,F)cf)root;".as_bytes(),
+        ))
+        .unwrap();
+        assert_eq!(
+            vec!["root", "ab", "A", "B", "cf", "C", "de", "D", "E", "F"],
+            names(&tree, tree.preorder_iter())
+        );
+        assert_eq!(
+            vec!["A", "B", "ab", "C", "D", "E", "de", "F", "cf", "root"],
+            names(&tree, tree.postorder_iter())
+        );
+        assert_eq!(
+            vec!["root", "ab", "cf", "A", "B", "C", "de", "F", "D", "E"],
+            names(&tree, tree.levelorder_iter())
+        );
+
+        let ab = tree.get_label_node("ab").unwrap();
+        let de = tree.get_label_node("de").unwrap();
+        assert_eq!(vec!["ab", "A", "B"], names(&tree, tree.subtree_iter(ab)));
+        assert_eq!(
+            vec!["D", "E", "de"],
+            names(&tree, PostOrderIterator::new(Some(de), &tree))
+        );
+        let d = tree.get_taxon_node("D").unwrap();
+        assert_eq!(vec!["de", "cf", "root"], names(&tree, tree.ancestors(d)));
+        assert_eq!(0, tree.ancestors(tree.get_root().unwrap()).count());
+    }
+
+    #[test]
+    fn deep_ladder_traversals() {
+        let depth = 100_000;
+        let tree = NewickImporter::read_tree(BufReader::new(
+            test_utils::deep_ladder(depth).as_bytes(),
+        ))
+        .unwrap();
+        assert_eq!(2 * depth + 1, tree.preorder_iter().count());
+        assert_eq!(2 * depth + 1, tree.postorder_iter().count());
+        assert_eq!(2 * depth + 1, tree.levelorder_iter().count());
+        let first = tree.postorder_iter().next().unwrap();
+        assert_eq!(Some("t0"), tree.get_taxon(first));
+        let last = tree.preorder_iter().last().unwrap();
+        assert_eq!(Some("t100000"), tree.get_taxon(last));
+        assert_eq!(depth, tree.ancestors(tree.get_taxon_node("t0").unwrap()).count());
+    }
+
+    #[test]
+    fn deep_ladder_copies() {
+        let depth = 100_000;
+        let mut root = FixedNode::new();
+        root.taxon = Some("t0".to_string());
+        root.length = Some(1.0);
+        for i in 1..=depth {
+            let mut tip = FixedNode::new();
+            tip.taxon = Some(format!("t{}", i));
+            tip.length = Some(1.0);
+            let mut node = FixedNode::new();
+            node.length = Some(1.0);
+            node.children = vec![root, tip];
+            root = node;
+        }
+        root.length = None;
+        let mut tree = MutableTree::from_fixed_node(root);
+        assert_eq!(test_utils::deep_ladder(depth), tree.to_string());
+        let t0 = tree.get_taxon_node("t0").unwrap();
+        assert_eq!(depth, tree.ancestors(t0).count());
+
+        let taxa = (0..=depth)
+            .step_by(2)
+            .map(|i| format!("t{}", i))
+            .collect::<HashSet<String>>();
+        let pruned = MutableTree::from_tree(&mut tree, &taxa);
+        assert_eq!(taxa.len(), pruned.get_external_node_count());
+        let t0 = pruned.get_taxon_node("t0").unwrap();
+        assert_eq!(taxa.len() - 1, pruned.ancestors(t0).count());
+        assert_eq!(
+            tree.get_height(tree.get_taxon_node("t2").unwrap()),
+            pruned.get_height(pruned.get_taxon_node("t2").unwrap())
+        );
+    }
+
+    fn check_links(tree: &MutableTree) {
+        for node in Tree::get_nodes(tree) {
+            assert_eq!(siblings(tree, node), tree.get_children(node));
+            for child in tree.get_children(node) {
+                assert_eq!(Some(node), tree.get_parent(child));
+            }
+        }
+    }
+
+    #[test]
+    fn delete_and_compact() {
+        let mut tree = NewickImporter::read_tree(BufReader::new(
+            "((A:1,B:1)ab:1,(C:1,(D:1,E:1)de:1,F:2)cf:1)root;".as_bytes(),
+        ))
+        .unwrap();
+        let de = tree.get_label_node("de").unwrap();
+        assert_eq!(3, tree.delete_subtree(de));
+        assert!(tree.get_taxon_node("D").is_none() && tree.get_label_node("de").is_none());
+        let e = tree.nodes.iter().position(|node| node.taxon.as_deref() == Some("E"));
+        assert!(!tree.is_external(e.unwrap()));
+        assert_eq!(4, tree.get_external_node_count());
+        assert_eq!(3, tree.get_internal_node_count());
+        assert_eq!(7, Tree::get_node_count(&tree));
+        assert_eq!(7, tree.get_node_count());
+
+        let cf = tree.get_label_node("cf").unwrap();
+        assert!(tree.delete_node(cf).is_err());
+        tree.delete_node(tree.get_taxon_node("C").unwrap()).unwrap();
+        assert!(tree.get_taxon_node("C").is_none());
+        tree.collapse_degree2_nodes();
+        assert_eq!("((A:1,B:1)ab:1,F:3)root;", tree.to_string());
+        // deleted nodes are left in the arena until compact
+        tree.calc_relative_node_heights(2020.0);
+        assert_eq!(Some(2017.0), tree.get_height(tree.get_root().unwrap()));
+        tree.heights_known = false;
+        tree.calc_node_heights();
+        assert_eq!(Some(3.0), tree.get_height(tree.get_root().unwrap()));
+        assert_eq!(Some(1.0), tree.get_height(tree.get_taxon_node("A").unwrap()));
+        tree.calculate_branchlengths();
+        assert_eq!("((A:1,B:1)ab:1,F:3)root;", tree.to_string());
+
+        assert_eq!(5, tree.get_node_count());
+        assert_eq!(5, Tree::get_node_count(&tree));
+        let arena_len = tree.arena_len();
+        tree.compact();
+        assert_eq!(5, tree.get_node_count());
+        assert_eq!(5, tree.arena_len());
+        assert!(arena_len > tree.arena_len());
+        check_links(&tree);
+        assert_eq!(
+            vec!["root", "ab", "A", "B", "F"],
+            names(&tree, tree.preorder_iter())
+        );
+        for (index, node) in tree.nodes.iter().enumerate() {
+            assert_eq!(index, node.number);
+        }
+        let f = tree.get_taxon_node("F").unwrap();
+        assert_eq!(Some("F"), tree.get_taxon(f));
+        assert_eq!(Some("ab"), tree.get_label(tree.get_label_node("ab").unwrap()));
+        assert_eq!(Some(3.0), tree.get_length(f));
+        assert_eq!("((A:1,B:1)ab:1,F:3)root;", tree.to_string());
+    }
+
+    #[test]
+    fn collapse_nodes() {
+        let mut tree = NewickImporter::read_tree(BufReader::new(
+            "((A:1,(B:1,C:2)bc:1,D:1)abcd:2,E:1)root;".as_bytes(),
+        ))
+        .unwrap();
+        let root = tree.get_root().unwrap();
+        assert!(tree.collapse_node(tree.get_taxon_node("A").unwrap()).is_err());
+        assert!(tree.collapse_node(root).is_err());
+        assert_eq!(2, tree.get_num_children(root));
+
+        tree.collapse_node(tree.get_label_node("bc").unwrap()).unwrap();
+        assert_eq!("((A:1,B:2,C:3,D:1)abcd:2,E:1)root;", tree.to_string());
+        check_links(&tree);
+
+        let abcd = tree.get_label_node("abcd").unwrap();
+        tree.delete_subtree(tree.get_taxon_node("E").unwrap());
+        tree.collapse_node(root).unwrap();
+        assert_eq!(Some(abcd), tree.get_root());
+        assert_eq!(None, tree.get_parent(abcd));
+        assert_eq!(1, tree.get_internal_node_count());
+        tree.calc_relative_node_heights(2020.0);
+        assert_eq!(Some(2018.0), tree.get_height(tree.get_taxon_node("A").unwrap()));
+        tree.heights_known = false;
+        tree.calc_node_heights();
+        assert_eq!(Some(3.0), tree.get_height(abcd));
+        tree.calculate_branchlengths();
+        assert_eq!(Some(3.0), tree.get_length(tree.get_taxon_node("C").unwrap()));
+        tree.compact();
+        check_links(&tree);
+        assert_eq!(5, tree.get_node_count());
+        assert_eq!("(A:1,B:2,C:3,D:1)abcd;", tree.to_string());
     }
 }
@@ -0,0 +1,14 @@
+//! Fixtures shared by tests. Only std is used so the binary's tests can include this
+//! file as well.
+
+/// Newick for a ladder with `depth` internal nodes. Each internal node joins the ladder
+/// below it to the next tip, from (t0,t1) at the bottom to t<depth> beside the root.
+/// Every branch but the root has length 1.
+pub fn deep_ladder(depth: usize) -> String {
+    let mut newick = "(".repeat(depth) + "t0:1";
+    for i in 1..depth {
+        newick.push_str(&format!(",t{}:1):1", i));
+    }
+    newick.push_str(&format!(",t{}:1);", depth));
+    newick
+}